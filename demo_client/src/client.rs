@@ -1,4 +1,5 @@
 use futures::{Future, future, Sink, Stream, stream};
+use futures::future::Either;
 use rand::thread_rng;
 use rand::Rng;
 use rand::ThreadRng;
@@ -7,6 +8,7 @@ use spatiub::spatial::Entity;
 use spatiub::spatial::MapDefinition;
 use spatiub::spatial::Point;
 use spatiub::spatial::SpatialEvent;
+use std::cell::Cell;
 use std::cell::RefCell;
 use std::net::SocketAddr;
 use std::ops::Add;
@@ -16,18 +18,44 @@ use tokio::net::TcpStream;
 use tokio::runtime::current_thread::Runtime;
 use tokio::timer::Delay;
 use tokio_codec::Decoder;
+use tokio_codec::Framed;
 use uuid::Uuid;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 use std::rc::Rc;
+use spatiub_demo_core::delta::DeltaDecoder;
+use spatiub_demo_core::format::AnyFormat;
+use spatiub_demo_core::handle::IntroducedEntities;
+use spatiub_demo_core::handshake;
+use spatiub_demo_core::handshake::Features;
+use spatiub_demo_core::handshake::Handshake;
+use spatiub_demo_core::handshake::Hello;
+use spatiub_demo_core::handshake::Welcome;
 use spatiub_demo_core::message::Message;
+use spatiub_demo_core::position::PositionEncoding;
+use spatiub_demo_core::position::ZonedEvent;
+use spatiub_demo_core::position::ZonedPositions;
 use spatiub_demo_core::entity::Timestamp;
 use spatiub_demo_core::entity::DemoEntity;
-use std::marker::PhantomData;
 
-fn client<C, F>(addr: &SocketAddr, message_consumer: C)
-                -> impl Future<Item=(), Error=()>
+#[derive(Debug, Clone, Copy)]
+pub struct ClientConfig {
+    /// The moves per second of each client.
+    pub msg_per_sec: u64,
+    /// The encoding of the positions asked to the server.
+    pub position_encoding: PositionEncoding,
+    /// The format asked to the server first, the others are read too.
+    pub format: AnyFormat,
+    /// Whether the server may send compressed frames.
+    pub compression: bool,
+}
+
+fn client<C, F>(
+    addr: &SocketAddr,
+    hello: Hello,
+    message_consumer: C,
+) -> impl Future<Item=(), Error=()>
     where
         C: Fn(Message) -> Option<F>,
         F: Future<Item=Message, Error=()>,
@@ -39,27 +67,111 @@ fn client<C, F>(addr: &SocketAddr, message_consumer: C)
         .and_then(move |socket| {
             socket.set_nodelay(true).unwrap();
             debug!("Connection established");
-            let (output, input) = codec().framed(socket).split();
+            handshake(socket, hello)
+        })
+        .and_then(move |(framed, welcome)| {
+            let position_encoding = welcome.features.position_encoding;
+            let (output, input) = framed.split();
             let output = output.sink_map_err(|err| error!("An error occurred in the input stream: {}", err));
+            let delta_decoder = RefCell::new(DeltaDecoder::new());
+            let introduced_entities = RefCell::new(IntroducedEntities::new());
+            let zoned_positions = Cell::new(None);
 
-            input
+            let replies = input
                 .map_err(|err| error!("An error occurred in the input stream: {}", err))
+                .map(move |message| {
+                    match message {
+                        Message::ConnectionAck(entity, map) => {
+                            // Zoned positions are decoded on the map of the server.
+                            if position_encoding == PositionEncoding::Zoned {
+                                zoned_positions.set(ZonedPositions::new(&map));
+                            }
+                            stream::iter_ok(vec![Message::ConnectionAck(entity, map)])
+                        },
+                        Message::Events(events) => stream::iter_ok(events.into_iter().map(Message::Event).collect::<Vec<_>>()),
+                        Message::ZonedEvent(event) => stream::iter_ok(vec![Message::Event(unzoned(zoned_positions.get(), event))]),
+                        Message::ZonedEvents(events) => stream::iter_ok(events.into_iter()
+                            .map(|event| Message::Event(unzoned(zoned_positions.get(), event)))
+                            .collect::<Vec<_>>()),
+                        Message::Introduction(handle, entity) => {
+                            introduced_entities.borrow_mut().introduce(handle, entity);
+                            stream::iter_ok(vec![])
+                        },
+                        Message::HandleEvent(event) => {
+                            let event = introduced_entities.borrow_mut().event_for(event)
+                                .unwrap_or_else(|err| panic!("Could not name the entity. Cause: {}", err));
+                            stream::iter_ok(vec![Message::Event(event)])
+                        },
+                        Message::HandleReleased(handle) => {
+                            introduced_entities.borrow_mut().release(handle);
+                            stream::iter_ok(vec![])
+                        },
+                        Message::Snapshot(snapshot) => {
+                            // The acknowledgement follows the events so that it is sent back to the server.
+                            let sequence = snapshot.sequence;
+                            let events = delta_decoder.borrow_mut().decode(snapshot)
+                                .unwrap_or_else(|err| panic!("Could not decode the snapshot. Cause: {}", err));
+
+                            stream::iter_ok(events.into_iter()
+                                .map(Message::Event)
+                                .chain(Some(Message::SnapshotAck(sequence)))
+                                .collect::<Vec<_>>())
+                        },
+                        message => stream::iter_ok(vec![message]),
+                    }
+                })
+                .flatten()
                 .map(move |message| {
                     message_consumer(message)
                 })
                 .filter_map(|future| future)
-                .buffered(100000)
-                .forward(output)
+                .buffered(100000);
+
+            replies.forward(output)
         })
         .map(|_|{})
 }
 
+/// Says hello, then frames the messages in the format the server chose.
+fn handshake(socket: TcpStream, hello: Hello)
+    -> impl Future<Item=(Framed<TcpStream, LengthFieldBasedCodec<Message, AnyFormat>>, Welcome), Error=()> {
+    handshake::codec().framed(socket)
+        .send(Handshake::Hello(hello))
+        .map_err(|err| error!("Could not say hello: {}", err))
+        .and_then(|framed| {
+            framed.into_future()
+                .map_err(|(err, _)| error!("Could not read the answer to the hello: {}", err))
+        })
+        .and_then(|(answer, framed)| {
+            match answer {
+                Some(Handshake::Welcome(welcome)) => {
+                    debug!("Welcome: {:?}", welcome);
+                    Ok((handshake::switch_codec(framed, codec(welcome.format)), welcome))
+                },
+                Some(Handshake::Rejected(rejection)) => {
+                    error!("Connection rejected: {}", rejection);
+                    Err(())
+                },
+                answer => {
+                    error!("Unexpected answer to the hello: {:?}", answer);
+                    Err(())
+                },
+            }
+        })
+}
+
+fn unzoned(zoned_positions: Option<ZonedPositions>, event: ZonedEvent) -> SpatialEvent<DemoEntity> {
+    zoned_positions
+        .expect("Received zoned positions without asking for them")
+        .decode_event(event)
+        .unwrap_or_else(|err| panic!("Could not decode the positions. Cause: {}", err))
+}
+
 pub fn run_clients(
-    map: &MapDefinition,
     addr: SocketAddr,
     number_of_clients: usize,
     log_file_path: &str,
-    msg_per_sec: u64,
+    config: ClientConfig,
 ) {
     let mut iter = vec![];
     for i in 0..number_of_clients as u64 { iter.push(i) }
@@ -70,7 +182,7 @@ pub fn run_clients(
         .map(|i| {
             Delay::new(Instant::now().add(Duration::from_millis(i * rng.gen_range(15, 30))))
                 .map(|_| {
-                    run_client(map.clone(), addr, logger.clone(), msg_per_sec)
+                    run_client(addr, logger.clone(), config)
                 })
                 .map_err(|err|{
                     panic!("Timer error: {}", err)
@@ -90,32 +202,51 @@ pub fn run_clients(
 }
 
 fn run_client(
-    map: MapDefinition,
     addr: SocketAddr,
     logger: Rc<RefCell<ClientEventLogger>>,
-    msg_per_sec: u64,
+    config: ClientConfig,
 ) -> impl Future<Item=(), Error=()> {
+    let ClientConfig{msg_per_sec, position_encoding, format, compression} = config;
     let ref addr = addr;
-    let client_entity_id = RefCell::new(None);
+    // The entity of the client and the map it moves on, both received with the acknowledgement.
+    let client_entity = RefCell::new(None);
+
+    // Every format is read, the one asked for first.
+    let formats = Some(format).into_iter()
+        .chain(AnyFormat::ALL.iter().cloned().filter(|other| *other != format))
+        .collect();
+    // The server keeps sending plain positions if it does not support the encoding.
+    let hello = Hello::new(formats, Features{
+        compression,
+        batching: true,
+        position_encoding,
+        delta_snapshots: true,
+        entity_handles: true,
+    });
 
     client(
         &addr,
+        hello,
         move |message| {
-            if let Message::ConnectionAck(entity) = &message {
-                client_entity_id.replace(Some(entity.id().clone()));
+            if let Message::ConnectionAck(entity, map) = &message {
+                client_entity.replace(Some((entity.id().clone(), map.clone())));
             } else if let Message::Event(event) = &message {
                 let latency = event.acting_entity.last_state_update.elapsed();
 
                 logger.borrow_mut().log(event.clone(), latency);
             };
 
-            if let Some(ref entity_id) = &*client_entity_id.borrow() {
+            if let Message::SnapshotAck(_) = message {
+                return Some(Either::A(future::ok(message)));
+            }
+
+            if let Some((ref entity_id, ref map)) = &*client_entity.borrow() {
                 trigger_new_move_if_client_entity_involved(
                     message,
-                    &map,
+                    map,
                     entity_id,
                     msg_per_sec
-                )
+                ).map(Either::B)
             } else {
                 panic!("Expected the entity id to be set");
             }
@@ -197,8 +328,65 @@ impl ClientEventLogger{
     }
 }
 
-pub fn codec() -> LengthFieldBasedCodec<Message> {
-    LengthFieldBasedCodec{
-        phantom: PhantomData,
+pub fn codec(format: AnyFormat) -> LengthFieldBasedCodec<Message, AnyFormat> {
+    LengthFieldBasedCodec::new(format)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use spatiub_demo_core::handshake::Offer;
+    use spatiub_demo_core::handshake::PROTOCOL_VERSION;
+    use spatiub_demo_core::handshake::Rejection;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use tokio_codec::Encoder;
+
+    #[test]
+    pub fn rejections_of_other_versions_fail_the_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (given_up, wait_for_client) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut codec = handshake::codec();
+            let mut received = BytesMut::new();
+            let hello = loop {
+                if let Some(Handshake::Hello(hello)) = codec.decode(&mut received).unwrap() {
+                    break hello;
+                }
+                let mut bytes = [0; 1024];
+                let read = socket.read(&mut bytes).unwrap();
+                assert!(read > 0, "The client left before saying hello");
+                received.extend_from_slice(&bytes[..read]);
+            };
+
+            let offer = Offer{formats: vec![AnyFormat::Bincode], features: Features::default()};
+            let rejection = offer.answer(&hello).unwrap_err();
+            let mut answer = BytesMut::new();
+            codec.encode(Handshake::Rejected(rejection.clone()), &mut answer).unwrap();
+            socket.write_all(&answer).unwrap();
+
+            // The connection stays open until the client gives up, so only the rejection can fail it.
+            wait_for_client.recv().unwrap();
+            rejection
+        });
+
+        let mut hello = Hello::new(vec![AnyFormat::Bincode], Features::default());
+        hello.version = PROTOCOL_VERSION + 1;
+        let handshake = TcpStream::connect(&addr)
+            .map_err(|err| panic!("Connection failed. Cause: {}", err))
+            .and_then(|socket| handshake(socket, hello));
+        let result = Runtime::new().unwrap().block_on(handshake);
+        given_up.send(()).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(
+            Rejection::UnsupportedVersion{version: PROTOCOL_VERSION + 1, supported: PROTOCOL_VERSION},
+            server.join().unwrap()
+        );
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
+#[cfg(test)] extern crate bytes;
 extern crate clap;
 extern crate core;
 extern crate env_logger;
@@ -16,7 +17,8 @@ extern crate spatiub_demo_core;
 use clap::App;
 use hwloc::{CPUBIND_THREAD, CpuSet, ObjectType, Topology};
 use log::LevelFilter;
-use spatiub::spatial::MapDefinition;
+use spatiub_demo_core::format::AnyFormat;
+use spatiub_demo_core::position::PositionEncoding;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -24,6 +26,7 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
 use clap::Arg;
+use client::ClientConfig;
 
 mod client;
 
@@ -51,11 +54,25 @@ fn main() {
                 .value_name("NUMBER_OF_CORES")
                 .help("The number of core, 1 thread per core")
                 .takes_value(true))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("The format asked to the server first, bincode by default. The others are read too.")
+                .possible_values(&["bincode", "json", "msgpack"])
+                .takes_value(true))
+            .arg(Arg::with_name("position_encoding")
+                .long("position_encoding")
+                .value_name("POSITION_ENCODING")
+                .help("How the server should encode the positions: as points, or as offsets inside zones.")
+                .possible_values(&["plain", "zoned"])
+                .takes_value(true))
+            .arg(Arg::with_name("compression")
+                .long("compression")
+                .help("Accepts compressed frames, if the server compresses them."))
         .get_matches();
 
     let hw_topo = Arc::new(Mutex::new(Topology::new()));
     let addr: SocketAddr = "127.0.0.1:6142".parse().unwrap();
-    let map = MapDefinition::new(16, 1024 * 4);
 
     let msg_per_sec = matches.value_of("rate").unwrap_or("1").parse::<u64>().unwrap();
     info!("Message rate: {}", msg_per_sec);
@@ -66,9 +83,27 @@ fn main() {
     let number_of_cores = matches.value_of("number_of_cores").unwrap_or("1").parse::<usize>().unwrap();
     info!("Number of cores: {}", number_of_cores);
 
+    let position_encoding = match matches.value_of("position_encoding").unwrap_or("plain") {
+        "zoned" => PositionEncoding::Zoned,
+        _ => PositionEncoding::Plain,
+    };
+    info!("Position encoding: {:?}", position_encoding);
+
+    let format = matches.value_of("format").unwrap_or("bincode").parse::<AnyFormat>().unwrap();
+    info!("Format: {:?}", format);
+
+    let compression = matches.is_present("compression");
+    info!("Compression: {}", compression);
+
+    let config = ClientConfig{
+        msg_per_sec,
+        position_encoding,
+        format,
+        compression,
+    };
+
     let mut client_handles = vec![];
     for i in 0..number_of_cores {
-        let map = map.clone();
         let addr = addr.clone();
         let handle = run_thread(
             hw_topo.clone(),
@@ -76,11 +111,10 @@ fn main() {
             format!("client {}", i),
             move || {
                 client::run_clients(
-                    &map,
                     addr,
                     number_of_clients,
                     format!("client_log_{}.csv", i).as_str(),
-                    msg_per_sec,
+                    config,
                 );
             }
         );
@@ -1,72 +1,590 @@
+use std::error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
 use std::io;
 use std::io::Error;
 use std::io::ErrorKind;
-use bytes::{BufMut, BytesMut, BigEndian, ByteOrder};
+use std::io::Read;
+use std::io::Write;
+use flate2;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use bytes::{BufMut, Bytes, BytesMut, BigEndian, ByteOrder};
 use tokio::codec::Decoder;
 use tokio::codec::Encoder;
-use bincode;
+use format::Bincode;
+use format::Format;
 use std::marker::PhantomData;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
-pub struct LengthFieldBasedCodec<M> {
-    pub phantom: PhantomData<M>
+const LENGTH_FIELD_LENGTH: usize = 4;
+
+/// Set in the length field of the frames whose payload is compressed.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// The default maximum length of the frames read, length field excluded.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The length field announces a frame longer than the maximum, the stream cannot be trusted.
+    FrameTooLong{length: usize, max_length: usize},
+    /// The compressed frame inflates to more bytes than the maximum.
+    DecompressedFrameTooLong{max_length: usize},
+    /// The frame is whole, but does not hold a message.
+    InvalidFrame(io::Error),
+    /// Reading the stream failed.
+    Io(io::Error),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::FrameTooLong{length, max_length} =>
+                write!(f, "Frame of {} bytes, longer than the maximum of {} bytes", length, max_length),
+            DecodeError::DecompressedFrameTooLong{max_length} =>
+                write!(f, "Compressed frame longer than the maximum of {} bytes once decompressed", max_length),
+            DecodeError::InvalidFrame(ref err) => write!(f, "Invalid frame: {}", err),
+            DecodeError::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            DecodeError::FrameTooLong{..} | DecodeError::DecompressedFrameTooLong{..} => None,
+            DecodeError::InvalidFrame(ref err) | DecodeError::Io(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> DecodeError {
+        DecodeError::Io(err)
+    }
+}
+
+/// Payloads of at least `threshold` bytes are deflated, when that makes them shorter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub threshold: usize,
+}
+
+impl Compression {
+    /// `None` if the payload stays raw.
+    fn compress(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.len() < self.threshold {
+            return None;
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::with_capacity(payload.len() / 2), flate2::Compression::fast());
+        encoder.write_all(payload).ok()?;
+        let compressed = encoder.finish().ok()?;
+
+        if compressed.len() < payload.len() {
+            Some(compressed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compressed frames are always decoded, the encoder only compresses them when given a
+/// `Compression`.
+pub struct LengthFieldBasedCodec<M, F: Format = Bincode> {
+    pub phantom: PhantomData<M>,
+    pub format: F,
+    max_frame_length: usize,
+    compression: Option<Compression>,
+}
+
+impl <M, F: Format> LengthFieldBasedCodec<M, F> {
+    pub fn new(format: F) -> LengthFieldBasedCodec<M, F> {
+        LengthFieldBasedCodec{
+            phantom: PhantomData,
+            format,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            compression: None,
+        }
+    }
+
+    /// Frames announcing more bytes than the maximum, length field excluded, are errors.
+    /// So are compressed frames inflating to more bytes than the maximum.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> LengthFieldBasedCodec<M, F> {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> LengthFieldBasedCodec<M, F> {
+        self.compression = Some(compression);
+        self
+    }
+
+    fn decompress(&self, payload: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut decompressed = Vec::new();
+        // Read one byte past the maximum to tell full frames from truncated ones.
+        DeflateDecoder::new(payload)
+            .take(self.max_frame_length as u64 + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(DecodeError::InvalidFrame)?;
+
+        if decompressed.len() > self.max_frame_length {
+            return Err(DecodeError::DecompressedFrameTooLong{max_length: self.max_frame_length});
+        }
+
+        Ok(decompressed)
+    }
 }
 
-impl <'de, M> Decoder for LengthFieldBasedCodec<M>
+impl <M, F> Decoder for LengthFieldBasedCodec<M, F>
     where
-        M: DeserializeOwned
+        M: DeserializeOwned,
+        F: Format,
 {
     type Item = M;
-    type Error = Error;
+    type Error = DecodeError;
 
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<M>, io::Error> {
-        // At least 4 bytes are required for a frame: 4 byte length field + the payload
-        if buf.len() < 4 {
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<M>, DecodeError> {
+        if buf.len() < LENGTH_FIELD_LENGTH {
             return Ok(None);
         }
 
-        let length_field = BigEndian::read_u32(&buf.as_ref()[0..4]);
+        let length_field = BigEndian::read_u32(&buf.as_ref()[0..LENGTH_FIELD_LENGTH]);
+        let is_compressed = length_field & COMPRESSED_FLAG != 0;
+        let length = (length_field & !COMPRESSED_FLAG) as usize;
+        if length > self.max_frame_length {
+            return Err(DecodeError::FrameTooLong{
+                length,
+                max_length: self.max_frame_length,
+            });
+        }
 
-        if buf.len() < length_field as usize {
+        let frame_length = LENGTH_FIELD_LENGTH + length;
+        if buf.len() < frame_length {
+            // Make room for the rest of the frame, rather than growing with each read.
+            buf.reserve(frame_length - buf.len());
             return Ok(None);
         }
 
-        buf.split_to(4); // The frame is whole, strip the length field.
-        let line = buf.split_to(length_field as usize);
+        buf.split_to(LENGTH_FIELD_LENGTH); // The frame is whole, strip the length field.
+        let frame = buf.split_to(length);
 
-        match bincode::deserialize(line.as_ref()) {
-            Ok(deserialized) => {
-                Ok(Some(deserialized))
-            },
-            Err(err) => {
-                Err(Error::new(ErrorKind::Other, err))
-            }
-        }
+        let result = if is_compressed {
+            self.format.deserialize(&self.decompress(frame.as_ref())?)
+        } else {
+            self.format.deserialize(frame.as_ref())
+        };
+
+        result
+            .map(Some)
+            .map_err(DecodeError::InvalidFrame)
     }
 }
 
-impl <M> Encoder for LengthFieldBasedCodec<M>
+impl <M, F> Encoder for LengthFieldBasedCodec<M, F>
     where
         M: Serialize,
+        F: Format,
 {
     type Item = M;
     type Error = Error;
 
     fn encode(&mut self, msg: M, buf: &mut BytesMut) -> io::Result<()> {
-        match bincode::serialize(&msg) {
-            Ok(serialized) => {
-                let len = serialized.len();
-                buf.reserve(len + 4);
-                buf.put_u32_be(len as u32);
+        write_frame(&self.format, self.compression.as_ref(), &msg, buf)
+    }
+}
 
-                buf.extend(&serialized);
+/// A length prefixed frame, encoded once and then written as is to as many connections as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedFrame(Bytes);
+
+impl EncodedFrame {
+    pub fn encode<M: Serialize>(msg: &M) -> io::Result<EncodedFrame> {
+        EncodedFrame::encode_with(msg, &Bincode)
+    }
 
-                Ok(())
+    pub fn encode_with<M: Serialize, F: Format>(msg: &M, format: &F) -> io::Result<EncodedFrame> {
+        let mut buf = BytesMut::new();
+        write_frame(format, None, msg, &mut buf)?;
+
+        Ok(EncodedFrame(buf.freeze()))
+    }
+
+    /// The frame with its payload compressed, or the frame itself if the payload stays raw.
+    pub fn compressed(&self, compression: &Compression) -> EncodedFrame {
+        let length_field = BigEndian::read_u32(&self.0[0..LENGTH_FIELD_LENGTH]);
+        if length_field & COMPRESSED_FLAG != 0 {
+            return self.clone();
+        }
+
+        match compression.compress(&self.0[LENGTH_FIELD_LENGTH..]) {
+            Some(payload) => {
+                let mut buf = BytesMut::with_capacity(LENGTH_FIELD_LENGTH + payload.len());
+                buf.put_u32_be(payload.len() as u32 | COMPRESSED_FLAG);
+                buf.extend(&payload);
+                EncodedFrame(buf.freeze())
             },
-            Err(err) => {
-                Err(Error::new(ErrorKind::Other, err))
+            None => self.clone(),
+        }
+    }
+
+    /// A frame written beforehand, length field included.
+    pub(crate) fn from_bytes(bytes: Bytes) -> EncodedFrame {
+        EncodedFrame(bytes)
+    }
+
+    /// The length of the frame, length field included.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AsRef<[u8]> for EncodedFrame {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// Decodes messages the same way as `LengthFieldBasedCodec`, but writes frames encoded beforehand.
+/// The frames have to be encoded in the format of the codec.
+pub struct EncodedFrameCodec<M, F: Format = Bincode> {
+    decoder: LengthFieldBasedCodec<M, F>,
+}
+
+impl <M> EncodedFrameCodec<M> {
+    pub fn new() -> EncodedFrameCodec<M> {
+        EncodedFrameCodec::with_format(Bincode)
+    }
+}
+
+impl <M, F: Format> EncodedFrameCodec<M, F> {
+    pub fn with_format(format: F) -> EncodedFrameCodec<M, F> {
+        EncodedFrameCodec{
+            decoder: LengthFieldBasedCodec::new(format),
+        }
+    }
+
+    /// See `LengthFieldBasedCodec::with_max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> EncodedFrameCodec<M, F> {
+        self.decoder = self.decoder.with_max_frame_length(max_frame_length);
+        self
+    }
+}
+
+impl <M> Default for EncodedFrameCodec<M> {
+    fn default() -> EncodedFrameCodec<M> {
+        EncodedFrameCodec::new()
+    }
+}
+
+impl <M, F> Decoder for EncodedFrameCodec<M, F>
+    where
+        M: DeserializeOwned,
+        F: Format,
+{
+    type Item = M;
+    type Error = DecodeError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<M>, DecodeError> {
+        self.decoder.decode(buf)
+    }
+}
+
+impl <M, F: Format> Encoder for EncodedFrameCodec<M, F> {
+    type Item = EncodedFrame;
+    type Error = Error;
+
+    fn encode(&mut self, frame: EncodedFrame, buf: &mut BytesMut) -> io::Result<()> {
+        buf.extend_from_slice(frame.as_ref());
+        Ok(())
+    }
+}
+
+fn write_frame<M: Serialize, F: Format>(format: &F, compression: Option<&Compression>, msg: &M, buf: &mut BytesMut)
+    -> io::Result<()> {
+    let serialized = format.serialize(msg)?;
+    let compressed = compression.and_then(|compression| compression.compress(&serialized));
+    let (payload, flag) = match compressed {
+        Some(ref compressed) => (compressed, COMPRESSED_FLAG),
+        None => (&serialized, 0),
+    };
+
+    // The highest bit of the length field is the compression flag.
+    let len = payload.len();
+    if len >= COMPRESSED_FLAG as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "The message does not fit in a frame"));
+    }
+
+    buf.reserve(len + LENGTH_FIELD_LENGTH);
+    buf.put_u32_be(len as u32 | flag);
+
+    buf.extend(payload);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::DemoEntity;
+    use entity::Timestamp;
+    use message::Message;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use spatiub::spatial::Point;
+    use spatiub::spatial::SpatialEvent;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn partial_frames_wait_for_the_rest() {
+        let frame = EncodedFrame::encode(&Message::SnapshotAck(7)).unwrap();
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode);
+        let mut buf = BytesMut::new();
+
+        // Up to the last byte, the length field included.
+        for byte in frame.as_ref()[..frame.len() - 1].iter() {
+            buf.extend_from_slice(&[*byte]);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+        }
+        assert!(buf.capacity() >= frame.len());
+
+        buf.extend_from_slice(&frame.as_ref()[frame.len() - 1..]);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::SnapshotAck(7)) => {},
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    pub fn frames_longer_than_the_maximum_are_errors() {
+        let frame = EncodedFrame::encode(&Message::SnapshotAck(7)).unwrap();
+        let length = frame.len() - LENGTH_FIELD_LENGTH;
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_max_frame_length(length - 1);
+        // The length field is enough to know.
+        let mut buf = BytesMut::from(&frame.as_ref()[..LENGTH_FIELD_LENGTH]);
+        match codec.decode(&mut buf) {
+            Err(DecodeError::FrameTooLong{length: actual_length, max_length}) => {
+                assert_eq!((length, length - 1), (actual_length, max_length));
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_max_frame_length(length);
+        let mut buf = BytesMut::from(frame.as_ref());
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn the_length_field_of_a_huge_frame_does_not_allocate() {
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode);
+        let mut buf = BytesMut::from(&[0xff, 0xff, 0xff, 0xff][..]);
+
+        assert!(codec.decode(&mut buf).is_err());
+        assert!(buf.capacity() < DEFAULT_MAX_FRAME_LENGTH);
+    }
+
+    #[test]
+    pub fn mixed_compressed_and_raw_frames_decode() {
+        let events = new_events(32);
+        let messages = vec![
+            Message::SnapshotAck(1),
+            Message::Events(events.clone()),
+            Message::Event(events[0].clone()),
+            Message::Events(events),
+            Message::SnapshotAck(2),
+        ];
+        let expected: Vec<_> = messages.iter().map(|message| format!("{:?}", message)).collect();
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_compression(Compression{threshold: 128});
+        let mut buf = BytesMut::new();
+
+        let mut flags = vec![];
+        for message in messages {
+            let start = buf.len();
+            codec.encode(message, &mut buf).unwrap();
+            flags.push(buf[start] & 0x80 != 0);
+        }
+        assert_eq!(vec![false, true, false, true, false], flags);
+
+        let mut decoded = vec![];
+        while let Some(message) = codec.decode(&mut buf).unwrap() {
+            decoded.push(format!("{:?}", message));
+        }
+        assert_eq!(expected, decoded);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    pub fn compressed_encoded_frames_decode_like_raw_ones() {
+        let compression = Compression{threshold: 128};
+        let message = Message::Events(new_events(32));
+        let frame = EncodedFrame::encode(&message).unwrap();
+        let compressed_frame = frame.compressed(&compression);
+        assert!(compressed_frame.len() < frame.len());
+        assert_eq!(compressed_frame, compressed_frame.compressed(&compression));
+
+        // Short frames stay raw.
+        let short_frame = EncodedFrame::encode(&Message::SnapshotAck(7)).unwrap();
+        assert_eq!(short_frame, short_frame.compressed(&compression));
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode);
+        let mut buf = BytesMut::from(compressed_frame.as_ref());
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(format!("{:?}", message), format!("{:?}", decoded));
+    }
+
+    #[test]
+    pub fn incompressible_payloads_stay_raw() {
+        let compression = Compression{threshold: 0};
+        let frame = EncodedFrame::encode(&Message::SnapshotAck(u32::MAX)).unwrap();
+        assert_eq!(frame, frame.compressed(&compression));
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_compression(compression);
+        let mut buf = BytesMut::new();
+        codec.encode(Message::SnapshotAck(u32::MAX), &mut buf).unwrap();
+        assert_eq!(frame.as_ref(), buf.as_ref());
+    }
+
+    #[test]
+    pub fn compressed_frames_inflating_past_the_maximum_are_errors() {
+        let frame = EncodedFrame::encode(&Message::Events(new_events(32))).unwrap();
+        let compressed_frame = frame.compressed(&Compression{threshold: 0});
+        let length = frame.len() - LENGTH_FIELD_LENGTH;
+        assert!(compressed_frame.len() - LENGTH_FIELD_LENGTH < length - 1);
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_max_frame_length(length - 1);
+        let mut buf = BytesMut::from(compressed_frame.as_ref());
+        match codec.decode(&mut buf) {
+            Err(DecodeError::DecompressedFrameTooLong{max_length}) => assert_eq!(length - 1, max_length),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+            .with_max_frame_length(length);
+        let mut buf = BytesMut::from(compressed_frame.as_ref());
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn messages_survive_any_split(
+            messages in vec(message_strategy(), 0..16),
+            chunk_lengths in vec(1usize..64, 1..32),
+            compression_threshold in proptest::option::of(0usize..256),
+        ) {
+            let expected: Vec<_> = messages.iter().map(|message| format!("{:?}", message)).collect();
+            let mut bytes = BytesMut::new();
+            let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode);
+            if let Some(threshold) = compression_threshold {
+                codec = codec.with_compression(Compression{threshold});
+            }
+            for message in messages {
+                codec.encode(message, &mut bytes).unwrap();
+            }
+
+            let mut decoded = vec![];
+            let mut buf = BytesMut::new();
+            for chunk in chunks(&bytes, &chunk_lengths) {
+                buf.extend_from_slice(chunk);
+                while let Some(message) = codec.decode(&mut buf).unwrap() {
+                    decoded.push(format!("{:?}", message));
+                }
+            }
+
+            prop_assert_eq!(expected, decoded);
+            prop_assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(
+            bytes in vec(any::<u8>(), 0..512),
+            chunk_lengths in vec(1usize..64, 1..32),
+            max_frame_length in 0usize..256,
+        ) {
+            let mut codec: LengthFieldBasedCodec<Message> = LengthFieldBasedCodec::new(Bincode)
+                .with_max_frame_length(max_frame_length);
+            let mut buf = BytesMut::new();
+
+            'stream: for chunk in chunks(&bytes, &chunk_lengths) {
+                buf.extend_from_slice(chunk);
+                loop {
+                    let length_before = buf.len();
+                    match codec.decode(&mut buf) {
+                        Ok(Some(_)) => prop_assert!(buf.len() < length_before),
+                        Ok(None) => {
+                            prop_assert_eq!(length_before, buf.len());
+                            break;
+                        },
+                        // The stream ends with the first error.
+                        Err(_) => break 'stream,
+                    }
+                }
+                prop_assert!(buf.capacity() <= LENGTH_FIELD_LENGTH + max_frame_length + bytes.len() + 64);
             }
         }
     }
+
+    /// The slices of the bytes, of the given lengths in turn.
+    fn chunks<'a>(bytes: &'a [u8], chunk_lengths: &'a [usize]) -> impl Iterator<Item=&'a [u8]> + 'a {
+        let mut start = 0;
+        chunk_lengths.iter().cycle()
+            .map(move |chunk_length| {
+                let chunk_start = start.min(bytes.len());
+                start += chunk_length;
+                &bytes[chunk_start..start.min(bytes.len())]
+            })
+            .take_while(|chunk| !chunk.is_empty())
+    }
+
+    /// Events of a single entity, moving along a line.
+    fn new_events(count: usize) -> Vec<SpatialEvent<DemoEntity>> {
+        let entity = DemoEntity{
+            id: Uuid::new_v4(),
+            last_state_update: Timestamp::new(),
+        };
+
+        (0..count)
+            .map(|x| SpatialEvent{
+                from: Point(x, 3),
+                to: Some(Point(x + 1, 3)),
+                acting_entity: entity.clone(),
+                is_a_move: true,
+            })
+            .collect()
+    }
+
+    fn message_strategy() -> impl Strategy<Value=Message> {
+        let event = (any::<usize>(), any::<usize>(), any::<Option<(usize, usize)>>(), any::<bool>())
+            .prop_map(|(x, y, to, is_a_move)| {
+                SpatialEvent{
+                    from: Point(x, y),
+                    to: to.map(|(x, y)| Point(x, y)),
+                    acting_entity: DemoEntity{
+                        id: Uuid::new_v4(),
+                        last_state_update: Timestamp::new(),
+                    },
+                    is_a_move,
+                }
+            });
+
+        prop_oneof![
+            event.clone().prop_map(Message::Event),
+            vec(event, 0..8).prop_map(Message::Events),
+            any::<u32>().prop_map(Message::SnapshotAck),
+            any::<u32>().prop_map(Message::HandleReleased),
+        ]
+    }
 }
@@ -0,0 +1,480 @@
+//! Quake style delta snapshots: each snapshot only carries what changed since the state the client
+//! acknowledged, entity by entity.
+//!
+//! The server encodes the events of a connection into numbered snapshots, and remembers the state
+//! it sent for each entity until the client acknowledges the snapshot. Each delta names the
+//! snapshot it applies to, the last one acknowledged that held the entity, so the client keeps the
+//! states it received until the server stops referring to them.
+
+use entity::DemoEntity;
+use entity::Timestamp;
+use spatiub::spatial::Point;
+use spatiub::spatial::SpatialEvent;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+type Event = SpatialEvent<DemoEntity>;
+
+/// The states of an entity not acknowledged yet, on both sides. Beyond, the oldest ones are
+/// forgotten: the client keeps the acknowledged state, and so remains able to decode the next
+/// deltas.
+const MAX_UNACKNOWLEDGED_STATES: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub sequence: u32,
+    pub deltas: Vec<EntityDelta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EntityDelta {
+    pub id: Uuid,
+    /// The snapshot holding the state the delta applies to, `None` if it carries the whole state.
+    pub base: Option<u32>,
+    pub position: PositionDelta,
+    /// `None` if unchanged.
+    pub last_state_update: Option<Timestamp>,
+    pub is_a_move: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PositionDelta {
+    Unchanged,
+    Offset(i8, i8),
+    Absolute(Point),
+    /// The entity left.
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct EntityState {
+    position: Point,
+    last_state_update: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeltaError {
+    /// The state the delta applies to is unknown.
+    MissingBase{id: Uuid, base: u32},
+    /// A whole state misses a field.
+    IncompleteState{id: Uuid},
+    /// The offset takes the entity out of the map.
+    InvalidOffset{id: Uuid},
+}
+
+impl Display for DeltaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DeltaError::MissingBase{ref id, base} => write!(f, "No state of {} in snapshot {}", id, base),
+            DeltaError::IncompleteState{ref id} => write!(f, "Incomplete state of {}", id),
+            DeltaError::InvalidOffset{ref id} => write!(f, "Invalid offset for {}", id),
+        }
+    }
+}
+
+impl Error for DeltaError {}
+
+/// Encodes the events sent to a connection into snapshots.
+#[derive(Debug, Default)]
+pub struct DeltaEncoder {
+    sequence: u32,
+    entities: HashMap<Uuid, SentStates>,
+}
+
+#[derive(Debug, Default)]
+struct SentStates {
+    acknowledged: Option<(u32, EntityState)>,
+    unacknowledged: VecDeque<(u32, EntityState)>,
+}
+
+impl DeltaEncoder {
+    pub fn new() -> DeltaEncoder {
+        DeltaEncoder::default()
+    }
+
+    /// Encodes the events into the next snapshot, at most one event per entity.
+    pub fn encode<'a, I: IntoIterator<Item=&'a Event>>(&mut self, events: I) -> Snapshot {
+        self.sequence += 1;
+        let sequence = self.sequence;
+
+        let deltas = events.into_iter()
+            .map(|event| {
+                let id = event.acting_entity.id;
+                let position = match event.to {
+                    Some(ref position) => position.clone(),
+                    None => {
+                        self.entities.remove(&id);
+                        return EntityDelta{
+                            id,
+                            base: None,
+                            position: PositionDelta::Removed,
+                            last_state_update: None,
+                            is_a_move: event.is_a_move,
+                        };
+                    },
+                };
+
+                let state = EntityState{
+                    position,
+                    last_state_update: event.acting_entity.last_state_update.clone(),
+                };
+                let sent_states = self.entities.entry(id).or_default();
+                let delta = delta(id, sent_states.acknowledged.as_ref(), &state, event.is_a_move);
+
+                sent_states.unacknowledged.push_back((sequence, state));
+                if sent_states.unacknowledged.len() > MAX_UNACKNOWLEDGED_STATES {
+                    sent_states.unacknowledged.pop_front();
+                }
+
+                delta
+            })
+            .collect();
+
+        Snapshot{
+            sequence,
+            deltas,
+        }
+    }
+
+    /// The client received the snapshot, and those before it.
+    pub fn acknowledge(&mut self, sequence: u32) {
+        for sent_states in self.entities.values_mut() {
+            while sent_states.unacknowledged.front().map(|&(sent, _)| sent <= sequence).unwrap_or(false) {
+                sent_states.acknowledged = sent_states.unacknowledged.pop_front();
+            }
+        }
+    }
+}
+
+fn delta(id: Uuid, base: Option<&(u32, EntityState)>, state: &EntityState, is_a_move: bool) -> EntityDelta {
+    let (base, base_state) = match base {
+        Some(&(base, ref base_state)) => (base, base_state),
+        None => return EntityDelta{
+            id,
+            base: None,
+            position: PositionDelta::Absolute(state.position.clone()),
+            last_state_update: Some(state.last_state_update.clone()),
+            is_a_move,
+        },
+    };
+
+    let position = if state.position == base_state.position {
+        PositionDelta::Unchanged
+    } else {
+        match (offset(base_state.position.0, state.position.0), offset(base_state.position.1, state.position.1)) {
+            (Some(offset_x), Some(offset_y)) => PositionDelta::Offset(offset_x, offset_y),
+            _ => PositionDelta::Absolute(state.position.clone()),
+        }
+    };
+
+    let last_state_update = if state.last_state_update == base_state.last_state_update {
+        None
+    } else {
+        Some(state.last_state_update.clone())
+    };
+
+    EntityDelta{
+        id,
+        base: Some(base),
+        position,
+        last_state_update,
+        is_a_move,
+    }
+}
+
+fn offset(from: usize, to: usize) -> Option<i8> {
+    let offset = to as i64 - from as i64;
+    if offset >= i64::from(i8::MIN) && offset <= i64::from(i8::MAX) {
+        Some(offset as i8)
+    } else {
+        None
+    }
+}
+
+/// Decodes the snapshots of a connection into events.
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    entities: HashMap<Uuid, VecDeque<(u32, EntityState)>>,
+}
+
+impl DeltaDecoder {
+    pub fn new() -> DeltaDecoder {
+        DeltaDecoder::default()
+    }
+
+    pub fn decode(&mut self, snapshot: Snapshot) -> Result<Vec<Event>, DeltaError> {
+        let mut events = Vec::with_capacity(snapshot.deltas.len());
+
+        for delta in snapshot.deltas {
+            let id = delta.id;
+
+            if delta.position == PositionDelta::Removed {
+                if let Some(mut received_states) = self.entities.remove(&id) {
+                    let (_sequence, last_state) = received_states.pop_back().expect("Entity without state");
+                    events.push(SpatialEvent{
+                        from: last_state.position,
+                        to: None,
+                        acting_entity: DemoEntity{
+                            id,
+                            last_state_update: last_state.last_state_update,
+                        },
+                        is_a_move: delta.is_a_move,
+                    });
+                }
+                continue;
+            }
+
+            let received_states = self.entities.entry(id).or_default();
+            let base_state = match delta.base {
+                Some(base) => {
+                    // The server only refers to more recent states from now on.
+                    while received_states.front().map(|&(received, _)| received < base).unwrap_or(false) {
+                        received_states.pop_front();
+                    }
+
+                    match received_states.front() {
+                        Some(&(received, ref state)) if received == base => Some(state.clone()),
+                        _ => return Err(DeltaError::MissingBase{id, base}),
+                    }
+                },
+                None => None,
+            };
+
+            let position = match (delta.position, base_state.as_ref()) {
+                (PositionDelta::Absolute(position), _) => position,
+                (PositionDelta::Unchanged, Some(base_state)) => base_state.position.clone(),
+                (PositionDelta::Offset(offset_x, offset_y), Some(base_state)) => {
+                    match (apply(base_state.position.0, offset_x), apply(base_state.position.1, offset_y)) {
+                        (Some(x), Some(y)) => Point(x, y),
+                        _ => return Err(DeltaError::InvalidOffset{id}),
+                    }
+                },
+                _ => return Err(DeltaError::IncompleteState{id}),
+            };
+
+            let last_state_update = match (delta.last_state_update, base_state) {
+                (Some(last_state_update), _) => last_state_update,
+                (None, Some(base_state)) => base_state.last_state_update,
+                (None, None) => return Err(DeltaError::IncompleteState{id}),
+            };
+
+            let from = received_states.back()
+                .map(|(_, state)| state.position.clone())
+                .unwrap_or_else(|| position.clone());
+            received_states.push_back((snapshot.sequence, EntityState{
+                position: position.clone(),
+                last_state_update: last_state_update.clone(),
+            }));
+
+            // The server refers to the base, or to one of the last states it sent once the client
+            // acknowledges it.
+            let kept_base = if delta.base.is_some() { 1 } else { 0 };
+            while received_states.len() > kept_base + MAX_UNACKNOWLEDGED_STATES {
+                received_states.remove(kept_base);
+            }
+
+            events.push(SpatialEvent{
+                from,
+                to: Some(position),
+                acting_entity: DemoEntity{
+                    id,
+                    last_state_update,
+                },
+                is_a_move: delta.is_a_move,
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+fn apply(coordinate: usize, offset: i8) -> Option<usize> {
+    if offset >= 0 {
+        coordinate.checked_add(offset as usize)
+    } else {
+        coordinate.checked_sub(offset.unsigned_abs() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use message::Message;
+
+    #[test]
+    pub fn first_snapshot_carries_whole_states() {
+        let mut encoder = DeltaEncoder::new();
+        let entity = new_entity();
+
+        let snapshot = encoder.encode(&[move_event(&entity, Point(5, 5), Point(6, 5))]);
+
+        assert_eq!(1, snapshot.sequence);
+        assert_eq!(None, snapshot.deltas[0].base);
+        assert_eq!(PositionDelta::Absolute(Point(6, 5)), snapshot.deltas[0].position);
+        assert_eq!(Some(entity.last_state_update), snapshot.deltas[0].last_state_update);
+    }
+
+    #[test]
+    pub fn acknowledged_states_are_the_base_of_the_next_deltas() {
+        let mut encoder = DeltaEncoder::new();
+        let entity = new_entity();
+        encoder.encode(&[move_event(&entity, Point(5, 5), Point(6, 5))]);
+        encoder.acknowledge(1);
+
+        let snapshot = encoder.encode(&[move_event(&entity, Point(6, 5), Point(7, 4))]);
+
+        assert_eq!(EntityDelta{
+            id: entity.id,
+            base: Some(1),
+            position: PositionDelta::Offset(1, -1),
+            last_state_update: None,
+            is_a_move: true,
+        }, snapshot.deltas[0]);
+    }
+
+    #[test]
+    pub fn deltas_apply_to_the_last_acknowledged_state() {
+        let mut encoder = DeltaEncoder::new();
+        let entity = new_entity();
+        encoder.encode(&[move_event(&entity, Point(5, 5), Point(6, 5))]);
+        encoder.acknowledge(1);
+        encoder.encode(&[move_event(&entity, Point(6, 5), Point(7, 5))]);
+
+        let snapshot = encoder.encode(&[move_event(&entity, Point(7, 5), Point(8, 5))]);
+
+        assert_eq!(Some(1), snapshot.deltas[0].base);
+        assert_eq!(PositionDelta::Offset(2, 0), snapshot.deltas[0].position);
+    }
+
+    #[test]
+    pub fn far_moves_are_absolute() {
+        let mut encoder = DeltaEncoder::new();
+        let entity = new_entity();
+        encoder.encode(&[move_event(&entity, Point(5, 5), Point(6, 5))]);
+        encoder.acknowledge(1);
+
+        let snapshot = encoder.encode(&[move_event(&entity, Point(6, 5), Point(600, 5))]);
+
+        assert_eq!(PositionDelta::Absolute(Point(600, 5)), snapshot.deltas[0].position);
+    }
+
+    #[test]
+    pub fn decoded_snapshots_give_back_the_events() {
+        let mut encoder = DeltaEncoder::new();
+        let mut decoder = DeltaDecoder::new();
+        let (entity, other_entity) = (new_entity(), new_entity());
+        let mut events = vec![];
+        let mut position = Point(100, 100);
+        for step in 0..10 {
+            let mut entity = entity.clone();
+            if step % 3 == 0 {
+                entity.last_state_update = Timestamp::new();
+            }
+            let destination = Point(position.0 + step * 20, position.1 - 1);
+            events.push(vec![
+                move_event(&entity, position.clone(), destination.clone()),
+                move_event(&other_entity, Point(0, 0), Point(0, 0)),
+            ]);
+            position = destination;
+        }
+        events.push(vec![SpatialEvent{
+            from: Point(0, 0),
+            to: None,
+            acting_entity: other_entity.clone(),
+            is_a_move: true,
+        }]);
+
+        for (index, tick_events) in events.iter().enumerate() {
+            let snapshot = encoder.encode(tick_events);
+            // Acknowledges late, and not every snapshot.
+            if index % 2 == 1 {
+                encoder.acknowledge(snapshot.sequence - 1);
+            }
+
+            let decoded = decoder.decode(snapshot).unwrap();
+            let destinations: Vec<_> = decoded.iter().map(|event| (event.acting_entity.clone(), event.to.clone())).collect();
+            let expected: Vec<_> = tick_events.iter().map(|event| (event.acting_entity.clone(), event.to.clone())).collect();
+            assert_eq!(expected, destinations);
+        }
+    }
+
+    #[test]
+    pub fn missing_base_is_an_error() {
+        let mut encoder = DeltaEncoder::new();
+        let entity = new_entity();
+        encoder.encode(&[move_event(&entity, Point(5, 5), Point(6, 5))]);
+        encoder.acknowledge(1);
+        let snapshot = encoder.encode(&[move_event(&entity, Point(6, 5), Point(7, 5))]);
+
+        match DeltaDecoder::new().decode(snapshot) {
+            Err(err) => assert_eq!(DeltaError::MissingBase{id: entity.id, base: 1}, err),
+            Ok(events) => panic!("Expected a missing base, got {:?}", events),
+        }
+    }
+
+    #[test]
+    pub fn states_received_without_acknowledgements_are_capped() {
+        let mut encoder = DeltaEncoder::new();
+        let mut decoder = DeltaDecoder::new();
+        let entity = new_entity();
+        let far_moves = |step: usize| move_event(&entity, Point(step * 200, 5), Point((step + 1) * 200, 5));
+
+        for step in 0..100 {
+            decoder.decode(encoder.encode(&[far_moves(step)])).unwrap();
+        }
+        assert_eq!(MAX_UNACKNOWLEDGED_STATES, decoder.entities[&entity.id].len());
+
+        encoder.acknowledge(100);
+        for step in 100..200 {
+            let snapshot = encoder.encode(&[far_moves(step)]);
+            assert_eq!(Some(100), snapshot.deltas[0].base);
+            decoder.decode(snapshot).unwrap();
+        }
+        assert_eq!(MAX_UNACKNOWLEDGED_STATES + 1, decoder.entities[&entity.id].len());
+
+        encoder.acknowledge(200);
+        let snapshot = encoder.encode(&[move_event(&entity, Point(200 * 200, 5), Point(200 * 200 + 1, 5))]);
+        assert_eq!(Some(200), snapshot.deltas[0].base);
+        assert_eq!(Some(Point(200 * 200 + 1, 5)), decoder.decode(snapshot).unwrap()[0].to);
+    }
+
+    #[test]
+    pub fn deltas_are_smaller_than_events() {
+        let mut encoder = DeltaEncoder::new();
+        let entities: Vec<_> = (0..10).map(|_| new_entity()).collect();
+        let moves = |from: usize| -> Vec<_> {
+            entities.iter().map(|entity| move_event(entity, Point(from, 5), Point(from + 1, 5))).collect()
+        };
+        encoder.encode(&moves(0));
+        encoder.acknowledge(1);
+
+        let events = moves(1);
+        let snapshot = encoder.encode(&events);
+
+        let snapshot_length = bincode::serialize(&Message::Snapshot(snapshot)).unwrap().len();
+        let events_length = bincode::serialize(&Message::Events(events)).unwrap().len();
+        assert!(snapshot_length < events_length, "{} bytes against {}", snapshot_length, events_length);
+    }
+
+    fn new_entity() -> DemoEntity {
+        DemoEntity{
+            id: Uuid::new_v4(),
+            last_state_update: Timestamp::new(),
+        }
+    }
+
+    fn move_event(entity: &DemoEntity, from: Point, to: Point) -> Event {
+        SpatialEvent{
+            from,
+            to: Some(to),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        }
+    }
+}
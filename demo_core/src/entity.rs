@@ -15,6 +15,8 @@ pub struct DemoEntity{
 }
 
 impl Entity for DemoEntity{
+    type Id = Uuid;
+
     fn id(&self) -> &Uuid {
         &self.id
     }
@@ -0,0 +1,472 @@
+use codec::EncodedFrame;
+use entity::DemoEntity;
+use format::AnyFormat;
+use futures::unsync::mpsc::{self, UnboundedReceiver};
+use futures::unsync::mpsc::UnboundedSender;
+use futures::{Async, Poll, Stream};
+use message::EventBatcher;
+use message::Message;
+use position::PositionEncoding;
+use position::ZonedPositions;
+use spatiub::pub_sub::PubSubError;
+use spatiub::pub_sub::Subscriber;
+use spatiub::spatial::Point;
+use spatiub::spatial::SpatialEvent;
+use std::cell::RefCell;
+use std::rc::Rc;
+use uuid::Uuid;
+
+type Event = SpatialEvent<DemoEntity>;
+
+/// Remembers the frames of the last published event, so that it is encoded once per format and
+/// position encoding whatever the number of connections it is sent to. Every subscription of a
+/// channel has to share the same cache.
+#[derive(Debug, Default)]
+pub struct FrameCache {
+    /// A copy rather than a reference, which would keep the publisher from updating the event in
+    /// place. The same event may then come back with other positions, so it is compared by value.
+    last_event: Option<Event>,
+    /// The frames of the last event, encoded on demand.
+    frames: Vec<(AnyFormat, PositionEncoding, EncodedFrame)>,
+    zoned_positions: Option<ZonedPositions>,
+    number_of_encodings: usize,
+}
+
+impl FrameCache {
+    pub fn new() -> FrameCache {
+        FrameCache::default()
+    }
+
+    /// Allows zoned positions, see `supports`.
+    pub fn with_zoned_positions(mut self, zoned_positions: ZonedPositions) -> FrameCache {
+        self.zoned_positions = Some(zoned_positions);
+        self
+    }
+
+    /// Whether frames can be encoded with the given position encoding.
+    pub fn supports(&self, encoding: PositionEncoding) -> bool {
+        match encoding {
+            PositionEncoding::Plain => true,
+            PositionEncoding::Zoned => self.zoned_positions.is_some(),
+        }
+    }
+
+    fn frame_for(&mut self, event: &Rc<Event>, format: AnyFormat, encoding: PositionEncoding) -> EncodedFrame {
+        let is_cached = match self.last_event {
+            Some(ref cached_event) => cached_event == event.as_ref(),
+            None => false,
+        };
+        if !is_cached {
+            self.last_event = Some(event.as_ref().clone());
+            self.frames.clear();
+        }
+
+        if encoding == PositionEncoding::Zoned {
+            if let Some(frame) = self.cached_frame(format, encoding) {
+                return frame;
+            }
+
+            // Events outside of the map have no zoned positions, they are sent as is.
+            let zoned_event = self.zoned_positions
+                .and_then(|zoned_positions| zoned_positions.encode_event(event));
+            if let Some(zoned_event) = zoned_event {
+                return self.encode(Message::ZonedEvent(zoned_event), format, encoding);
+            }
+        }
+
+        match self.cached_frame(format, PositionEncoding::Plain) {
+            Some(frame) => frame,
+            None => self.encode(Message::Event(event.as_ref().clone()), format, PositionEncoding::Plain),
+        }
+    }
+
+    fn cached_frame(&self, format: AnyFormat, encoding: PositionEncoding) -> Option<EncodedFrame> {
+        self.frames.iter()
+            .find(|&&(cached_format, cached_encoding, _)| cached_format == format && cached_encoding == encoding)
+            .map(|(_, _, frame)| frame.clone())
+    }
+
+    fn encode(&mut self, message: Message, format: AnyFormat, encoding: PositionEncoding) -> EncodedFrame {
+        let frame = EncodedFrame::encode_with(&message, &format).expect("Could not encode the event");
+        self.frames.push((format, encoding, frame.clone()));
+        self.number_of_encodings += 1;
+        frame
+    }
+
+    /// The number of events encoded so far.
+    pub fn number_of_encodings(&self) -> usize {
+        self.number_of_encodings
+    }
+}
+
+/// An event encoded into a frame, with what a connection needs to schedule it without decoding it.
+#[derive(Clone, Debug)]
+pub struct OutgoingFrame {
+    pub entity_id: Uuid,
+    /// Where the event takes its entity, `None` if it leaves.
+    pub position: Option<Point>,
+    /// `None` for the subscribers without frames, see `FrameSubscriber::without_frames`.
+    pub frame: Option<EncodedFrame>,
+    pub event: Rc<Event>,
+}
+
+/// A subscriber sending events already encoded into frames, sharing the same buffer with every
+/// other subscriber of the event.
+#[derive(Clone, Debug)]
+pub struct FrameSubscriber {
+    sender: UnboundedSender<OutgoingFrame>,
+    entity_id: Uuid,
+    cache: Rc<RefCell<FrameCache>>,
+    format: AnyFormat,
+    position_encoding: PositionEncoding,
+    sends_frames: bool,
+}
+
+impl FrameSubscriber {
+    /// The format of the frames sent to the subscriber, bincode by default.
+    pub fn with_format(mut self, format: AnyFormat) -> FrameSubscriber {
+        self.format = format;
+        self
+    }
+
+    /// The encoding of the positions sent to the subscriber, plain by default. The cache has to
+    /// support it, see `FrameCache::supports`.
+    pub fn with_position_encoding(mut self, position_encoding: PositionEncoding) -> FrameSubscriber {
+        self.position_encoding = position_encoding;
+        self
+    }
+
+    /// Sends the events without their frames, for the connections sent something else, such as
+    /// delta snapshots. The events are then not encoded for them.
+    pub fn without_frames(mut self) -> FrameSubscriber {
+        self.sends_frames = false;
+        self
+    }
+}
+
+pub fn new_subscriber(entity_id: Uuid, cache: Rc<RefCell<FrameCache>>) -> (FrameSubscriber, UnboundedReceiver<OutgoingFrame>) {
+    let (sender, receiver) = mpsc::unbounded();
+
+    let subscriber = FrameSubscriber {
+        sender,
+        entity_id,
+        cache,
+        format: AnyFormat::default(),
+        position_encoding: PositionEncoding::Plain,
+        sends_frames: true,
+    };
+
+    (subscriber, receiver)
+}
+
+impl Subscriber<Event> for FrameSubscriber {
+    type Id = Uuid;
+
+    fn send(&self, event: Rc<Event>) -> Result<bool, PubSubError> {
+        let frame = if self.sends_frames {
+            Some(self.cache.borrow_mut().frame_for(&event, self.format, self.position_encoding))
+        } else {
+            None
+        };
+        let outgoing_frame = OutgoingFrame{
+            entity_id: event.acting_entity.id,
+            position: event.to.clone(),
+            frame,
+            event,
+        };
+
+        match &self.sender.unbounded_send(outgoing_frame) {
+            Ok(()) => {
+                Ok(true)
+            },
+            Err(_err) => {
+                Err(PubSubError::ReceiverIsGone)
+            }
+        }
+    }
+
+    fn entity_id(&self) -> &Uuid {
+        &self.entity_id
+    }
+
+    /// The sender goes with the subscription, ending the stream of the connection.
+    fn on_dropped(&self, reason: &PubSubError) {
+        debug!("Subscription of {} dropped: {}", self.entity_id, reason);
+    }
+}
+
+/// Packs the frames of a subscription into batches, see `batched`.
+pub struct BatchedFrames<S> {
+    frames: S,
+    batcher: EventBatcher,
+    is_done: bool,
+}
+
+/// Packs the frames ready together into batches, with the given batcher.
+pub fn batched<S: Stream<Item=OutgoingFrame>>(frames: S, batcher: EventBatcher) -> BatchedFrames<S> {
+    BatchedFrames{
+        frames,
+        batcher,
+        is_done: false,
+    }
+}
+
+impl <S: Stream<Item=OutgoingFrame>> Stream for BatchedFrames<S> {
+    type Item = EncodedFrame;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<EncodedFrame>, S::Error> {
+        if self.is_done {
+            return Ok(Async::Ready(self.batcher.finish()));
+        }
+
+        loop {
+            match self.frames.poll()? {
+                Async::Ready(Some(outgoing_frame)) => {
+                    let frame = outgoing_frame.frame
+                        .expect("Frames are only batched for subscribers sending them");
+                    match self.batcher.push(frame) {
+                        Ok(Some(batch)) => return Ok(Async::Ready(Some(batch))),
+                        Ok(None) => {},
+                        Err(err) => error!("Frame of {} dropped: {}", outgoing_frame.entity_id, err),
+                    }
+                },
+                Async::Ready(None) => {
+                    self.is_done = true;
+                    return Ok(Async::Ready(self.batcher.finish()));
+                },
+                Async::NotReady => {
+                    return match self.batcher.finish() {
+                        Some(batch) => Ok(Async::Ready(Some(batch))),
+                        None => Ok(Async::NotReady),
+                    };
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use codec::EncodedFrameCodec;
+    use entity::Timestamp;
+    use futures::{Future, Stream};
+    use spatiub::pub_sub::PubSubChannel;
+    use spatiub::spatial::MapDefinition;
+    use spatiub::spatial::Point;
+    use tokio::codec::Decoder;
+
+    #[test]
+    pub fn events_are_encoded_once() {
+        let cache = Rc::new(RefCell::new(FrameCache::new()));
+        let (first_subscriber, first_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let (second_subscriber, second_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(first_subscriber);
+        channel.subscribe(second_subscriber);
+
+        let event = SpatialEvent{
+            from: Point(0, 0),
+            to: Some(Point(1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        };
+        channel.publish(Rc::new(event.clone()));
+
+        let (first_frame, _receiver) = first_receiver.into_future().wait().ok().unwrap();
+        let (second_frame, _receiver) = second_receiver.into_future().wait().ok().unwrap();
+        let first_frame = first_frame.unwrap().frame.unwrap();
+        let second_frame = second_frame.unwrap().frame.unwrap();
+
+        assert_eq!(1, cache.borrow().number_of_encodings());
+        assert_eq!(first_frame.as_ref().as_ptr(), second_frame.as_ref().as_ptr());
+
+        let mut buf = BytesMut::from(first_frame.as_ref());
+        let decoded: Option<Message> = EncodedFrameCodec::new().decode(&mut buf).unwrap();
+        match decoded {
+            Some(Message::Event(decoded_event)) => {
+                assert_eq!(event.acting_entity, decoded_event.acting_entity);
+                assert_eq!(event.to, decoded_event.to);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn events_are_encoded_once_per_position_encoding() {
+        let zoned_positions = ZonedPositions::new(&MapDefinition::new(16, 4)).unwrap();
+        let cache = Rc::new(RefCell::new(FrameCache::new().with_zoned_positions(zoned_positions)));
+        let (plain_subscriber, plain_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let (zoned_subscriber, zoned_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let (other_zoned_subscriber, _other_zoned_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let zoned_subscriber = zoned_subscriber.with_position_encoding(PositionEncoding::Zoned);
+        let other_zoned_subscriber = other_zoned_subscriber.with_position_encoding(PositionEncoding::Zoned);
+
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(plain_subscriber);
+        channel.subscribe(zoned_subscriber);
+        channel.subscribe(other_zoned_subscriber);
+
+        let event = SpatialEvent{
+            from: Point(63, 62),
+            to: Some(Point(63, 63)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        };
+        channel.publish(Rc::new(event.clone()));
+
+        assert_eq!(2, cache.borrow().number_of_encodings());
+
+        let (plain_frame, _receiver) = plain_receiver.into_future().wait().ok().unwrap();
+        let mut buf = BytesMut::from(plain_frame.unwrap().frame.unwrap().as_ref());
+        match EncodedFrameCodec::new().decode(&mut buf).unwrap() {
+            Some(Message::Event(decoded_event)) => assert_eq!(event.to, decoded_event.to),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        let (zoned_frame, _receiver) = zoned_receiver.into_future().wait().ok().unwrap();
+        let mut buf = BytesMut::from(zoned_frame.unwrap().frame.unwrap().as_ref());
+        match EncodedFrameCodec::new().decode(&mut buf).unwrap() {
+            Some(Message::ZonedEvent(decoded_event)) => {
+                assert_eq!(event.to, decoded_event.to.map(|to| zoned_positions.decode(&to).unwrap()));
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn events_are_encoded_once_per_format() {
+        let cache = Rc::new(RefCell::new(FrameCache::new()));
+        let (bincode_subscriber, bincode_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let (json_subscriber, json_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let (other_json_subscriber, _other_json_receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(bincode_subscriber);
+        channel.subscribe(json_subscriber.with_format(AnyFormat::Json));
+        channel.subscribe(other_json_subscriber.with_format(AnyFormat::Json));
+
+        let event = SpatialEvent{
+            from: Point(0, 0),
+            to: Some(Point(1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        };
+        channel.publish(Rc::new(event.clone()));
+
+        assert_eq!(2, cache.borrow().number_of_encodings());
+
+        let (bincode_frame, _receiver) = bincode_receiver.into_future().wait().ok().unwrap();
+        let mut buf = BytesMut::from(bincode_frame.unwrap().frame.unwrap().as_ref());
+        match EncodedFrameCodec::new().decode(&mut buf).unwrap() {
+            Some(Message::Event(decoded_event)) => assert_eq!(event.to, decoded_event.to),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        let (json_frame, _receiver) = json_receiver.into_future().wait().ok().unwrap();
+        let mut buf = BytesMut::from(json_frame.unwrap().frame.unwrap().as_ref());
+        match EncodedFrameCodec::with_format(AnyFormat::Json).decode(&mut buf).unwrap() {
+            Some(Message::Event(decoded_event)) => assert_eq!(event.to, decoded_event.to),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn events_updated_in_place_are_encoded_again() {
+        let cache = Rc::new(RefCell::new(FrameCache::new()));
+        let (subscriber, receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(subscriber);
+
+        let mut event = Rc::new(SpatialEvent{
+            from: Point(0, 0),
+            to: Some(Point(1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        });
+        channel.publish(event.clone());
+
+        let (frame, receiver) = receiver.into_future().wait().ok().unwrap();
+        drop(frame);
+        Rc::get_mut(&mut event).expect("The cache holds on to the event").to = Some(Point(2, 0));
+        channel.publish(event.clone());
+
+        assert_eq!(2, cache.borrow().number_of_encodings());
+        let (frame, _receiver) = receiver.into_future().wait().ok().unwrap();
+        let mut buf = BytesMut::from(frame.unwrap().frame.unwrap().as_ref());
+        match EncodedFrameCodec::new().decode(&mut buf).unwrap() {
+            Some(Message::Event(decoded_event)) => assert_eq!(Some(Point(2, 0)), decoded_event.to),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn subscribers_without_frames_do_not_encode_events() {
+        let cache = Rc::new(RefCell::new(FrameCache::new()));
+        let (subscriber, receiver) = new_subscriber(Uuid::new_v4(), cache.clone());
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(subscriber.without_frames());
+
+        channel.publish(Rc::new(SpatialEvent{
+            from: Point(0, 0),
+            to: Some(Point(1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        }));
+
+        let (outgoing_frame, _receiver) = receiver.into_future().wait().ok().unwrap();
+        let outgoing_frame = outgoing_frame.unwrap();
+        assert_eq!(None, outgoing_frame.frame);
+        assert_eq!(Some(Point(1, 0)), outgoing_frame.position);
+        assert_eq!(0, cache.borrow().number_of_encodings());
+    }
+
+    #[test]
+    pub fn frames_ready_together_are_batched() {
+        let cache = Rc::new(RefCell::new(FrameCache::new()));
+        let (subscriber, receiver) = new_subscriber(Uuid::new_v4(), cache);
+        let mut channel = PubSubChannel::new();
+        channel.subscribe(subscriber);
+
+        for x in 0..3 {
+            channel.publish(Rc::new(SpatialEvent{
+                from: Point(x, 0),
+                to: Some(Point(x + 1, 0)),
+                acting_entity: DemoEntity{
+                    id: Uuid::new_v4(),
+                    last_state_update: Timestamp::new(),
+                },
+                is_a_move: true,
+            }));
+        }
+        drop(channel);
+
+        let batches: Vec<_> = batched(receiver, EventBatcher::new(usize::MAX)).collect().wait().unwrap();
+        assert_eq!(1, batches.len());
+
+        let mut buf = BytesMut::from(batches[0].as_ref());
+        let decoded: Option<Message> = EncodedFrameCodec::new().decode(&mut buf).unwrap();
+        match decoded {
+            Some(Message::Events(events)) => assert_eq!(3, events.len()),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! How messages are written into frames. Bincode is the most compact, JSON and MessagePack are
+//! there for debugging and for clients not written in Rust.
+
+use bincode;
+use rmp_serde;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+pub trait Format {
+    fn serialize<M: Serialize>(&self, msg: &M) -> io::Result<Vec<u8>>;
+
+    fn deserialize<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M>;
+
+    /// Whether `EventBatcher` can pack the frames of events written in this format.
+    fn packs_events(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Bincode;
+
+impl Format for Bincode {
+    fn serialize<M: Serialize>(&self, msg: &M) -> io::Result<Vec<u8>> {
+        bincode::serialize(msg).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+    }
+
+    fn deserialize<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M> {
+        bincode::deserialize(bytes).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+
+    fn packs_events(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Json;
+
+impl Format for Json {
+    fn serialize<M: Serialize>(&self, msg: &M) -> io::Result<Vec<u8>> {
+        serde_json::to_vec(msg).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+    }
+
+    fn deserialize<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M> {
+        serde_json::from_slice(bytes).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessagePack;
+
+impl Format for MessagePack {
+    fn serialize<M: Serialize>(&self, msg: &M) -> io::Result<Vec<u8>> {
+        rmp_serde::to_vec(msg).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+    }
+
+    fn deserialize<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M> {
+        rmp_serde::from_slice(bytes).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+}
+
+/// One of the formats, chosen at runtime for each connection.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AnyFormat {
+    #[default]
+    Bincode,
+    Json,
+    MessagePack,
+}
+
+impl AnyFormat {
+    pub const ALL: [AnyFormat; 3] = [AnyFormat::Bincode, AnyFormat::Json, AnyFormat::MessagePack];
+}
+
+impl Format for AnyFormat {
+    fn serialize<M: Serialize>(&self, msg: &M) -> io::Result<Vec<u8>> {
+        match *self {
+            AnyFormat::Bincode => Bincode.serialize(msg),
+            AnyFormat::Json => Json.serialize(msg),
+            AnyFormat::MessagePack => MessagePack.serialize(msg),
+        }
+    }
+
+    fn deserialize<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M> {
+        match *self {
+            AnyFormat::Bincode => Bincode.deserialize(bytes),
+            AnyFormat::Json => Json.deserialize(bytes),
+            AnyFormat::MessagePack => MessagePack.deserialize(bytes),
+        }
+    }
+
+    fn packs_events(&self) -> bool {
+        match *self {
+            AnyFormat::Bincode => Bincode.packs_events(),
+            AnyFormat::Json => Json.packs_events(),
+            AnyFormat::MessagePack => MessagePack.packs_events(),
+        }
+    }
+}
+
+/// The names of the formats on the command lines: `bincode`, `json` and `msgpack`.
+impl FromStr for AnyFormat {
+    type Err = UnknownFormat;
+
+    fn from_str(name: &str) -> Result<AnyFormat, UnknownFormat> {
+        match name {
+            "bincode" => Ok(AnyFormat::Bincode),
+            "json" => Ok(AnyFormat::Json),
+            "msgpack" => Ok(AnyFormat::MessagePack),
+            _ => Err(UnknownFormat(name.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownFormat(pub String);
+
+impl Display for UnknownFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Unknown format: {}", self.0)
+    }
+}
+
+impl error::Error for UnknownFormat {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use codec::DecodeError;
+    use codec::LengthFieldBasedCodec;
+    use delta::EntityDelta;
+    use delta::PositionDelta;
+    use delta::Snapshot;
+    use entity::DemoEntity;
+    use entity::Timestamp;
+    use handle::HandleEvent;
+    use message::Message;
+    use position::ZonedEvent;
+    use position::ZonedPoint;
+    use spatiub::layout::ZoneLayout;
+    use spatiub::spatial::MapDefinition;
+    use spatiub::spatial::Point;
+    use spatiub::spatial::SpatialEvent;
+    use tokio::codec::Decoder;
+    use tokio::codec::Encoder;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn bincode_round_trips_every_message() {
+        assert_every_message_round_trips(Bincode);
+    }
+
+    #[test]
+    pub fn json_round_trips_every_message() {
+        assert_every_message_round_trips(Json);
+    }
+
+    #[test]
+    pub fn message_pack_round_trips_every_message() {
+        assert_every_message_round_trips(MessagePack);
+    }
+
+    #[test]
+    pub fn any_format_writes_like_the_format_it_names() {
+        let message = Message::Event(new_event());
+
+        // `AnyFormat` is serializable too.
+        assert_eq!(Bincode.serialize(&message).unwrap(), Format::serialize(&AnyFormat::Bincode, &message).unwrap());
+        assert_eq!(Json.serialize(&message).unwrap(), Format::serialize(&AnyFormat::Json, &message).unwrap());
+        assert_eq!(MessagePack.serialize(&message).unwrap(), Format::serialize(&AnyFormat::MessagePack, &message).unwrap());
+    }
+
+    #[test]
+    pub fn invalid_frames_are_errors() {
+        let mut codec: LengthFieldBasedCodec<Message, Json> = LengthFieldBasedCodec::new(Json);
+        let mut buf = BytesMut::from(&b"\0\0\0\x02{}"[..]);
+
+        match codec.decode(&mut buf) {
+            Err(DecodeError::InvalidFrame(err)) => assert_eq!(ErrorKind::InvalidData, err.kind()),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    fn assert_every_message_round_trips<F: Format + Copy>(format: F) {
+        let mut codec = LengthFieldBasedCodec::new(format);
+        let mut buf = BytesMut::new();
+        let messages = every_message();
+        // Events cannot be compared, their debug output can.
+        let expected: Vec<_> = messages.iter().map(|message| format!("{:?}", message)).collect();
+
+        for message in messages {
+            codec.encode(message, &mut buf).unwrap();
+        }
+        for expected_message in expected {
+            let decoded: Message = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(expected_message, format!("{:?}", decoded));
+        }
+        assert!(buf.is_empty());
+    }
+
+    /// One message of each variant.
+    fn every_message() -> Vec<Message> {
+        let event = new_event();
+        let zoned_event = ZonedEvent{
+            from: ZonedPoint{zone: 4, offset: (0, 15)},
+            to: None,
+            acting_entity: event.acting_entity.clone(),
+            is_a_move: false,
+        };
+
+        vec![
+            Message::ConnectionAck(event.acting_entity.clone(), MapDefinition::new(16, 8).with_layout(ZoneLayout::Morton)),
+            Message::Event(event.clone()),
+            Message::Events(vec![event.clone(), event.clone()]),
+            Message::Snapshot(Snapshot{
+                sequence: 3,
+                deltas: vec![
+                    EntityDelta{
+                        id: event.acting_entity.id,
+                        base: Some(2),
+                        position: PositionDelta::Offset(-1, 1),
+                        last_state_update: None,
+                        is_a_move: true,
+                    },
+                    EntityDelta{
+                        id: Uuid::new_v4(),
+                        base: None,
+                        position: PositionDelta::Absolute(Point(7, 8)),
+                        last_state_update: Some(Timestamp::new()),
+                        is_a_move: true,
+                    },
+                    EntityDelta{
+                        id: Uuid::new_v4(),
+                        base: None,
+                        position: PositionDelta::Removed,
+                        last_state_update: None,
+                        is_a_move: false,
+                    },
+                ],
+            }),
+            Message::SnapshotAck(3),
+            Message::ZonedEvent(zoned_event.clone()),
+            Message::ZonedEvents(vec![zoned_event.clone(), zoned_event]),
+            Message::Introduction(1, event.acting_entity.clone()),
+            Message::HandleEvent(HandleEvent{
+                handle: 1,
+                from: event.from.clone(),
+                to: event.to.clone(),
+                last_state_update: event.acting_entity.last_state_update.clone(),
+                is_a_move: true,
+            }),
+            Message::HandleReleased(1),
+        ]
+    }
+
+    fn new_event() -> SpatialEvent<DemoEntity> {
+        SpatialEvent{
+            from: Point(0, usize::MAX),
+            to: Some(Point(1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        }
+    }
+}
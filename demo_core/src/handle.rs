@@ -0,0 +1,378 @@
+//! Short handles naming the entities on a connection, instead of their 16 byte ids.
+//!
+//! The server introduces an entity with its id and data the first time it is sent to a client,
+//! along with its handle. The next events of the entity only carry the handle. Once the entity
+//! leaves the view of the client, the handle is released and may be given to another entity.
+
+use codec::EncodedFrame;
+use entity::DemoEntity;
+use entity::Timestamp;
+use format::Format;
+use message::Message;
+use spatiub::spatial::MapDefinition;
+use spatiub::spatial::Point;
+use spatiub::spatial::SpatialEvent;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+type Event = SpatialEvent<DemoEntity>;
+
+/// `SpatialEvent`, with the handle of its entity.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HandleEvent {
+    pub handle: u32,
+    pub from: Point,
+    pub to: Option<Point>,
+    pub last_state_update: Timestamp,
+    pub is_a_move: bool,
+}
+
+/// Gives handles to the entities sent to a connection, see the module documentation.
+///
+/// An entity is in view of the client if it is in the zones around the zone of the client, as
+/// seen by `SpatialChannel`. Assuming a different view only costs more introductions.
+#[derive(Debug)]
+pub struct EntityHandles {
+    own_id: Uuid,
+    own_zone: Option<(usize, usize)>,
+    zone_width: usize,
+    handles: HashMap<Uuid, HandleState>,
+    released_handles: Vec<u32>,
+    next_handle: u32,
+}
+
+#[derive(Debug)]
+struct HandleState {
+    handle: u32,
+    position: Point,
+}
+
+impl EntityHandles {
+    pub fn new(own_id: Uuid, map: &MapDefinition) -> EntityHandles {
+        EntityHandles{
+            own_id,
+            own_zone: None,
+            zone_width: map.zone_width(),
+            handles: HashMap::new(),
+            released_handles: vec![],
+            next_handle: 0,
+        }
+    }
+
+    /// Encodes the messages of the event in the given format, see `messages_for`.
+    pub fn frames_for<F: Format>(&mut self, event: &Event, format: &F) -> Vec<EncodedFrame> {
+        let mut messages = vec![];
+        self.messages_for(event, &mut messages);
+
+        messages.iter()
+            .map(|message| EncodedFrame::encode_with(message, format).expect("Could not encode the message"))
+            .collect()
+    }
+
+    /// The messages telling the event to the client: the introduction of its entity if needed,
+    /// the event, then the release of the handles of the entities out of view.
+    pub fn messages_for(&mut self, event: &Event, messages: &mut Vec<Message>) {
+        let id = event.acting_entity.id;
+        let is_own_event = id == self.own_id;
+
+        let handle = match self.handles.get(&id) {
+            Some(state) => state.handle,
+            None => {
+                let handle = self.released_handles.pop().unwrap_or_else(|| {
+                    self.next_handle += 1;
+                    self.next_handle - 1
+                });
+                messages.push(Message::Introduction(handle, event.acting_entity.clone()));
+                handle
+            },
+        };
+
+        messages.push(Message::HandleEvent(HandleEvent{
+            handle,
+            from: event.from.clone(),
+            to: event.to.clone(),
+            last_state_update: event.acting_entity.last_state_update.clone(),
+            is_a_move: event.is_a_move,
+        }));
+
+        let position = match event.to {
+            Some(ref position) => position.clone(),
+            None => {
+                self.release(&id, handle, messages);
+                return;
+            },
+        };
+
+        if is_own_event {
+            let zone = self.zone_of(&position);
+            let zone_changed = self.own_zone != Some(zone);
+            self.own_zone = Some(zone);
+
+            if zone_changed {
+                let out_of_view: Vec<_> = self.handles.iter()
+                    .filter(|&(other_id, state)| *other_id != id && !self.is_in_view(&state.position))
+                    .map(|(other_id, state)| (*other_id, state.handle))
+                    .collect();
+                for (other_id, other_handle) in out_of_view {
+                    self.release(&other_id, other_handle, messages);
+                }
+            }
+        } else if !self.is_in_view(&position) {
+            self.release(&id, handle, messages);
+            return;
+        }
+
+        self.handles.insert(id, HandleState{
+            handle,
+            position,
+        });
+    }
+
+    /// The number of handles given to entities in view.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    fn release(&mut self, id: &Uuid, handle: u32, messages: &mut Vec<Message>) {
+        self.handles.remove(id);
+        self.released_handles.push(handle);
+        messages.push(Message::HandleReleased(handle));
+    }
+
+    fn zone_of(&self, point: &Point) -> (usize, usize) {
+        (point.0 / self.zone_width, point.1 / self.zone_width)
+    }
+
+    fn is_in_view(&self, point: &Point) -> bool {
+        match self.own_zone {
+            Some((own_x, own_y)) => {
+                let (x, y) = self.zone_of(point);
+                x.abs_diff(own_x) <= 1 && y.abs_diff(own_y) <= 1
+            },
+            // The position of the client is unknown yet.
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandleError {
+    /// No entity was introduced with the handle.
+    UnknownHandle(u32),
+}
+
+impl Display for HandleError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            HandleError::UnknownHandle(handle) => write!(f, "No entity introduced with handle {}", handle),
+        }
+    }
+}
+
+impl Error for HandleError {}
+
+/// The entities introduced to a client, by handle.
+#[derive(Debug, Default)]
+pub struct IntroducedEntities {
+    entities: HashMap<u32, DemoEntity>,
+}
+
+impl IntroducedEntities {
+    pub fn new() -> IntroducedEntities {
+        IntroducedEntities::default()
+    }
+
+    /// A handle given again replaces the entity it named.
+    pub fn introduce(&mut self, handle: u32, entity: DemoEntity) {
+        self.entities.insert(handle, entity);
+    }
+
+    pub fn release(&mut self, handle: u32) {
+        self.entities.remove(&handle);
+    }
+
+    pub fn event_for(&mut self, event: HandleEvent) -> Result<Event, HandleError> {
+        let entity = self.entities.get_mut(&event.handle)
+            .ok_or(HandleError::UnknownHandle(event.handle))?;
+        entity.last_state_update = event.last_state_update;
+
+        Ok(SpatialEvent{
+            from: event.from,
+            to: event.to,
+            acting_entity: entity.clone(),
+            is_a_move: event.is_a_move,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+
+    #[test]
+    pub fn entities_are_introduced_once() {
+        let (own, other) = (new_entity(), new_entity());
+        let mut handles = EntityHandles::new(own.id, &MapDefinition::new(16, 8));
+        let mut messages = vec![];
+
+        handles.messages_for(&move_event(&own, Point(20, 20), Some(Point(21, 20))), &mut messages);
+        handles.messages_for(&move_event(&other, Point(22, 20), Some(Point(23, 20))), &mut messages);
+        handles.messages_for(&move_event(&other, Point(23, 20), Some(Point(24, 20))), &mut messages);
+
+        let introductions: Vec<_> = messages.iter()
+            .filter_map(|message| match *message {
+                Message::Introduction(handle, ref entity) => Some((handle, entity.id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![(0, own.id), (1, other.id)], introductions);
+        assert_eq!(5, messages.len());
+        assert_eq!(2, handles.len());
+    }
+
+    #[test]
+    pub fn handles_are_released_when_entities_leave_the_view() {
+        let (own, other) = (new_entity(), new_entity());
+        let mut handles = EntityHandles::new(own.id, &MapDefinition::new(16, 8));
+        let mut messages = vec![];
+        handles.messages_for(&move_event(&own, Point(20, 20), Some(Point(21, 20))), &mut messages);
+        handles.messages_for(&move_event(&other, Point(46, 20), Some(Point(47, 20))), &mut messages);
+
+        messages.clear();
+        handles.messages_for(&move_event(&other, Point(47, 20), Some(Point(48, 20))), &mut messages);
+
+        assert_last_released(1, &messages);
+        assert_eq!(1, handles.len());
+
+        // The handle is given to the next entity introduced.
+        messages.clear();
+        let newcomer = new_entity();
+        handles.messages_for(&move_event(&newcomer, Point(30, 30), Some(Point(31, 30))), &mut messages);
+        match messages[0] {
+            Message::Introduction(handle, ref entity) => assert_eq!((1, &newcomer), (handle, entity)),
+            ref other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn handles_are_released_when_the_client_moves_away() {
+        let (own, other) = (new_entity(), new_entity());
+        let mut handles = EntityHandles::new(own.id, &MapDefinition::new(16, 8));
+        let mut messages = vec![];
+        handles.messages_for(&move_event(&own, Point(31, 20), Some(Point(31, 20))), &mut messages);
+        handles.messages_for(&move_event(&other, Point(0, 20), Some(Point(1, 20))), &mut messages);
+
+        messages.clear();
+        handles.messages_for(&move_event(&own, Point(31, 20), Some(Point(32, 20))), &mut messages);
+
+        assert_last_released(1, &messages);
+        assert_eq!(1, handles.len());
+    }
+
+    #[test]
+    pub fn handles_are_released_when_entities_leave_the_map() {
+        let (own, other) = (new_entity(), new_entity());
+        let mut handles = EntityHandles::new(own.id, &MapDefinition::new(16, 8));
+        let mut messages = vec![];
+        handles.messages_for(&move_event(&own, Point(20, 20), Some(Point(21, 20))), &mut messages);
+
+        messages.clear();
+        handles.messages_for(&move_event(&other, Point(22, 20), None), &mut messages);
+
+        assert_last_released(1, &messages);
+        assert_eq!(1, handles.len());
+    }
+
+    #[test]
+    pub fn clients_rebuild_the_events() {
+        let (own, other) = (new_entity(), new_entity());
+        let mut handles = EntityHandles::new(own.id, &MapDefinition::new(16, 8));
+        let mut introduced_entities = IntroducedEntities::new();
+        let events = [
+            move_event(&own, Point(20, 20), Some(Point(21, 20))),
+            move_event(&other, Point(46, 20), Some(Point(47, 20))),
+            move_event(&other, Point(47, 20), Some(Point(48, 20))),
+        ];
+
+        let mut received_events = vec![];
+        for event in events.iter() {
+            let mut messages = vec![];
+            handles.messages_for(event, &mut messages);
+
+            for message in messages {
+                match message {
+                    Message::Introduction(handle, entity) => introduced_entities.introduce(handle, entity),
+                    Message::HandleEvent(event) => received_events.push(introduced_entities.event_for(event).unwrap()),
+                    Message::HandleReleased(handle) => introduced_entities.release(handle),
+                    other => panic!("Unexpected message: {:?}", other),
+                }
+            }
+        }
+
+        for (event, received_event) in events.iter().zip(received_events.iter()) {
+            assert_eq!(event.acting_entity, received_event.acting_entity);
+            assert_eq!(event.to, received_event.to);
+        }
+        assert_eq!(1, introduced_entities.len());
+        assert_eq!(Err(HandleError::UnknownHandle(1)), introduced_entities.event_for(HandleEvent{
+            handle: 1,
+            from: Point(0, 0),
+            to: None,
+            last_state_update: Timestamp::new(),
+            is_a_move: false,
+        }).map(|event| event.to));
+    }
+
+    #[test]
+    pub fn handle_events_are_smaller() {
+        let entity = new_entity();
+        let event = move_event(&entity, Point(20, 20), Some(Point(21, 20)));
+        let mut handles = EntityHandles::new(entity.id, &MapDefinition::new(16, 8));
+        let mut messages = vec![];
+        handles.messages_for(&event, &mut messages);
+
+        let event_length = bincode::serialize(&Message::Event(event)).unwrap().len();
+        let handle_event_length = bincode::serialize(&messages[1]).unwrap().len();
+        assert_eq!(event_length - 20, handle_event_length);
+    }
+
+    fn assert_last_released(expected_handle: u32, messages: &[Message]) {
+        match messages.last() {
+            Some(&Message::HandleReleased(handle)) => assert_eq!(expected_handle, handle),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    fn new_entity() -> DemoEntity {
+        DemoEntity{
+            id: Uuid::new_v4(),
+            last_state_update: Timestamp::new(),
+        }
+    }
+
+    fn move_event(entity: &DemoEntity, from: Point, to: Option<Point>) -> Event {
+        SpatialEvent{
+            is_a_move: to.is_some(),
+            from,
+            to,
+            acting_entity: entity.clone(),
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! The first frames of a connection: the client says hello with its protocol version, the formats
+//! it reads and the features it supports, and the server welcomes it with what it chose, or
+//! rejects it.
+//!
+//! The handshake frames are always written with bincode, whatever the format chosen. Then both
+//! sides switch to a `Message` codec in that format, see `switch_codec`.
+
+use codec::LengthFieldBasedCodec;
+use format::AnyFormat;
+use format::Bincode;
+use format::Format;
+use position::PositionEncoding;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use tokio::codec::Decoder;
+use tokio::codec::Encoder;
+use tokio::codec::Framed;
+use tokio::codec::FramedParts;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A hello is a few bytes, anything longer is not one.
+const MAX_HANDSHAKE_FRAME_LENGTH: usize = 1024;
+
+/// The variants keep their order across versions, so that any hello can be answered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Handshake {
+    Hello(Hello),
+    Welcome(Welcome),
+    Rejected(Rejection),
+}
+
+/// The version comes first, so that the hello of a later version reads far enough to be rejected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    /// The formats the client reads, the one it would rather use first.
+    pub formats: Vec<AnyFormat>,
+    pub features: Features,
+}
+
+impl Hello {
+    pub fn new(formats: Vec<AnyFormat>, features: Features) -> Hello {
+        Hello{
+            version: PROTOCOL_VERSION,
+            formats,
+            features,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// Longer frames may be compressed, see `codec::Compression`.
+    pub compression: bool,
+    /// Events may be packed into `Message::Events` frames, see `EventBatcher`.
+    pub batching: bool,
+    /// The encoding of the positions of the events. Plain positions are always supported.
+    pub position_encoding: PositionEncoding,
+    /// Events may be sent as delta snapshots, see `delta`.
+    pub delta_snapshots: bool,
+    /// Entities may be named by handles, see `handle`.
+    pub entity_handles: bool,
+}
+
+impl Features {
+    /// The features supported by both sides.
+    pub fn intersection(&self, other: &Features) -> Features {
+        Features{
+            compression: self.compression && other.compression,
+            batching: self.batching && other.batching,
+            position_encoding: if self.position_encoding == other.position_encoding {
+                self.position_encoding
+            } else {
+                PositionEncoding::Plain
+            },
+            delta_snapshots: self.delta_snapshots && other.delta_snapshots,
+            entity_handles: self.entity_handles && other.entity_handles,
+        }
+    }
+}
+
+/// What the server chose for the connection.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Welcome {
+    pub version: u32,
+    pub format: AnyFormat,
+    pub features: Features,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    UnsupportedVersion{version: u32, supported: u32},
+    NoCommonFormat{supported: Vec<AnyFormat>},
+    /// The first frame of the client was not a hello.
+    ExpectedHello,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Rejection::UnsupportedVersion{version, supported} =>
+                write!(f, "Protocol version {} is not supported, expected version {}", version, supported),
+            Rejection::NoCommonFormat{ref supported} => write!(f, "No common format, supported formats: {:?}", supported),
+            Rejection::ExpectedHello => write!(f, "Expected a hello"),
+        }
+    }
+}
+
+impl Error for Rejection {}
+
+/// What the server supports, to answer the hellos.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offer {
+    pub formats: Vec<AnyFormat>,
+    pub features: Features,
+}
+
+impl Offer {
+    /// Chooses the first format of the client the server supports, and the features both support.
+    /// Events are only batched in the formats packing them. Snapshots name the entities by their
+    /// ids, and both snapshots and handle events hold plain positions: delta snapshots win over the
+    /// other two.
+    pub fn answer(&self, hello: &Hello) -> Result<Welcome, Rejection> {
+        if hello.version != PROTOCOL_VERSION {
+            return Err(Rejection::UnsupportedVersion{
+                version: hello.version,
+                supported: PROTOCOL_VERSION,
+            });
+        }
+
+        let format = hello.formats.iter()
+            .find(|format| self.formats.contains(format))
+            .ok_or_else(|| Rejection::NoCommonFormat{supported: self.formats.clone()})?;
+
+        let mut features = self.features.intersection(&hello.features);
+        features.batching &= format.packs_events();
+        if features.delta_snapshots {
+            features.entity_handles = false;
+        }
+        if features.delta_snapshots || features.entity_handles {
+            features.position_encoding = PositionEncoding::Plain;
+        }
+
+        Ok(Welcome{
+            version: PROTOCOL_VERSION,
+            format: *format,
+            features,
+        })
+    }
+}
+
+pub fn codec() -> LengthFieldBasedCodec<Handshake, Bincode> {
+    LengthFieldBasedCodec::new(Bincode).with_max_frame_length(MAX_HANDSHAKE_FRAME_LENGTH)
+}
+
+/// Keeps the bytes read or waiting to be written, the frames following the handshake included.
+pub fn switch_codec<T, U, V>(framed: Framed<T, U>, codec: V) -> Framed<T, V>
+    where
+        U: Decoder + Encoder,
+        V: Decoder + Encoder,
+{
+    let parts = framed.into_parts();
+    let mut switched_parts = FramedParts::new(parts.io, codec);
+    switched_parts.read_buf = parts.read_buf;
+    switched_parts.write_buf = parts.write_buf;
+
+    Framed::from_parts(switched_parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use codec::EncodedFrame;
+    use futures::Future;
+    use futures::Stream;
+    use message::Message;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn matching_hellos_are_welcome() {
+        let hello = Hello::new(vec![AnyFormat::Json, AnyFormat::Bincode], Features{
+            compression: true,
+            batching: true,
+            position_encoding: PositionEncoding::Zoned,
+            delta_snapshots: false,
+            entity_handles: true,
+        });
+
+        assert_eq!(Ok(Welcome{
+            version: PROTOCOL_VERSION,
+            format: AnyFormat::Bincode,
+            features: Features{
+                batching: true,
+                entity_handles: true,
+                ..Features::default()
+            },
+        }), offer().answer(&hello));
+    }
+
+    #[test]
+    pub fn zoned_positions_are_only_chosen_by_both_sides() {
+        let offer = Offer{
+            formats: vec![AnyFormat::Bincode],
+            features: Features{
+                position_encoding: PositionEncoding::Zoned,
+                ..Features::default()
+            },
+        };
+        let zoned = Features{
+            position_encoding: PositionEncoding::Zoned,
+            ..Features::default()
+        };
+
+        assert_eq!(PositionEncoding::Zoned, offer.answer(&Hello::new(vec![AnyFormat::Bincode], zoned)).unwrap().features.position_encoding);
+        assert_eq!(PositionEncoding::Plain, offer.answer(&Hello::new(vec![AnyFormat::Bincode], Features::default())).unwrap().features.position_encoding);
+    }
+
+    #[test]
+    pub fn delta_snapshots_win_over_handles_and_zoned_positions() {
+        let everything = Features{
+            compression: true,
+            batching: true,
+            position_encoding: PositionEncoding::Zoned,
+            delta_snapshots: true,
+            entity_handles: true,
+        };
+        let offer = Offer{
+            formats: vec![AnyFormat::Bincode],
+            features: everything,
+        };
+
+        let features = offer.answer(&Hello::new(vec![AnyFormat::Bincode], everything)).unwrap().features;
+        assert_eq!(Features{
+            delta_snapshots: true,
+            entity_handles: false,
+            position_encoding: PositionEncoding::Plain,
+            ..everything
+        }, features);
+
+        let without_snapshots = Features{
+            delta_snapshots: false,
+            ..everything
+        };
+        let features = offer.answer(&Hello::new(vec![AnyFormat::Bincode], without_snapshots)).unwrap().features;
+        assert_eq!(Features{
+            position_encoding: PositionEncoding::Plain,
+            ..without_snapshots
+        }, features);
+    }
+
+    #[test]
+    pub fn the_format_the_client_would_rather_use_is_chosen() {
+        let offer = Offer{
+            formats: vec![AnyFormat::Bincode, AnyFormat::MessagePack],
+            features: Features::default(),
+        };
+        let hello = Hello::new(vec![AnyFormat::Json, AnyFormat::MessagePack, AnyFormat::Bincode], Features::default());
+
+        assert_eq!(AnyFormat::MessagePack, offer.answer(&hello).unwrap().format);
+    }
+
+    #[test]
+    pub fn events_are_only_batched_in_formats_packing_them() {
+        let offer = Offer{
+            formats: AnyFormat::ALL.to_vec(),
+            features: Features{
+                batching: true,
+                ..Features::default()
+            },
+        };
+        let batching = Features{
+            batching: true,
+            ..Features::default()
+        };
+
+        assert!(offer.answer(&Hello::new(vec![AnyFormat::Bincode], batching)).unwrap().features.batching);
+        assert!(!offer.answer(&Hello::new(vec![AnyFormat::Json], batching)).unwrap().features.batching);
+        assert!(!offer.answer(&Hello::new(vec![AnyFormat::MessagePack], batching)).unwrap().features.batching);
+    }
+
+    #[test]
+    pub fn other_versions_are_rejected() {
+        for version in [0, PROTOCOL_VERSION + 1].iter() {
+            let mut hello = Hello::new(vec![AnyFormat::Bincode], Features::default());
+            hello.version = *version;
+
+            assert_eq!(Err(Rejection::UnsupportedVersion{version: *version, supported: PROTOCOL_VERSION}), offer().answer(&hello));
+        }
+    }
+
+    #[test]
+    pub fn the_hello_of_a_later_version_reads_far_enough_to_be_rejected() {
+        #[derive(Serialize)]
+        enum LaterHandshake {
+            Hello{version: u32, formats: Vec<AnyFormat>, features: Features, extensions: Vec<String>},
+        }
+        let later_hello = LaterHandshake::Hello{
+            version: PROTOCOL_VERSION + 1,
+            formats: vec![AnyFormat::Bincode],
+            features: Features::default(),
+            extensions: vec!["anything".to_owned()],
+        };
+        let frame = EncodedFrame::encode(&later_hello).unwrap();
+
+        let mut buf = BytesMut::from(frame.as_ref());
+        match codec().decode(&mut buf).unwrap() {
+            Some(Handshake::Hello(hello)) => assert_eq!(
+                Err(Rejection::UnsupportedVersion{version: PROTOCOL_VERSION + 1, supported: PROTOCOL_VERSION}),
+                offer().answer(&hello)
+            ),
+            other => panic!("Unexpected handshake: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn hellos_without_a_common_format_are_rejected() {
+        let hello = Hello::new(vec![AnyFormat::Json], Features::default());
+
+        assert_eq!(Err(Rejection::NoCommonFormat{supported: vec![AnyFormat::Bincode]}), offer().answer(&hello));
+        assert_eq!(Err(Rejection::NoCommonFormat{supported: vec![AnyFormat::Bincode]}), offer().answer(&Hello::new(vec![], Features::default())));
+    }
+
+    #[test]
+    pub fn rejections_are_read_by_the_client() {
+        let rejection = offer().answer(&Hello::new(vec![AnyFormat::Json], Features::default())).unwrap_err();
+        let mut buf = BytesMut::new();
+        codec().encode(Handshake::Rejected(rejection.clone()), &mut buf).unwrap();
+
+        assert_eq!(Some(Handshake::Rejected(rejection)), codec().decode(&mut buf).unwrap());
+    }
+
+    #[test]
+    pub fn frames_following_the_handshake_survive_the_switch() {
+        let mut bytes = BytesMut::new();
+        codec().encode(Handshake::Hello(Hello::new(vec![AnyFormat::Json], Features::default())), &mut bytes).unwrap();
+        bytes.extend_from_slice(EncodedFrame::encode_with(&Message::SnapshotAck(3), &AnyFormat::Json).unwrap().as_ref());
+
+        let framed = codec().framed(Cursor::new(bytes.to_vec()));
+        let (hello, framed) = framed.into_future().wait().map_err(|(err, _)| err).unwrap();
+        match hello {
+            Some(Handshake::Hello(_)) => {},
+            other => panic!("Unexpected handshake: {:?}", other),
+        }
+
+        let framed = switch_codec(framed, LengthFieldBasedCodec::<Message, AnyFormat>::new(AnyFormat::Json));
+        match framed.into_future().wait().map_err(|(err, _)| err).unwrap().0 {
+            Some(Message::SnapshotAck(3)) => {},
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    fn offer() -> Offer {
+        Offer{
+            formats: vec![AnyFormat::Bincode],
+            features: Features{
+                batching: true,
+                entity_handles: true,
+                ..Features::default()
+            },
+        }
+    }
+}
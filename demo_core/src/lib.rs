@@ -1,14 +1,26 @@
 extern crate bincode;
 extern crate bytes;
 extern crate core;
+extern crate flate2;
 extern crate futures;
+#[macro_use] extern crate log;
+#[cfg(test)] extern crate proptest;
+extern crate rmp_serde;
 extern crate serde;
 #[macro_use]extern crate serde_derive;
+extern crate serde_json;
 extern crate spatiub;
 extern crate tokio;
 extern crate tokio_codec;
 extern crate uuid;
 
 pub mod codec;
+pub mod delta;
 pub mod entity;
-pub mod message;
\ No newline at end of file
+pub mod fan_out;
+pub mod format;
+pub mod handle;
+pub mod handshake;
+pub mod message;
+pub mod position;
+pub mod scheduler;
\ No newline at end of file
@@ -1,8 +1,304 @@
+use bytes::{BufMut, BytesMut, ByteOrder, LittleEndian};
+use codec::EncodedFrame;
+use delta::Snapshot;
 use entity::DemoEntity;
+use format::Format;
+use handle::HandleEvent;
+use position::ZonedEvent;
+use spatiub::spatial::MapDefinition;
 use spatiub::spatial::SpatialEvent;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// Bincode writes the index of the variant, then its content. Sequences start with their length.
+/// The tags follow the order of the variants of `Message`, see `the_tags_match_the_variants`.
+const LENGTH_FIELD_LENGTH: usize = 4;
+const TAG_LENGTH: usize = 4;
+const SEQUENCE_LENGTH: usize = 8;
+const EVENT_TAG: u32 = 1;
+const EVENTS_TAG: u32 = 2;
+const ZONED_EVENT_TAG: u32 = 5;
+const ZONED_EVENTS_TAG: u32 = 6;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message{
-    ConnectionAck(DemoEntity),
-    Event(SpatialEvent<DemoEntity>)
-}
\ No newline at end of file
+    /// The entity of the client, and the map it moves on.
+    ConnectionAck(DemoEntity, MapDefinition),
+    Event(SpatialEvent<DemoEntity>),
+    /// Several events in one frame, see `EventBatcher`.
+    Events(Vec<SpatialEvent<DemoEntity>>),
+    /// The events of a tick, as deltas against what the client acknowledged.
+    Snapshot(Snapshot),
+    /// Sent by clients, with the sequence of the last snapshot received.
+    SnapshotAck(u32),
+    /// An event with zoned positions, see `position::ZonedPositions`.
+    ZonedEvent(ZonedEvent),
+    /// Several zoned events in one frame, see `EventBatcher`.
+    ZonedEvents(Vec<ZonedEvent>),
+    /// Gives a handle to an entity, before the events naming it by its handle. See `handle`.
+    Introduction(u32, DemoEntity),
+    HandleEvent(HandleEvent),
+    /// The entity left the view of the client, the handle may be given to another one.
+    HandleReleased(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchError {
+    /// The frame given to `EventBatcher::push` does not hold an event.
+    NotAnEvent{tag: u32},
+}
+
+impl Display for BatchError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            BatchError::NotAnEvent{tag} => write!(f, "Cannot batch the message of tag {}", tag),
+        }
+    }
+}
+
+impl Error for BatchError {}
+
+/// Packs frames of `Message::Event` into frames of `Message::Events`, without encoding the events
+/// again: the content of each event frame is copied as is after the header of the batch.
+/// A batch of a single event is left as a `Message::Event` frame.
+///
+/// Frames of `Message::ZonedEvent` are packed the same way into `Message::ZonedEvents`, a frame
+/// of the other kind starting a new batch.
+///
+/// Only bincode frames can be packed, the frames of other formats are left as is.
+pub struct EventBatcher {
+    max_frame_length: usize,
+    packs_events: bool,
+    frames: Vec<EncodedFrame>,
+    batch_length: usize,
+    batch_tag: u32,
+}
+
+impl EventBatcher {
+    /// A frame holds at least one event, whatever its length.
+    pub fn new(max_frame_length: usize) -> EventBatcher {
+        EventBatcher{
+            max_frame_length,
+            packs_events: true,
+            frames: vec![],
+            batch_length: 0,
+            batch_tag: EVENTS_TAG,
+        }
+    }
+
+    /// A batcher for the frames encoded in the given format.
+    pub fn for_format<F: Format>(max_frame_length: usize, format: &F) -> EventBatcher {
+        EventBatcher{
+            packs_events: format.packs_events(),
+            ..EventBatcher::new(max_frame_length)
+        }
+    }
+
+    /// Adds the frame of a `Message::Event` or a `Message::ZonedEvent` to the batch.
+    /// Returns the batch so far if the event does not fit in it, the event starting the next one.
+    /// The frame of any other message is left out of the batch.
+    pub fn push(&mut self, frame: EncodedFrame) -> Result<Option<EncodedFrame>, BatchError> {
+        if !self.packs_events {
+            let previous_frame = self.frames.pop();
+            self.frames.push(frame);
+            return Ok(previous_frame);
+        }
+
+        let batch_tag = match LittleEndian::read_u32(&frame.as_ref()[LENGTH_FIELD_LENGTH..]) {
+            EVENT_TAG => EVENTS_TAG,
+            ZONED_EVENT_TAG => ZONED_EVENTS_TAG,
+            tag => return Err(BatchError::NotAnEvent{tag}),
+        };
+
+        let event_length = frame.len() - LENGTH_FIELD_LENGTH - TAG_LENGTH;
+        let full_batch = if !self.frames.is_empty()
+            && (self.batch_length + event_length > self.max_frame_length || self.batch_tag != batch_tag) {
+            self.finish()
+        } else {
+            None
+        };
+
+        if self.frames.is_empty() {
+            self.batch_length = LENGTH_FIELD_LENGTH + TAG_LENGTH + SEQUENCE_LENGTH;
+            self.batch_tag = batch_tag;
+        }
+        self.batch_length += event_length;
+        self.frames.push(frame);
+
+        Ok(full_batch)
+    }
+
+    /// Takes the batch so far, if any.
+    pub fn finish(&mut self) -> Option<EncodedFrame> {
+        if self.frames.len() < 2 {
+            return self.frames.pop();
+        }
+
+        let mut buf = BytesMut::with_capacity(self.batch_length);
+        buf.put_u32_be((self.batch_length - LENGTH_FIELD_LENGTH) as u32);
+        buf.put_u32_le(self.batch_tag);
+        buf.put_u64_le(self.frames.len() as u64);
+        for frame in self.frames.drain(..) {
+            buf.extend_from_slice(&frame.as_ref()[LENGTH_FIELD_LENGTH + TAG_LENGTH..]);
+        }
+
+        Some(EncodedFrame::from_bytes(buf.freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use codec::LengthFieldBasedCodec;
+    use entity::Timestamp;
+    use format::Bincode;
+    use format::Json;
+    use position::ZonedPositions;
+    use spatiub::spatial::MapDefinition;
+    use spatiub::spatial::Point;
+    use tokio::codec::Decoder;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn batches_are_encoded_like_events_messages() {
+        let events: Vec<_> = (0..3).map(new_event).collect();
+        let mut batcher = EventBatcher::new(usize::MAX);
+
+        for event in events.iter() {
+            assert_eq!(Ok(None), batcher.push(event_frame(event)));
+        }
+
+        let expected = EncodedFrame::encode(&Message::Events(events)).unwrap();
+        assert_eq!(Some(expected), batcher.finish());
+        assert_eq!(None, batcher.finish());
+    }
+
+    #[test]
+    pub fn batches_can_be_decoded() {
+        let events: Vec<_> = (0..3).map(new_event).collect();
+        let mut batcher = EventBatcher::new(usize::MAX);
+        for event in events.iter() {
+            batcher.push(event_frame(event)).unwrap();
+        }
+
+        let mut buf = BytesMut::from(batcher.finish().unwrap().as_ref());
+        let mut codec = LengthFieldBasedCodec::new(Bincode);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::Events(decoded_events)) => {
+                let destinations: Vec<_> = decoded_events.into_iter().map(|event| event.to).collect();
+                let expected: Vec<_> = events.into_iter().map(|event| event.to).collect();
+                assert_eq!(expected, destinations);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn batches_do_not_exceed_the_max_frame_length() {
+        let frame_length = event_frame(&new_event(0)).len();
+        // The header of a batch is as long as the headers of two events.
+        let batch_length = frame_length * 2;
+        let mut batcher = EventBatcher::new(batch_length);
+        let mut batches = vec![];
+
+        for x in 0..5 {
+            batches.extend(batcher.push(event_frame(&new_event(x))).unwrap());
+        }
+        batches.extend(batcher.finish());
+
+        let lengths: Vec<_> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(vec![batch_length, batch_length, frame_length], lengths);
+    }
+
+    #[test]
+    pub fn lone_events_are_left_as_is() {
+        let frame = event_frame(&new_event(0));
+        let mut batcher = EventBatcher::new(0);
+
+        assert_eq!(Ok(None), batcher.push(frame.clone()));
+        assert_eq!(Ok(Some(frame.clone())), batcher.push(event_frame(&new_event(1))));
+    }
+
+    #[test]
+    pub fn zoned_events_are_batched_apart() {
+        let positions = ZonedPositions::new(&MapDefinition::new(16, 4)).unwrap();
+        let events: Vec<_> = (0..4).map(new_event).collect();
+        let zoned_events: Vec<_> = events.iter()
+            .map(|event| positions.encode_event(event).unwrap())
+            .collect();
+        let mut batcher = EventBatcher::new(usize::MAX);
+        let mut batches = vec![];
+
+        batches.extend(batcher.push(event_frame(&events[0])).unwrap());
+        batches.extend(batcher.push(event_frame(&events[1])).unwrap());
+        for zoned_event in zoned_events[2..].iter() {
+            batches.extend(batcher.push(EncodedFrame::encode(&Message::ZonedEvent(zoned_event.clone())).unwrap()).unwrap());
+        }
+        batches.extend(batcher.finish());
+
+        let expected = vec![
+            EncodedFrame::encode(&Message::Events(events[..2].to_vec())).unwrap(),
+            EncodedFrame::encode(&Message::ZonedEvents(zoned_events[2..].to_vec())).unwrap(),
+        ];
+        assert_eq!(expected, batches);
+    }
+
+    #[test]
+    pub fn frames_of_other_formats_are_left_as_is() {
+        let frames: Vec<_> = (0..3)
+            .map(|x| EncodedFrame::encode_with(&Message::Event(new_event(x)), &Json).unwrap())
+            .collect();
+        let mut batcher = EventBatcher::for_format(usize::MAX, &Json);
+        let mut batches = vec![];
+
+        for frame in frames.iter() {
+            batches.extend(batcher.push(frame.clone()).unwrap());
+        }
+        batches.extend(batcher.finish());
+
+        assert_eq!(frames, batches);
+    }
+
+    #[test]
+    pub fn the_tags_match_the_variants() {
+        let positions = ZonedPositions::new(&MapDefinition::new(16, 4)).unwrap();
+        let event = new_event(0);
+        let zoned_event = positions.encode_event(&event).unwrap();
+        let tag = |message: &Message| LittleEndian::read_u32(&bincode::serialize(message).unwrap());
+
+        assert_eq!(EVENT_TAG, tag(&Message::Event(event.clone())));
+        assert_eq!(EVENTS_TAG, tag(&Message::Events(vec![event])));
+        assert_eq!(ZONED_EVENT_TAG, tag(&Message::ZonedEvent(zoned_event.clone())));
+        assert_eq!(ZONED_EVENTS_TAG, tag(&Message::ZonedEvents(vec![zoned_event])));
+    }
+
+    #[test]
+    pub fn other_messages_are_not_batched() {
+        let mut batcher = EventBatcher::new(usize::MAX);
+        batcher.push(event_frame(&new_event(0))).unwrap();
+
+        let ack_tag = LittleEndian::read_u32(&bincode::serialize(&Message::SnapshotAck(1)).unwrap());
+        assert_eq!(Err(BatchError::NotAnEvent{tag: ack_tag}),
+                   batcher.push(EncodedFrame::encode(&Message::SnapshotAck(1)).unwrap()));
+        assert_eq!(Some(event_frame(&new_event(0)).len()), batcher.finish().map(|frame| frame.len()));
+    }
+
+    fn new_event(x: usize) -> SpatialEvent<DemoEntity> {
+        SpatialEvent{
+            from: Point(x, 0),
+            to: Some(Point(x + 1, 0)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        }
+    }
+
+    fn event_frame(event: &SpatialEvent<DemoEntity>) -> EncodedFrame {
+        EncodedFrame::encode(&Message::Event(event.clone())).unwrap()
+    }
+}
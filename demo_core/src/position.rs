@@ -0,0 +1,228 @@
+//! A compact wire encoding of the positions: the index of the zone of the point, and its offset
+//! inside the zone. Bincode writes a `Point` as two 8 byte integers, a `ZonedPoint` takes 6 bytes.
+//!
+//! Clients ask for the encoding in their hello, see `handshake::Features`. The server keeps
+//! sending plain positions if the map does not fit, events of both encodings having their own
+//! messages.
+
+use entity::DemoEntity;
+use spatiub::spatial::MapDefinition;
+use spatiub::spatial::Point;
+use spatiub::spatial::SpatialEvent;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+type Event = SpatialEvent<DemoEntity>;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// Positions are written as points.
+    #[default]
+    Plain,
+    /// Positions are written as zoned points, see `ZonedPositions`.
+    Zoned,
+}
+
+/// A position as the index of its zone, row by row, and its offset inside the zone.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZonedPoint {
+    pub zone: u32,
+    pub offset: (u8, u8),
+}
+
+/// `SpatialEvent`, with zoned points.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ZonedEvent {
+    pub from: ZonedPoint,
+    pub to: Option<ZonedPoint>,
+    pub acting_entity: DemoEntity,
+    pub is_a_move: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PositionError {
+    /// The zone is outside of the map.
+    UnknownZone(u32),
+    /// The offset is outside of the zone.
+    InvalidOffset(u8, u8),
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            PositionError::UnknownZone(zone) => write!(f, "Zone {} is outside of the map", zone),
+            PositionError::InvalidOffset(x, y) => write!(f, "Offset ({}, {}) is outside of the zone", x, y),
+        }
+    }
+}
+
+impl Error for PositionError {}
+
+/// Converts points to zoned points and back for a given map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZonedPositions {
+    zone_width: usize,
+    map_width_in_zones: usize,
+}
+
+impl ZonedPositions {
+    /// `None` if the offsets do not fit in a byte, or the zone indexes in 4 bytes.
+    pub fn new(map: &MapDefinition) -> Option<ZonedPositions> {
+        let zone_width = map.zone_width();
+        let map_width_in_zones = map.map_width_in_zones();
+        let number_of_zones = map_width_in_zones.checked_mul(map_width_in_zones)?;
+
+        if zone_width == 0 || zone_width > 256 || number_of_zones > u32::MAX as usize + 1 {
+            return None;
+        }
+
+        Some(ZonedPositions{
+            zone_width,
+            map_width_in_zones,
+        })
+    }
+
+    /// `None` if the point is outside of the map.
+    pub fn encode(&self, point: &Point) -> Option<ZonedPoint> {
+        let (zone_x, zone_y) = (point.0 / self.zone_width, point.1 / self.zone_width);
+        if zone_x >= self.map_width_in_zones || zone_y >= self.map_width_in_zones {
+            return None;
+        }
+
+        Some(ZonedPoint{
+            zone: (zone_y * self.map_width_in_zones + zone_x) as u32,
+            offset: ((point.0 % self.zone_width) as u8, (point.1 % self.zone_width) as u8),
+        })
+    }
+
+    pub fn decode(&self, point: &ZonedPoint) -> Result<Point, PositionError> {
+        let zone = point.zone as usize;
+        if zone >= self.map_width_in_zones * self.map_width_in_zones {
+            return Err(PositionError::UnknownZone(point.zone));
+        }
+
+        let (offset_x, offset_y) = (point.offset.0 as usize, point.offset.1 as usize);
+        if offset_x >= self.zone_width || offset_y >= self.zone_width {
+            return Err(PositionError::InvalidOffset(point.offset.0, point.offset.1));
+        }
+
+        Ok(Point(
+            zone % self.map_width_in_zones * self.zone_width + offset_x,
+            zone / self.map_width_in_zones * self.zone_width + offset_y,
+        ))
+    }
+
+    /// `None` if a position of the event is outside of the map.
+    pub fn encode_event(&self, event: &Event) -> Option<ZonedEvent> {
+        let to = match event.to {
+            Some(ref to) => Some(self.encode(to)?),
+            None => None,
+        };
+
+        Some(ZonedEvent{
+            from: self.encode(&event.from)?,
+            to,
+            acting_entity: event.acting_entity.clone(),
+            is_a_move: event.is_a_move,
+        })
+    }
+
+    pub fn decode_event(&self, event: ZonedEvent) -> Result<Event, PositionError> {
+        let to = match event.to {
+            Some(ref to) => Some(self.decode(to)?),
+            None => None,
+        };
+
+        Ok(SpatialEvent{
+            from: self.decode(&event.from)?,
+            to,
+            acting_entity: event.acting_entity,
+            is_a_move: event.is_a_move,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use entity::Timestamp;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn edge_of_map_points_round_trip() {
+        let map = MapDefinition::new(16, 8);
+        let positions = ZonedPositions::new(&map).unwrap();
+        let max = 16 * 8 - 1;
+
+        for point in [Point(0, 0), Point(max, 0), Point(0, max), Point(max, max), Point(15, 16), Point(16, 15)].iter() {
+            let zoned_point = positions.encode(point).unwrap();
+            assert_eq!(Ok(point.clone()), positions.decode(&zoned_point));
+        }
+        assert_eq!(Some(ZonedPoint{zone: 63, offset: (15, 15)}), positions.encode(&Point(max, max)));
+    }
+
+    #[test]
+    pub fn every_point_of_a_small_map_round_trips() {
+        let map = MapDefinition::new(4, 3);
+        let positions = ZonedPositions::new(&map).unwrap();
+
+        for x in 0..12 {
+            for y in 0..12 {
+                let zoned_point = positions.encode(&Point(x, y)).unwrap();
+                assert_eq!(Ok(Point(x, y)), positions.decode(&zoned_point));
+            }
+        }
+    }
+
+    #[test]
+    pub fn widest_zones_use_the_whole_offset() {
+        let map = MapDefinition::new(256, 2);
+        let positions = ZonedPositions::new(&map).unwrap();
+
+        let zoned_point = positions.encode(&Point(511, 255)).unwrap();
+        assert_eq!(ZonedPoint{zone: 1, offset: (255, 255)}, zoned_point);
+        assert_eq!(Ok(Point(511, 255)), positions.decode(&zoned_point));
+
+        assert_eq!(None, ZonedPositions::new(&MapDefinition::new(257, 2)));
+    }
+
+    #[test]
+    pub fn points_outside_of_the_map_are_rejected() {
+        let map = MapDefinition::new(16, 8);
+        let positions = ZonedPositions::new(&map).unwrap();
+
+        assert_eq!(None, positions.encode(&Point(128, 0)));
+        assert_eq!(None, positions.encode(&Point(0, 128)));
+        assert_eq!(Err(PositionError::UnknownZone(64)), positions.decode(&ZonedPoint{zone: 64, offset: (0, 0)}));
+        assert_eq!(Err(PositionError::InvalidOffset(16, 0)), positions.decode(&ZonedPoint{zone: 0, offset: (16, 0)}));
+    }
+
+    #[test]
+    pub fn zoned_events_are_smaller() {
+        let map = MapDefinition::new(16, 1024 * 4);
+        let positions = ZonedPositions::new(&map).unwrap();
+        let max = 16 * 1024 * 4 - 1;
+        let event = SpatialEvent{
+            from: Point(max, max - 1),
+            to: Some(Point(max, max)),
+            acting_entity: DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            },
+            is_a_move: true,
+        };
+
+        let zoned_event = positions.encode_event(&event).unwrap();
+        let event_length = bincode::serialize(&event).unwrap().len();
+        let zoned_event_length = bincode::serialize(&zoned_event).unwrap().len();
+        assert_eq!(event_length - 20, zoned_event_length);
+
+        let decoded_event = positions.decode_event(zoned_event).unwrap();
+        assert_eq!(event.from, decoded_event.from);
+        assert_eq!(event.to, decoded_event.to);
+        assert_eq!(event.acting_entity, decoded_event.acting_entity);
+    }
+}
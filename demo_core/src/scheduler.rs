@@ -0,0 +1,289 @@
+use fan_out::OutgoingFrame;
+use spatiub::spatial::Point;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// What a connection may be sent per tick. A tick sends at least one frame, whatever its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub tick: Duration,
+    /// Counts the frames of the events, so subscribers without frames are not limited by it.
+    pub max_bytes: Option<usize>,
+    pub max_messages: Option<usize>,
+}
+
+/// What a tick sent and held back.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TickReport {
+    pub frames_sent: usize,
+    pub bytes_sent: usize,
+    /// Frames replaced by a more recent one of the same entity since the previous tick.
+    pub frames_conflated: usize,
+    /// Frames left for the next ticks.
+    pub frames_deferred: usize,
+}
+
+/// Sends the frames of a connection within its budget, the most important first: the events of its
+/// own entity, then those of the nearest entities. A frame waiting for the next tick is replaced
+/// by any newer frame of the same entity, and its distance is halved at each tick it waits, so
+/// that far entities are late but not starved.
+pub struct Scheduler {
+    entity_id: Uuid,
+    position: Option<Point>,
+    budget: Budget,
+    pending: HashMap<Uuid, PendingFrame>,
+    frames_conflated: usize,
+    /// Reused by each tick, to avoid allocating.
+    ranking: Vec<(usize, Uuid)>,
+}
+
+struct PendingFrame {
+    outgoing_frame: OutgoingFrame,
+    ticks_waited: u32,
+}
+
+impl Scheduler {
+    pub fn new(entity_id: Uuid, budget: Budget) -> Scheduler {
+        Scheduler{
+            entity_id,
+            position: None,
+            budget,
+            pending: HashMap::new(),
+            frames_conflated: 0,
+            ranking: vec![],
+        }
+    }
+
+    pub fn push(&mut self, outgoing_frame: OutgoingFrame) {
+        if outgoing_frame.entity_id == self.entity_id && outgoing_frame.position.is_some() {
+            self.position.clone_from(&outgoing_frame.position);
+        }
+
+        let entity_id = outgoing_frame.entity_id;
+        let ticks_waited = match self.pending.remove(&entity_id) {
+            Some(conflated) => {
+                self.frames_conflated += 1;
+                conflated.ticks_waited
+            },
+            None => 0,
+        };
+
+        self.pending.insert(entity_id, PendingFrame{
+            outgoing_frame,
+            ticks_waited,
+        });
+    }
+
+    /// Takes the frames to send during this tick, in the order they must be sent.
+    pub fn tick(&mut self, frames: &mut Vec<OutgoingFrame>) -> TickReport {
+        self.ranking.clear();
+        for (entity_id, pending_frame) in self.pending.iter() {
+            self.ranking.push((self.rank(pending_frame), *entity_id));
+        }
+        self.ranking.sort_unstable_by_key(|&(rank, _)| rank);
+
+        let mut report = TickReport{
+            frames_conflated: self.frames_conflated,
+            ..TickReport::default()
+        };
+        self.frames_conflated = 0;
+
+        for &(_rank, ref entity_id) in self.ranking.iter() {
+            let frame_length = self.pending[entity_id].outgoing_frame.frame.as_ref().map_or(0, |frame| frame.len());
+            if report.frames_sent > 0 && !self.fits(&report, frame_length) {
+                break;
+            }
+
+            let pending_frame = self.pending.remove(entity_id).expect("Ranked frame is not pending");
+            report.frames_sent += 1;
+            report.bytes_sent += frame_length;
+            frames.push(pending_frame.outgoing_frame);
+        }
+
+        for pending_frame in self.pending.values_mut() {
+            pending_frame.ticks_waited += 1;
+        }
+        report.frames_deferred = self.pending.len();
+
+        report
+    }
+
+    pub fn number_of_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn fits(&self, report: &TickReport, frame_length: usize) -> bool {
+        let fits_bytes = self.budget.max_bytes
+            .map(|max_bytes| report.bytes_sent + frame_length <= max_bytes)
+            .unwrap_or(true);
+        let fits_messages = self.budget.max_messages
+            .map(|max_messages| report.frames_sent < max_messages)
+            .unwrap_or(true);
+
+        fits_bytes && fits_messages
+    }
+
+    /// The own entity ranks 0, the others rank after it by distance.
+    fn rank(&self, pending_frame: &PendingFrame) -> usize {
+        let outgoing_frame = &pending_frame.outgoing_frame;
+        if outgoing_frame.entity_id == self.entity_id {
+            return 0;
+        }
+
+        let distance = match (self.position.as_ref(), outgoing_frame.position.as_ref()) {
+            (Some(position), Some(entity_position)) =>
+                max(position.0.abs_diff(entity_position.0), position.1.abs_diff(entity_position.1)),
+            _ => 0,
+        };
+
+        1 + distance.checked_shr(pending_frame.ticks_waited).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::EncodedFrame;
+    use entity::DemoEntity;
+    use entity::Timestamp;
+    use message::Message;
+    use spatiub::spatial::SpatialEvent;
+    use std::rc::Rc;
+
+    #[test]
+    pub fn own_entity_then_nearest_entities_are_sent_first() {
+        let own_entity = new_entity();
+        let mut scheduler = Scheduler::new(own_entity.id, unlimited());
+        let (far, near) = (new_entity(), new_entity());
+
+        scheduler.push(outgoing_frame(&far, Point(50, 0)));
+        scheduler.push(outgoing_frame(&near, Point(5, 0)));
+        scheduler.push(outgoing_frame(&own_entity, Point(0, 0)));
+
+        let mut frames = vec![];
+        let report = scheduler.tick(&mut frames);
+
+        assert_eq!(3, report.frames_sent);
+        let frames: Vec<_> = frames.into_iter().map(|outgoing_frame| outgoing_frame.frame).collect();
+        assert_eq!(vec![
+            outgoing_frame(&own_entity, Point(0, 0)).frame,
+            outgoing_frame(&near, Point(5, 0)).frame,
+            outgoing_frame(&far, Point(50, 0)).frame,
+        ], frames);
+    }
+
+    #[test]
+    pub fn frames_beyond_the_budget_are_deferred() {
+        let own_entity = new_entity();
+        let frame_length = outgoing_frame(&own_entity, Point(0, 0)).frame.unwrap().len();
+        let mut scheduler = Scheduler::new(own_entity.id, Budget{
+            max_bytes: Some(frame_length * 2),
+            ..unlimited()
+        });
+        scheduler.push(outgoing_frame(&own_entity, Point(0, 0)));
+        for x in 1..4 {
+            scheduler.push(outgoing_frame(&new_entity(), Point(x, 0)));
+        }
+
+        let mut frames = vec![];
+        let report = scheduler.tick(&mut frames);
+        assert_eq!(TickReport{frames_sent: 2, bytes_sent: frame_length * 2, frames_conflated: 0, frames_deferred: 2}, report);
+
+        let report = scheduler.tick(&mut frames);
+        assert_eq!(2, report.frames_sent);
+        assert_eq!(0, scheduler.number_of_pending());
+    }
+
+    #[test]
+    pub fn message_budget_limits_the_frames_per_tick() {
+        let own_entity = new_entity();
+        let mut scheduler = Scheduler::new(own_entity.id, Budget{
+            max_messages: Some(1),
+            ..unlimited()
+        });
+        for x in 1..4 {
+            scheduler.push(outgoing_frame(&new_entity(), Point(x, 0)));
+        }
+
+        let mut frames = vec![];
+        for _tick in 0..3 {
+            assert_eq!(1, scheduler.tick(&mut frames).frames_sent);
+        }
+        assert_eq!(3, frames.len());
+    }
+
+    #[test]
+    pub fn pending_frames_of_an_entity_are_conflated() {
+        let own_entity = new_entity();
+        let mut scheduler = Scheduler::new(own_entity.id, unlimited());
+        let other_entity = new_entity();
+
+        for x in 1..5 {
+            scheduler.push(outgoing_frame(&other_entity, Point(x, 0)));
+        }
+
+        let mut frames = vec![];
+        let report = scheduler.tick(&mut frames);
+        assert_eq!(3, report.frames_conflated);
+        assert_eq!(1, frames.len());
+        assert_eq!(outgoing_frame(&other_entity, Point(4, 0)).frame, frames[0].frame);
+    }
+
+    #[test]
+    pub fn far_entities_waiting_are_not_starved() {
+        let own_entity = new_entity();
+        let mut scheduler = Scheduler::new(own_entity.id, Budget{
+            max_messages: Some(1),
+            ..unlimited()
+        });
+        scheduler.push(outgoing_frame(&own_entity, Point(0, 0)));
+        let far = new_entity();
+        scheduler.push(outgoing_frame(&far, Point(1000, 0)));
+        let mut frames = vec![];
+        scheduler.tick(&mut frames);
+
+        let near = new_entity();
+        let mut far_is_sent = false;
+        for _tick in 0..20 {
+            scheduler.push(outgoing_frame(&near, Point(10, 0)));
+            frames.clear();
+            scheduler.tick(&mut frames);
+            far_is_sent |= frames[0].entity_id == far.id;
+        }
+
+        assert!(far_is_sent);
+    }
+
+    fn unlimited() -> Budget {
+        Budget{
+            tick: Duration::from_millis(50),
+            max_bytes: None,
+            max_messages: None,
+        }
+    }
+
+    fn new_entity() -> DemoEntity {
+        DemoEntity{
+            id: Uuid::new_v4(),
+            last_state_update: Timestamp::new(),
+        }
+    }
+
+    fn outgoing_frame(entity: &DemoEntity, position: Point) -> OutgoingFrame {
+        let event = SpatialEvent{
+            from: position.clone(),
+            to: Some(position.clone()),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        };
+
+        OutgoingFrame{
+            entity_id: entity.id,
+            position: Some(position),
+            frame: Some(EncodedFrame::encode(&Message::Event(event.clone())).unwrap()),
+            event: Rc::new(event),
+        }
+    }
+}
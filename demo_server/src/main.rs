@@ -16,7 +16,12 @@ extern crate spatiub_demo_core;
 use clap::App;
 use hwloc::{CPUBIND_THREAD, CpuSet, ObjectType, Topology};
 use log::LevelFilter;
+use spatiub::quadtree::QuadtreeConfig;
 use spatiub::spatial::MapDefinition;
+use spatiub::spatial::Partitioning;
+use spatiub_demo_core::codec::Compression;
+use spatiub_demo_core::scheduler::Budget;
+use server::ServerConfig;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -25,6 +30,7 @@ use std::thread::JoinHandle;
 use std::time::Duration;
 use clap::Arg;
 
+mod metrics;
 mod server;
 
 fn main() {
@@ -37,6 +43,48 @@ fn main() {
             .value_name("CORE")
             .help("The logical core (or processing unit) to pin the server on.")
             .takes_value(true))
+        .arg(Arg::with_name("partitioning")
+            .short("p")
+            .long("partitioning")
+            .value_name("PARTITIONING")
+            .help("How the map is partitioned: fixed size zones, or zones splitting when crowded.")
+            .possible_values(&["grid", "quadtree"])
+            .takes_value(true))
+        .arg(Arg::with_name("byte-budget")
+            .long("byte-budget")
+            .value_name("BYTES")
+            .help("The bytes sent to each connection per tick. Frames that do not fit wait for the next ticks.")
+            .takes_value(true))
+        .arg(Arg::with_name("message-budget")
+            .long("message-budget")
+            .value_name("MESSAGES")
+            .help("The messages sent to each connection per tick. Messages that do not fit wait for the next ticks.")
+            .takes_value(true))
+        .arg(Arg::with_name("max-batch-length")
+            .long("max-batch-length")
+            .value_name("BYTES")
+            .help("The events sent together to a connection are packed into frames of at most this length, 16KiB by default. 0 sends a frame per event.")
+            .takes_value(true))
+        .arg(Arg::with_name("delta")
+            .long("delta")
+            .conflicts_with_all(&["entity-handles", "byte-budget"])
+            .help("Sends the events of each tick as a snapshot of what changed since the client acknowledged."))
+        .arg(Arg::with_name("entity-handles")
+            .long("entity-handles")
+            .conflicts_with("byte-budget")
+            .help("Names the entities by short handles given to each connection, instead of their ids."))
+        .arg(Arg::with_name("compression-threshold")
+            .long("compression-threshold")
+            .value_name("BYTES")
+            .help("Compresses the frames of at least this length for the clients accepting it.")
+            .takes_value(true))
+        .arg(Arg::with_name("tick")
+            .long("tick")
+            .value_name("MILLIS")
+            .help("Enables the tick mode: the moves of a tick are collapsed by entity and published \
+            at its end, and each connection is flushed once per tick. Also the period of the \
+            budget, 50ms by default.")
+            .takes_value(true))
         .version("0.1")
         .author("Pierre L. <pierre.larger@gmail.com>")
         .get_matches();
@@ -48,12 +96,58 @@ fn main() {
     let core = matches.value_of("core").unwrap_or("0").parse::<usize>().unwrap();
     info!("Core: {}", core);
 
+    let partitioning = match matches.value_of("partitioning").unwrap_or("grid") {
+        "quadtree" => Partitioning::Quadtree(QuadtreeConfig::default()),
+        _ => Partitioning::Grid,
+    };
+    info!("Partitioning: {:?}", partitioning);
+
+    let max_bytes = matches.value_of("byte-budget").map(|bytes| bytes.parse::<usize>().unwrap());
+    let max_messages = matches.value_of("message-budget").map(|messages| messages.parse::<usize>().unwrap());
+    let tick = matches.value_of("tick").map(|millis| Duration::from_millis(millis.parse::<u64>().unwrap()));
+    info!("Tick: {:?}", tick);
+
+    let delta_snapshots = matches.is_present("delta");
+    info!("Delta snapshots: {}", delta_snapshots);
+
+    let entity_handles = matches.is_present("entity-handles");
+    info!("Entity handles: {}", entity_handles);
+
+    let compression = matches.value_of("compression-threshold")
+        .map(|bytes| Compression{threshold: bytes.parse::<usize>().unwrap()});
+    info!("Compression: {:?}", compression);
+
+    // The tick mode and the snapshots schedule the connections too, even without limits.
+    let budget = if max_bytes.is_some() || max_messages.is_some() || tick.is_some() || delta_snapshots {
+        Some(Budget{
+            tick: tick.unwrap_or_else(|| Duration::from_millis(50)),
+            max_bytes,
+            max_messages,
+        })
+    } else {
+        None
+    };
+    info!("Budget: {:?}", budget);
+
+    let max_batch_length = matches.value_of("max-batch-length").unwrap_or("16384").parse::<usize>().unwrap();
+    info!("Max batch length: {}", max_batch_length);
+
+    let config = ServerConfig{
+        partitioning,
+        budget,
+        tick,
+        max_batch_length,
+        delta_snapshots,
+        entity_handles,
+        compression,
+    };
+
     let addr = addr.clone();
     let map = map.clone();
     run_thread(
         hw_topo.clone(),
         core,
-        "server".to_string(),move || server::server(&addr, &map),
+        "server".to_string(),move || server::server(&addr, &map, config),
     ).join().unwrap();
 }
 
@@ -0,0 +1,102 @@
+use spatiub::spatial::PublishReport;
+use spatiub_demo_core::scheduler::TickReport;
+use std::cmp::max;
+use std::time::Duration;
+
+/// Aggregates the publication reports between two reporting intervals.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    events_published: usize,
+    fan_out: usize,
+    zones_visited: usize,
+    subscribers_dropped: usize,
+    entities_in_range_notifications: usize,
+    zone_changes: usize,
+    ticks: usize,
+    frames_scheduled: usize,
+    bytes_scheduled: usize,
+    frames_conflated: usize,
+    frames_deferred: usize,
+    largest_backlog: usize,
+    moves_collapsed: usize,
+    snapshots: usize,
+    snapshot_bytes: usize,
+}
+
+impl ServerMetrics {
+    pub fn record(&mut self, report: &PublishReport) {
+        self.events_published += 1;
+        self.fan_out += report.fan_out;
+        self.zones_visited += report.zones_visited;
+        self.subscribers_dropped += report.subscribers_dropped;
+        self.entities_in_range_notifications += report.entities_in_range_notifications;
+
+        if report.changed_zone {
+            self.zone_changes += 1;
+        }
+    }
+
+    pub fn record_tick(&mut self, report: &TickReport) {
+        self.ticks += 1;
+        self.frames_scheduled += report.frames_sent;
+        self.bytes_scheduled += report.bytes_sent;
+        self.frames_conflated += report.frames_conflated;
+        self.frames_deferred += report.frames_deferred;
+        self.largest_backlog = max(self.largest_backlog, report.frames_deferred);
+    }
+
+    pub fn record_collapsed_move(&mut self) {
+        self.moves_collapsed += 1;
+    }
+
+    pub fn record_snapshot(&mut self, length: usize) {
+        self.snapshots += 1;
+        self.snapshot_bytes += length;
+    }
+
+    /// Logs the metrics collected during the interval then starts a new one.
+    pub fn report(&mut self, interval: Duration) {
+        let interval_in_secs = interval.as_secs().max(1) as usize;
+        let average_fan_out = if self.events_published > 0 {
+            self.fan_out as f64 / self.events_published as f64
+        } else {
+            0.0
+        };
+
+        info!(
+            "Events/s: {}, deliveries/s: {}, average fan-out: {:.2}, zones visited/s: {}, \
+            entities in range notifications/s: {}, zone changes/s: {}, subscribers dropped: {}",
+            self.events_published / interval_in_secs,
+            self.fan_out / interval_in_secs,
+            average_fan_out,
+            self.zones_visited / interval_in_secs,
+            self.entities_in_range_notifications / interval_in_secs,
+            self.zone_changes / interval_in_secs,
+            self.subscribers_dropped,
+        );
+
+        if self.ticks > 0 {
+            info!(
+                "Scheduled frames/s: {}, scheduled bytes/s: {}, conflated frames/s: {}, \
+                average backlog per connection: {:.2}, largest backlog: {}, collapsed moves/s: {}",
+                self.frames_scheduled / interval_in_secs,
+                self.bytes_scheduled / interval_in_secs,
+                self.frames_conflated / interval_in_secs,
+                self.frames_deferred as f64 / self.ticks as f64,
+                self.largest_backlog,
+                self.moves_collapsed / interval_in_secs,
+            );
+        }
+
+        if self.snapshots > 0 {
+            info!(
+                "Snapshots/s: {}, snapshot bytes/s: {}, average snapshot length: {:.2}",
+                self.snapshots / interval_in_secs,
+                self.snapshot_bytes / interval_in_secs,
+                self.snapshot_bytes as f64 / self.snapshots as f64,
+            );
+        }
+
+        *self = ServerMetrics::default();
+    }
+}
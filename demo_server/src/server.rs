@@ -1,17 +1,19 @@
 use futures::{Future, future, Stream, stream, Sink};
-use spatiub::futures_sub;
+use futures::future::Either;
 use spatiub::spatial::Entity;
 use spatiub::spatial::MapDefinition;
 use spatiub::spatial::Point;
-use spatiub::spatial::SpatialChannel;
+use spatiub::spatial::Partitioning;
+use spatiub::spatial::SpatialPubSub;
+use spatiub::tick::TickBuffer;
 use tokio_codec::Decoder;
+use tokio_codec::Framed;
 use tokio::net::TcpListener;
+use tokio::net::TcpStream;
 use tokio::runtime::current_thread::Runtime;
 use uuid::Uuid;
 use spatiub::spatial::SpatialEvent;
 use std::io::Error;
-use std::marker::PhantomData;
-use spatiub::futures_sub::FutureSubscriber;
 use futures::unsync::mpsc::UnboundedReceiver;
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -20,61 +22,226 @@ use rand::thread_rng;
 use spatiub_demo_core::entity::Timestamp;
 use spatiub_demo_core::entity::DemoEntity;
 use spatiub_demo_core::message::Message;
-use spatiub_demo_core::codec::LengthFieldBasedCodec;
+use spatiub_demo_core::codec::Compression;
+use spatiub_demo_core::codec::EncodedFrame;
+use spatiub_demo_core::codec::EncodedFrameCodec;
+use spatiub_demo_core::delta::DeltaEncoder;
+use spatiub_demo_core::fan_out;
+use spatiub_demo_core::fan_out::FrameCache;
+use spatiub_demo_core::fan_out::FrameSubscriber;
+use spatiub_demo_core::fan_out::OutgoingFrame;
+use spatiub_demo_core::format::AnyFormat;
+use spatiub_demo_core::handshake;
+use spatiub_demo_core::handshake::Features;
+use spatiub_demo_core::handshake::Handshake;
+use spatiub_demo_core::handshake::Offer;
+use spatiub_demo_core::handshake::Rejection;
+use spatiub_demo_core::handshake::Welcome;
+use spatiub_demo_core::handle::EntityHandles;
+use spatiub_demo_core::message::EventBatcher;
+use spatiub_demo_core::position::PositionEncoding;
+use spatiub_demo_core::position::ZonedPositions;
+use spatiub_demo_core::scheduler::Budget;
+use spatiub_demo_core::scheduler::Scheduler;
+use metrics::ServerMetrics;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::timer::Interval;
 
 type Event = SpatialEvent<DemoEntity>;
-type SpatialChannelCell = RefCell<SpatialChannel<FutureSubscriber<Event>, DemoEntity>>;
+type MessageFramed = Framed<TcpStream, EncodedFrameCodec<Message, AnyFormat>>;
+type SpatialChannelCell = RefCell<Box<dyn SpatialPubSub<FrameSubscriber, DemoEntity>>>;
 
-pub fn server(addr: &SocketAddr, map: &MapDefinition) {
-    let mut rng = thread_rng();
+const METRICS_INTERVAL: Duration = Duration::from_secs(10);
 
-    let channel = RefCell::new(SpatialChannel::new(map.clone()));
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    pub partitioning: Partitioning,
+    /// Without a budget, events are sent as soon as they are published.
+    pub budget: Option<Budget>,
+    /// With a tick, the moves received during a tick are collapsed by entity and published at its end.
+    pub tick: Option<Duration>,
+    /// The events sent together to a connection are packed into frames of at most this length.
+    pub max_batch_length: usize,
+    /// Events are sent as delta snapshots, one per tick of the budget, to the connections
+    /// supporting them. Requires a budget without a byte limit, excludes entity handles, and
+    /// positions stay plain.
+    pub delta_snapshots: bool,
+    /// Entities are named by short handles given to each connection supporting them, see
+    /// `EntityHandles`. Events are then encoded for each connection, and not batched. Excludes a
+    /// byte budget.
+    pub entity_handles: bool,
+    /// Frames are compressed for the connections supporting it.
+    pub compression: Option<Compression>,
+}
+
+pub fn server(addr: &SocketAddr, map: &MapDefinition, config: ServerConfig) {
+    if config.delta_snapshots {
+        let budget = config.budget.expect("Delta snapshots are sent once per tick of a budget");
+        assert!(budget.max_bytes.is_none(), "The byte budget does not count delta snapshots");
+        assert!(!config.entity_handles, "Delta snapshots name the entities by their ids, not by handles");
+    }
+    if config.entity_handles {
+        assert!(config.budget.and_then(|budget| budget.max_bytes).is_none(), "The byte budget does not count the frames naming entities by handles");
+    }
+
+    let channel: SpatialChannelCell = RefCell::new(config.partitioning.build(map.clone()));
+    let metrics = RefCell::new(ServerMetrics::default());
+    let frame_cache = FrameCache::new();
+    let frame_cache = match ZonedPositions::new(map) {
+        Some(zoned_positions) => frame_cache.with_zoned_positions(zoned_positions),
+        None => frame_cache,
+    };
+    // Positions stay plain if the map does not fit the zoned encoding.
+    let position_encoding = if frame_cache.supports(PositionEncoding::Zoned) {
+        PositionEncoding::Zoned
+    } else {
+        PositionEncoding::Plain
+    };
+    let frame_cache = Rc::new(RefCell::new(frame_cache));
+    let tick_buffer = config.tick.map(|_| RefCell::new(TickBuffer::new()));
+    let (channel, metrics, tick_buffer) = (&channel, &metrics, tick_buffer.as_ref());
 
     let mut runtime = Runtime::new().unwrap();
 
     let listener = TcpListener::bind(&addr).unwrap();
 
+    let offer = Offer{
+        formats: AnyFormat::ALL.to_vec(),
+        features: Features{
+            compression: config.compression.is_some(),
+            batching: config.max_batch_length > 0,
+            position_encoding,
+            delta_snapshots: config.delta_snapshots,
+            entity_handles: config.entity_handles,
+        },
+    };
+    let offer = &offer;
+
     let server = listener.incoming().map(|socket| {
         socket.set_nodelay(true).unwrap();
-        let (output, input) = codec().framed(socket).split();
+        let frame_cache = frame_cache.clone();
 
-        let entity = DemoEntity{
-            id: Uuid::new_v4(),
-            last_state_update: Timestamp::new(),
-        };
+        handshake(socket, offer).and_then(move |accepted| {
+            let (framed, welcome) = match accepted {
+                Some(accepted) => accepted,
+                None => return Either::B(future::ok(())),
+            };
+            let (output, input) = framed.split();
+            let compression = if welcome.features.compression { config.compression } else { None };
+            // Every frame is compressed on its way out.
+            let output = output.with(move |frame: EncodedFrame| -> Result<EncodedFrame, Error> {
+                Ok(match compression {
+                    Some(compression) => frame.compressed(&compression),
+                    None => frame,
+                })
+            });
+            // The connection only gets what its client supports, in the format it chose. Without
+            // batching, each event keeps its own frame.
+            let format = welcome.format;
+            let mut config = config;
+            if !welcome.features.batching {
+                config.max_batch_length = 0;
+            }
+            config.delta_snapshots = welcome.features.delta_snapshots;
+            config.entity_handles = welcome.features.entity_handles;
 
-        let (subscriber, subscription) = futures_sub::new_subscriber(entity.id().clone());
+            let entity = DemoEntity{
+                id: Uuid::new_v4(),
+                last_state_update: Timestamp::new(),
+            };
 
-        let position = map.random_point(&mut rng);
-        subscribe(&channel, subscriber, &position);
+            let (subscriber, subscription) = fan_out::new_subscriber(entity.id().clone(), frame_cache.clone());
+            // The snapshots and the frames naming entities by handles are encoded for each
+            // connection, the frames of the events are of no use.
+            let subscriber = if config.delta_snapshots || config.entity_handles { subscriber.without_frames() } else { subscriber };
+            let subscriber = subscriber
+                .with_format(format)
+                .with_position_encoding(welcome.features.position_encoding);
+            let delta_encoder = if config.delta_snapshots {
+                Some(Rc::new(RefCell::new(DeltaEncoder::new())))
+            } else {
+                None
+            };
+            let entity_handles = if config.entity_handles {
+                Some(EntityHandles::new(entity.id, map))
+            } else {
+                None
+            };
 
-        publish(&channel, Event{
-            to: Some(position.clone()),
-            from: position,
-            acting_entity: entity.clone(),
-            is_a_move: true,
-        });
+            let position = map.random_point(&mut thread_rng());
+            subscribe(channel, subscriber, &position);
+            // Where the entity leaves from once the connection closes.
+            let last_position = Rc::new(RefCell::new(position.clone()));
+            let received_position = last_position.clone();
+            let leaving_entity = entity.clone();
+            let entity_id = entity.id;
 
-        outgoing_events(subscription, entity, output)
-            .join(
-                input
-                    .map_err(|err|{
-                        error!("IO error in the input stream: {}", err)
-                    })
-                    .for_each(|message|{
-                        match message {
-                            Message::Event(event) => {
-                                // TODO Only accept events from the same entity.
-                                publish(&channel, event);
-
-                                future::ok(())
-                            },
-                            Message::ConnectionAck(_) => {
-                                // Forbidden for clients
-                                future::err(())
-                            },
-                        }
-                    }))
+            publish(channel, metrics, Event{
+                to: Some(position.clone()),
+                from: position,
+                acting_entity: entity.clone(),
+                is_a_move: true,
+            });
+
+            // The map comes with the acknowledgement, for the client to move on it.
+            let connection_ack = EncodedFrame::encode_with(&Message::ConnectionAck(entity.clone(), map.clone()), &format)
+                .expect("Could not encode the connection acknowledgement");
+            let outgoing_events = {
+                let delta_encoder = delta_encoder.clone();
+                output.send(connection_ack)
+                    .map_err(|err| error!("Could not acknowledge the connection: {}", err))
+                    .and_then(move |output| outgoing_events(subscription, entity, output, config, format, delta_encoder, entity_handles, metrics))
+            };
+
+            Either::A(outgoing_events
+                .select(
+                    input
+                        .map_err(|err|{
+                            error!("IO error in the input stream: {}", err)
+                        })
+                        .for_each(move |message|{
+                            match message {
+                                Message::Event(event) => {
+                                    // TODO Only accept events from the same entity.
+                                    remember_position(&received_position, &entity_id, &event);
+                                    receive(channel, metrics, tick_buffer, event);
+
+                                    future::ok(())
+                                },
+                                Message::Events(events) => {
+                                    for event in events {
+                                        remember_position(&received_position, &entity_id, &event);
+                                        receive(channel, metrics, tick_buffer, event);
+                                    }
+
+                                    future::ok(())
+                                },
+                                Message::SnapshotAck(sequence) => {
+                                    if let Some(ref delta_encoder) = delta_encoder {
+                                        delta_encoder.borrow_mut().acknowledge(sequence);
+                                    }
+
+                                    future::ok(())
+                                },
+                                Message::ConnectionAck(_, _)
+                                | Message::Snapshot(_)
+                                | Message::ZonedEvent(_)
+                                | Message::ZonedEvents(_)
+                                | Message::Introduction(_, _)
+                                | Message::HandleEvent(_)
+                                | Message::HandleReleased(_) => {
+                                    // Forbidden for clients
+                                    future::err(())
+                                },
+                            }
+                        }))
+                // Either side ending closes the connection, without stopping the server.
+                .then(move |_| {
+                    release(channel, metrics, tick_buffer, leaving_entity, last_position.borrow().clone());
+                    Ok(())
+                }))
+        })
     })
         .map_err(|err| {
             error!("An unexpected error occurred: {}", err);
@@ -85,18 +252,94 @@ pub fn server(addr: &SocketAddr, map: &MapDefinition) {
         })
     ;
 
-    runtime.block_on(server).unwrap();
+    let metrics_reporting = Interval::new(Instant::now() + METRICS_INTERVAL, METRICS_INTERVAL)
+        .map_err(|err|{
+            error!("Timer error: {}", err)
+        })
+        .for_each(|_|{
+            metrics.borrow_mut().report(METRICS_INTERVAL);
+            Ok(())
+        });
+
+    let ticks = match (config.tick, tick_buffer) {
+        (Some(tick), Some(tick_buffer)) => Either::A(Interval::new(Instant::now() + tick, tick)
+            .map_err(|err|{
+                error!("Timer error: {}", err)
+            })
+            .for_each(move |_|{
+                for event in tick_buffer.borrow_mut().drain() {
+                    publish(channel, metrics, event);
+                }
+                Ok(())
+            })),
+        _ => Either::B(future::ok(())),
+    };
+
+    runtime.block_on(server.join3(metrics_reporting, ticks)).unwrap();
 
     info!("Server stopped");
 }
 
+/// Unsubscribes the entity of a closed connection, releasing what its subscription holds, and
+/// tells the others it left.
+fn release(
+    channel: &SpatialChannelCell,
+    metrics: &RefCell<ServerMetrics>,
+    tick_buffer: Option<&RefCell<TickBuffer<DemoEntity>>>,
+    entity: DemoEntity,
+    position: Point,
+) {
+    match channel.try_borrow_mut(){
+        Ok(mut channel_ref) => {
+            channel_ref.unsubscribe(entity.id());
+        },
+        Err(err) => {
+            panic!("Could not unsubscribe {}. Cause: {}", entity.id(), err)
+        }
+    }
+
+    receive(channel, metrics, tick_buffer, Event{
+        from: position,
+        to: None,
+        acting_entity: entity,
+        is_a_move: true,
+    });
+}
+
+fn remember_position(last_position: &RefCell<Point>, entity_id: &Uuid, event: &Event) {
+    if event.acting_entity.id == *entity_id {
+        if let Some(ref to) = event.to {
+            last_position.replace(to.clone());
+        }
+    }
+}
+
+/// Publishes the event, or buffers it until the end of the tick in tick mode.
+fn receive(
+    channel: &SpatialChannelCell,
+    metrics: &RefCell<ServerMetrics>,
+    tick_buffer: Option<&RefCell<TickBuffer<DemoEntity>>>,
+    event: Event,
+) {
+    match tick_buffer {
+        Some(tick_buffer) => {
+            if tick_buffer.borrow_mut().push(event) {
+                metrics.borrow_mut().record_collapsed_move();
+            }
+        },
+        None => publish(channel, metrics, event),
+    }
+}
+
 fn publish(
     channel: &SpatialChannelCell,
+    metrics: &RefCell<ServerMetrics>,
     event: Event,
 ) {
     match channel.try_borrow_mut(){
         Ok(mut channel_ref) => {
-            channel_ref.publish(event);
+            let report = channel_ref.publish(event);
+            metrics.borrow_mut().record(&report);
         },
         Err(err) => {
             panic!("Could not publish {:?}. Cause: {}", event, err)
@@ -106,7 +349,7 @@ fn publish(
 
 fn subscribe(
     channel: &SpatialChannelCell,
-    subscriber: FutureSubscriber<Event>,
+    subscriber: FrameSubscriber,
     position: &Point,
 ) {
     match channel.try_borrow_mut(){
@@ -119,32 +362,144 @@ fn subscribe(
     }
 }
 
-pub fn codec() -> LengthFieldBasedCodec<Message> {
-    LengthFieldBasedCodec{
-        phantom: PhantomData,
-    }
+pub fn codec(format: AnyFormat) -> EncodedFrameCodec<Message, AnyFormat> {
+    EncodedFrameCodec::with_format(format)
+}
+
+/// Answers the hello of the client, then frames the messages in the format chosen.
+/// `None` once the client is rejected, or gone.
+fn handshake(socket: TcpStream, offer: &Offer)
+    -> impl Future<Item=Option<(MessageFramed, Welcome)>, Error=()> {
+    let offer = offer.clone();
+
+    handshake::codec().framed(socket)
+        .into_future()
+        .map_err(|(err, _)| error!("Could not read the hello: {}", err))
+        .and_then(move |(hello, framed)| {
+            let answer = match hello {
+                Some(Handshake::Hello(hello)) => offer.answer(&hello),
+                Some(_) => Err(Rejection::ExpectedHello),
+                None => return Either::B(future::ok(None)),
+            };
+            let reply = match answer {
+                Ok(welcome) => Handshake::Welcome(welcome),
+                Err(rejection) => {
+                    info!("Client rejected: {}", rejection);
+                    Handshake::Rejected(rejection)
+                },
+            };
+
+            Either::A(framed.send(reply.clone())
+                .map_err(|err| error!("Could not answer the hello: {}", err))
+                .map(move |framed| match reply {
+                    Handshake::Welcome(welcome) => Some((handshake::switch_codec(framed, codec(welcome.format)), welcome)),
+                    _ => None,
+                }))
+        })
+        // A failed handshake only ends its connection.
+        .or_else(|_| Ok(None))
 }
 
-fn outgoing_events<S>(
-    subscription_stream: UnboundedReceiver<Rc<Event>>,
+// The state of the connection comes apart, as each part goes to a different stage of the stream.
+#[allow(clippy::too_many_arguments)]
+fn outgoing_events<'a, S>(
+    subscription_stream: UnboundedReceiver<OutgoingFrame>,
     entity: DemoEntity,
     sender: S,
-) -> impl Future<Item=(), Error=()>
-    where S: Sink<SinkItem=Message, SinkError=Error>,
+    config: ServerConfig,
+    format: AnyFormat,
+    delta_encoder: Option<Rc<RefCell<DeltaEncoder>>>,
+    mut entity_handles: Option<EntityHandles>,
+    metrics: &'a RefCell<ServerMetrics>,
+) -> impl Future<Item=(), Error=()> + 'a
+    where S: Sink<SinkItem=EncodedFrame, SinkError=Error> + 'a,
 {
-    let connection_ack = stream::once(Ok(Message::ConnectionAck(entity)));
-    let outgoing_events =
-        connection_ack.chain(subscription_stream
-            .map(|event|{
-                Message::Event(event.as_ref().clone())
-            })
-        )
-            .forward(sender
-                .sink_map_err(|err|{
-                    error!("IO error in the output stream: {}", err)
-                }));
-
-    outgoing_events
-        .map(|_|{})
-        .map_err(|_|{})
-}
\ No newline at end of file
+    let sender = sender
+        .sink_map_err(|err|{
+            error!("IO error in the output stream: {}", err)
+        });
+
+    match config.budget {
+        None => {
+            let outgoing_events = match entity_handles {
+                Some(mut entity_handles) => Either::A(subscription_stream
+                    .map(move |outgoing_frame| stream::iter_ok(entity_handles.frames_for(&outgoing_frame.event, &format)))
+                    .flatten()
+                    .forward(sender)
+                    .map(|_|{})),
+                None => Either::B(fan_out::batched(subscription_stream, EventBatcher::for_format(config.max_batch_length, &format))
+                    .forward(sender)
+                    .map(|_|{})),
+            };
+
+            Either::A(outgoing_events
+                .map_err(|_|{}))
+        },
+        Some(budget) => {
+            let scheduler = Rc::new(RefCell::new(Scheduler::new(entity.id, budget)));
+            let mut batcher = EventBatcher::for_format(config.max_batch_length, &format);
+
+            let scheduling = {
+                let scheduler = scheduler.clone();
+                subscription_stream.for_each(move |outgoing_frame| {
+                    scheduler.borrow_mut().push(outgoing_frame);
+                    Ok(())
+                })
+            };
+
+            // The frames of a tick are ready together, so the sink is flushed once per tick.
+            let ticks = Interval::new(Instant::now() + budget.tick, budget.tick)
+                .map_err(|err|{
+                    error!("Timer error: {}", err)
+                })
+                .map(move |_| {
+                    let mut frames = vec![];
+                    let report = scheduler.borrow_mut().tick(&mut frames);
+                    metrics.borrow_mut().record_tick(&report);
+
+                    let mut batches = vec![];
+                    match (delta_encoder.as_ref(), entity_handles.as_mut()) {
+                        (Some(delta_encoder), _) => {
+                            if !frames.is_empty() {
+                                let snapshot = delta_encoder.borrow_mut()
+                                    .encode(frames.iter().map(|outgoing_frame| outgoing_frame.event.as_ref()));
+                                let frame = EncodedFrame::encode_with(&Message::Snapshot(snapshot), &format)
+                                    .expect("Could not encode the snapshot");
+                                metrics.borrow_mut().record_snapshot(frame.len());
+                                batches.push(frame);
+                            }
+                        },
+                        (None, Some(entity_handles)) => {
+                            for outgoing_frame in frames {
+                                batches.extend(entity_handles.frames_for(&outgoing_frame.event, &format));
+                            }
+                        },
+                        (None, None) => {
+                            for outgoing_frame in frames {
+                                let frame = outgoing_frame.frame
+                                    .expect("Connections without snapshots are sent frames");
+                                match batcher.push(frame) {
+                                    Ok(batch) => batches.extend(batch),
+                                    Err(err) => error!("Frame of {} dropped: {}", outgoing_frame.entity_id, err),
+                                }
+                            }
+                            batches.extend(batcher.finish());
+                        },
+                    }
+
+                    stream::iter_ok(batches)
+                })
+                .flatten();
+
+            // The connection ends with its subscription, or when the socket fails.
+            let outgoing_events = ticks
+                .forward(sender)
+                .map(|_|{});
+
+            Either::B(outgoing_events
+                .select(scheduling)
+                .map(|_|{})
+                .map_err(|_|{}))
+        },
+    }
+}
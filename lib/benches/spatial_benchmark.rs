@@ -1,33 +1,50 @@
 #[macro_use] extern crate criterion;
-extern crate futures;
+extern crate rand;
 extern crate uuid;
 extern crate spatiub;
 
 use criterion::Criterion;
 use uuid::Uuid;
-use futures::future;
-use futures::Stream;
-use futures::Future;
 use spatiub::spatial::SpatialChannel;
 use spatiub::spatial::MapDefinition;
-use spatiub::futures_sub;
 use spatiub::spatial::Point;
 use spatiub::spatial::SpatialEvent;
 use spatiub::spatial::Entity;
+use spatiub::fn_sub::FnSubscriber;
+use spatiub::pub_sub::PubSubError;
+use spatiub::layout::ZoneLayout;
+use spatiub::quadtree::QuadtreeConfig;
+use spatiub::spatial::Partitioning;
+use spatiub::spatial::SpatialPubSub;
+use rand::thread_rng;
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
 
 const ZONE_WIDTH: usize = 16;
+
+/// An entity walking along a row, past a crowd of subscribers standing next to its path.
 fn bench_sending(c: &mut Criterion) {
-    c.bench_function("bench_sending", |b| {
+    let crowd_sizes = vec![0, 100, 1000];
+
+    c.bench_function_over_inputs("bench_sending", |b, &crowd_size| {
         let map_width_in_zones = 1000;
         let map_width = map_width_in_zones * ZONE_WIDTH;
         let mut channel = SpatialChannel::new(
             MapDefinition::new(ZONE_WIDTH, map_width_in_zones)
         );
 
+        let crowd_events_received = Rc::new(Cell::new(0));
+        for i in 0..crowd_size {
+            let subscriber = FnSubscriber::new(Uuid::new_v4(), count_event(crowd_events_received.clone()));
+            channel.subscribe(subscriber, &Point(i * 1000 / crowd_size, 1));
+        }
+
         let entity_id = Uuid::new_v4();
 
         b.iter(|| {
-            let (subscriber, receiver) = futures_sub::new_subscriber(entity_id);
+            let events_received = Rc::new(Cell::new(0));
+            let subscriber = FnSubscriber::new(entity_id, count_event(events_received.clone()));
 
             let number_of_events = 1000;
             let mut position = Point(0, 0);
@@ -53,33 +70,173 @@ fn bench_sending(c: &mut Criterion) {
                 position = destination;
             }
 
+            assert!(events_received.get() >= number_of_events);
+        });
 
-            let mut number_of_events_left = number_of_events;
+        drop(channel);
+    }, crowd_sizes);
+}
 
-            let stream = receiver
-                .map_err(|_err|{
-                    EndOfStream::Unexpected
-                })
-                .for_each(|_event|{
-                number_of_events_left -= 1;
-                if number_of_events_left > 1 {
-                    future::ok(())
-                } else {
-                    future::err(EndOfStream::OutOfEvents)
-                }
+/// An entity pacing across the border of a zone crowded with subscribers.
+fn bench_crowded_zone_handoff(c: &mut Criterion) {
+    c.bench_function("bench_crowded_zone_handoff", |b| {
+        let mut channel = SpatialChannel::new(
+            MapDefinition::new(ZONE_WIDTH, 100)
+        );
+
+        let crowd_position = Point(ZONE_WIDTH * 10, ZONE_WIDTH * 10);
+        for _i in 0..5000 {
+            channel.subscribe(FnSubscriber::new(Uuid::new_v4(), accept_event), &crowd_position);
+        }
+
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let inside = Point(ZONE_WIDTH * 10, ZONE_WIDTH * 10 + 1);
+        let outside = Point(ZONE_WIDTH * 10 - 1, ZONE_WIDTH * 10 + 1);
+        channel.subscribe(FnSubscriber::new(entity.id, accept_event), &inside);
+
+        b.iter(|| {
+            channel.publish(SpatialEvent{
+                from: inside.clone(),
+                to: Some(outside.clone()),
+                acting_entity: entity.clone(),
+                is_a_move: true,
             });
 
-            assert_eq!(EndOfStream::OutOfEvents, stream.wait().err().unwrap());
+            channel.publish(SpatialEvent{
+                from: outside.clone(),
+                to: Some(inside.clone()),
+                acting_entity: entity.clone(),
+                is_a_move: true,
+            });
         });
-
-        drop(channel);
     });
 }
 
-#[derive(Debug, PartialEq)]
-enum EndOfStream{
-    OutOfEvents,
-    Unexpected,
+/// Entities scattered over a large map, pacing back and forth. Compares the zone layouts.
+fn bench_zone_layouts(c: &mut Criterion) {
+    let layouts = vec![ZoneLayout::RowMajor, ZoneLayout::Morton, ZoneLayout::Hilbert];
+
+    c.bench_function_over_inputs("bench_zone_layouts", |b, &layout| {
+        let map = MapDefinition::new(ZONE_WIDTH, 512).with_layout(layout);
+        let mut channel = SpatialChannel::new(map.clone());
+
+        let mut rng = thread_rng();
+        let mut entities = vec![];
+        for _i in 0..10_000 {
+            let entity = TestEntity{
+                id: Uuid::new_v4(),
+            };
+            let position = map.random_point(&mut rng);
+            let next_position = map.random_point_next_to(&position, &mut rng);
+            channel.subscribe(FnSubscriber::new(entity.id, accept_event), &position);
+
+            entities.push((entity, position, next_position));
+        }
+
+        let mut step = 0;
+        b.iter(|| {
+            for (entity, position, next_position) in &entities {
+                let (origin, destination) = if step % 2 == 0 {
+                    (position, next_position)
+                } else {
+                    (next_position, position)
+                };
+
+                channel.publish(SpatialEvent{
+                    from: origin.clone(),
+                    to: Some(destination.clone()),
+                    acting_entity: entity.clone(),
+                    is_a_move: true,
+                });
+            }
+
+            step += 1;
+        });
+    }, layouts);
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Population {
+    /// Scattered over the whole map.
+    Uniform,
+    /// Piled into a town square.
+    Clustered,
+}
+
+struct Scenario(Partitioning, Population);
+
+impl fmt::Debug for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Partitioning::Grid => write!(f, "Grid/{:?}", self.1),
+            Partitioning::Quadtree(_) => write!(f, "Quadtree/{:?}", self.1),
+        }
+    }
+}
+
+/// Entities pacing back and forth, with each partitioning and population.
+fn bench_partitionings(c: &mut Criterion) {
+    let quadtree = Partitioning::Quadtree(QuadtreeConfig::default());
+    let scenarios = vec![
+        Scenario(Partitioning::Grid, Population::Uniform),
+        Scenario(quadtree, Population::Uniform),
+        Scenario(Partitioning::Grid, Population::Clustered),
+        Scenario(quadtree, Population::Clustered),
+    ];
+
+    c.bench_function_over_inputs("bench_partitionings", |b, &Scenario(partitioning, population)| {
+        let map = MapDefinition::new(ZONE_WIDTH, 256);
+        let mut channel: Box<dyn SpatialPubSub<_, TestEntity>> = partitioning.build(map.clone());
+
+        let mut rng = thread_rng();
+        let mut entities = vec![];
+        for i in 0..2000 {
+            let entity = TestEntity{
+                id: Uuid::new_v4(),
+            };
+            let position = match population {
+                Population::Uniform => map.random_point(&mut rng),
+                Population::Clustered => Point(ZONE_WIDTH * 128 + i % 32, ZONE_WIDTH * 128 + i / 64),
+            };
+            let next_position = map.random_point_next_to(&position, &mut rng);
+            channel.subscribe(FnSubscriber::new(entity.id, accept_event), &position);
+
+            entities.push((entity, position, next_position));
+        }
+
+        let mut step = 0;
+        b.iter(|| {
+            for (entity, position, next_position) in &entities {
+                let (origin, destination) = if step % 2 == 0 {
+                    (position, next_position)
+                } else {
+                    (next_position, position)
+                };
+
+                channel.publish(SpatialEvent{
+                    from: origin.clone(),
+                    to: Some(destination.clone()),
+                    acting_entity: entity.clone(),
+                    is_a_move: true,
+                });
+            }
+
+            step += 1;
+        });
+    }, scenarios);
+}
+
+fn count_event(received: Rc<Cell<usize>>) -> impl Fn(Rc<SpatialEvent<TestEntity>>) -> Result<bool, PubSubError> {
+    move |_event| {
+        received.set(received.get() + 1);
+        Ok(true)
+    }
+}
+
+fn accept_event(_event: Rc<SpatialEvent<TestEntity>>) -> Result<bool, PubSubError> {
+    Ok(true)
 }
 
 #[derive(Clone)]
@@ -88,10 +245,12 @@ struct TestEntity{
 }
 
 impl Entity for TestEntity{
+    type Id = Uuid;
+
     fn id(&self) -> &Uuid {
         &self.id
     }
 }
 
-criterion_group!(benches, bench_sending);
+criterion_group!(benches, bench_sending, bench_crowded_zone_handoff, bench_zone_layouts, bench_partitionings);
 criterion_main!(benches);
\ No newline at end of file
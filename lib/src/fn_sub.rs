@@ -0,0 +1,114 @@
+use pub_sub::EntityId;
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// A subscriber invoking a callback inline, from within the publication.
+///
+/// The callback has the same contract as `Subscriber::send`: returning `Ok(false)` or an error
+/// drops the subscription. It must not publish or subscribe to the channel that invokes it.
+pub struct FnSubscriber<E, F, I: EntityId = Uuid> where F: Fn(Rc<E>) -> Result<bool, PubSubError> {
+    callback: Rc<F>,
+    entity_id: I,
+    phantom: PhantomData<E>,
+}
+
+impl <E, F, I: EntityId> FnSubscriber<E, F, I> where F: Fn(Rc<E>) -> Result<bool, PubSubError> {
+    pub fn new(entity_id: I, callback: F) -> FnSubscriber<E, F, I> {
+        FnSubscriber{
+            callback: Rc::new(callback),
+            entity_id,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl <E, F, I: EntityId> Clone for FnSubscriber<E, F, I> where F: Fn(Rc<E>) -> Result<bool, PubSubError> {
+    fn clone(&self) -> FnSubscriber<E, F, I> {
+        FnSubscriber{
+            callback: self.callback.clone(),
+            entity_id: self.entity_id.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl <E, F, I: EntityId> Subscriber<E> for FnSubscriber<E, F, I> where F: Fn(Rc<E>) -> Result<bool, PubSubError> {
+    type Id = I;
+
+    fn send(&self, event: Rc<E>) -> Result<bool, PubSubError> {
+        (self.callback)(event)
+    }
+
+    fn entity_id(&self) -> &I {
+        &self.entity_id
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use pub_sub::PubSubChannel;
+    use spatial::SpatialEvent;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::rc::Weak;
+    use subscriber_tests::SubscriberHarness;
+    use subscriber_tests::TestEntity;
+    use super::*;
+
+    #[test]
+    pub fn can_subscribe(){
+        let number_of_calls = Rc::new(Cell::new(0));
+        let calls = number_of_calls.clone();
+        let subscriber = FnSubscriber::new(Uuid::new_v4(), move |_event: Rc<()>|{
+            calls.set(calls.get() + 1);
+            Ok(calls.get() < 2)
+        });
+
+        let mut pub_sub = PubSubChannel::new();
+        pub_sub.subscribe(subscriber);
+
+        pub_sub.publish(Rc::new(()));
+        pub_sub.publish(Rc::new(()));
+        pub_sub.publish(Rc::new(()));
+
+        assert_eq!(2, number_of_calls.get());
+        assert!(pub_sub.is_empty());
+    }
+
+    type Received = RefCell<Vec<Rc<SpatialEvent<TestEntity>>>>;
+    type Callback = Box<dyn Fn(Rc<SpatialEvent<TestEntity>>) -> Result<bool, PubSubError>>;
+
+    struct FnHarness {
+        received: Rc<Received>,
+    }
+
+    impl SubscriberHarness for FnHarness {
+        type Subscriber = FnSubscriber<SpatialEvent<TestEntity>, Callback>;
+
+        fn new_subscriber(entity_id: Uuid) -> (Self::Subscriber, FnHarness) {
+            let received = Rc::new(RefCell::new(vec![]));
+            let weak_received: Weak<Received> = Rc::downgrade(&received);
+
+            let callback: Callback = Box::new(move |event|{
+                match weak_received.upgrade() {
+                    Some(received) => {
+                        received.borrow_mut().push(event);
+                        Ok(true)
+                    },
+                    None => Err(PubSubError::ReceiverIsGone),
+                }
+            });
+
+            (FnSubscriber::new(entity_id, callback), FnHarness{ received })
+        }
+
+        fn received_events(&mut self) -> Vec<Rc<SpatialEvent<TestEntity>>> {
+            self.received.replace(vec![])
+        }
+    }
+
+    subscriber_test_suite!(FnHarness);
+}
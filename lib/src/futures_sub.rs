@@ -1,17 +1,18 @@
 use futures::unsync::mpsc::{self, UnboundedReceiver};
 use futures::unsync::mpsc::UnboundedSender;
+use pub_sub::EntityId;
 use pub_sub::PubSubError;
 use pub_sub::Subscriber;
 use std::rc::Rc;
 use uuid::Uuid;
 
 #[derive(Clone, Debug)]
-pub struct FutureSubscriber<E: Clone> {
+pub struct FutureSubscriber<E: Clone, I: EntityId = Uuid> {
     sender: UnboundedSender<Rc<E>>,
-    entity_id: Uuid,
+    entity_id: I,
 }
 
-pub fn new_subscriber<E: Clone>(entity_id: Uuid) -> (FutureSubscriber<E>, UnboundedReceiver<Rc<E>>) {
+pub fn new_subscriber<E: Clone, I: EntityId>(entity_id: I) -> (FutureSubscriber<E, I>, UnboundedReceiver<Rc<E>>) {
     let (sender, receiver) = mpsc::unbounded();
 
     let subscriber = FutureSubscriber {
@@ -22,7 +23,9 @@ pub fn new_subscriber<E: Clone>(entity_id: Uuid) -> (FutureSubscriber<E>, Unboun
     (subscriber, receiver)
 }
 
-impl <E: Clone> Subscriber<E> for FutureSubscriber<E> {
+impl <E: Clone, I: EntityId> Subscriber<E> for FutureSubscriber<E, I> {
+    type Id = I;
+
     fn send(&self, event: Rc<E>) -> Result<bool, PubSubError> {
         match &self.sender.unbounded_send(event) {
             Ok(()) => {
@@ -34,7 +37,7 @@ impl <E: Clone> Subscriber<E> for FutureSubscriber<E> {
         }
     }
 
-    fn entity_id(&self) -> &Uuid {
+    fn entity_id(&self) -> &I {
         &self.entity_id
     }
 }
@@ -42,7 +45,11 @@ impl <E: Clone> Subscriber<E> for FutureSubscriber<E> {
 #[cfg(test)]
 mod tests{
     use pub_sub::PubSubChannel;
-    use futures::{Stream, Future};
+    use futures::{Async, Stream, Future};
+    use futures::future;
+    use spatial::SpatialEvent;
+    use subscriber_tests::SubscriberHarness;
+    use subscriber_tests::TestEntity;
     use super::*;
 
     #[derive(Debug, PartialEq, Clone)]
@@ -55,9 +62,38 @@ mod tests{
 
         pub_sub.subscribe(subscriber);
 
-        pub_sub.publish(Rc::new(TestEvent {})).unwrap();
+        pub_sub.publish(Rc::new(TestEvent {}));
 
         let (received_event_option, _receiver) = receiver.into_future().wait().unwrap();
         assert_eq!(TestEvent {}, Rc::try_unwrap(received_event_option.unwrap()).unwrap());
     }
-}
\ No newline at end of file
+
+    struct FutureHarness {
+        receiver: UnboundedReceiver<Rc<SpatialEvent<TestEntity>>>,
+    }
+
+    impl SubscriberHarness for FutureHarness {
+        type Subscriber = FutureSubscriber<SpatialEvent<TestEntity>>;
+
+        fn new_subscriber(entity_id: Uuid) -> (Self::Subscriber, FutureHarness) {
+            let (subscriber, receiver) = super::new_subscriber(entity_id);
+            (subscriber, FutureHarness{ receiver })
+        }
+
+        fn received_events(&mut self) -> Vec<Rc<SpatialEvent<TestEntity>>> {
+            let receiver = &mut self.receiver;
+
+            // Polling requires a task, the lazy future provides one.
+            future::lazy(move ||{
+                let mut events = vec![];
+                while let Ok(Async::Ready(Some(event))) = receiver.poll() {
+                    events.push(event);
+                }
+
+                future::ok::<_, ()>(events)
+            }).wait().unwrap()
+        }
+    }
+
+    subscriber_test_suite!(FutureHarness);
+}
@@ -0,0 +1,613 @@
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use spatial::Entity;
+use spatial::Point;
+use spatial::SpatialEvent;
+use spatial::Zone;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::cmp::min;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Ranks the entities in range of a `CappedSubscriber`, the lowest rank being the most relevant.
+pub trait Relevance<E> {
+    fn rank(&self, own_position: &Point, entity: &E, position: &Point) -> u64;
+}
+
+/// Ranks the entities by distance, the nearest first.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Nearest;
+
+impl <E> Relevance<E> for Nearest {
+    fn rank(&self, own_position: &Point, _entity: &E, position: &Point) -> u64 {
+        let distance_x = own_position.0.abs_diff(position.0) as u64;
+        let distance_y = own_position.1.abs_diff(position.1) as u64;
+        distance_x * distance_x + distance_y * distance_y
+    }
+}
+
+impl <E, F> Relevance<E> for F where F: Fn(&Point, &E, &Point) -> u64 {
+    fn rank(&self, own_position: &Point, entity: &E, position: &Point) -> u64 {
+        self(own_position, entity, position)
+    }
+}
+
+/// A subscriber only forwarding the events of the `capacity` most relevant entities in range.
+///
+/// The event of an entity only ranks that entity again, the moves of the subscribing entity rank
+/// everything in range again. An entity getting in is introduced by an event that is not a move,
+/// from its position to its position, like the entities replayed by the `SpatialChannel`. An
+/// entity getting out is dismissed by an event that is not a move either, from its last known
+/// position to `None`. The events of the subscribing entity itself are always forwarded, they
+/// tell where it stands.
+///
+/// The channel does not tell when an entity goes out of range, so entities farther than the
+/// view distance are dismissed and forgotten. Behind a `SpatialChannel`, entities in view are at
+/// most twice the zone width away. Entities of the same rank are ordered by id.
+pub struct CappedSubscriber<S, E, R> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, R: Relevance<E> {
+    inner: S,
+    relevance: Rc<R>,
+    capacity: usize,
+    view_distance: usize,
+    state: Rc<RefCell<InterestState<E>>>,
+}
+
+struct InterestState<E: Entity> {
+    position: Point,
+    /// The entities within the view distance.
+    in_range: HashMap<E::Id, InRange<E>>,
+    /// The ranks of the relevant entities, at most `capacity` of them.
+    relevant: BTreeSet<(u64, E::Id)>,
+    /// The ranks of the other entities in range, all after those of the relevant ones.
+    others: BTreeSet<(u64, E::Id)>,
+    /// Reused by each ranking of everything in range, to avoid allocating.
+    ranking: Vec<(u64, E::Id)>,
+}
+
+struct InRange<E> {
+    entity: E,
+    position: Point,
+    rank: u64,
+    is_relevant: bool,
+}
+
+impl <S, E, R> CappedSubscriber<S, E, R> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, R: Relevance<E> {
+    /// The position is where the subscription starts, the events of the entity update it. The
+    /// view distance is along either axis.
+    pub fn new(inner: S, position: Point, capacity: usize, view_distance: usize, relevance: R) -> CappedSubscriber<S, E, R> {
+        CappedSubscriber{
+            inner,
+            relevance: Rc::new(relevance),
+            capacity,
+            view_distance,
+            state: Rc::new(RefCell::new(InterestState{
+                position,
+                in_range: HashMap::new(),
+                relevant: BTreeSet::new(),
+                others: BTreeSet::new(),
+                ranking: vec![],
+            })),
+        }
+    }
+
+    /// Ranks the acting entity again, and forwards the event if it was and still is relevant.
+    /// An entity getting in or out of the relevant ones takes the place of another.
+    fn update_entity(&self, state: &mut InterestState<E>, event: &Rc<SpatialEvent<E>>, destination: &Point) -> Result<bool, PubSubError> {
+        let entity_id = event.acting_entity.id();
+        let rank = self.relevance.rank(&state.position, &event.acting_entity, destination);
+        let is_in_view = chebyshev_distance(&state.position, destination) <= self.view_distance;
+
+        let was_relevant = match state.in_range.get_mut(entity_id) {
+            Some(entity_in_range) => {
+                let key = (entity_in_range.rank, entity_id.clone());
+                if entity_in_range.is_relevant {
+                    state.relevant.remove(&key);
+                } else {
+                    state.others.remove(&key);
+                }
+
+                entity_in_range.entity.clone_from(&event.acting_entity);
+                entity_in_range.position.clone_from(destination);
+                entity_in_range.rank = rank;
+                entity_in_range.is_relevant
+            },
+            None if is_in_view => {
+                state.in_range.insert(entity_id.clone(), InRange{
+                    entity: event.acting_entity.clone(),
+                    position: destination.clone(),
+                    rank,
+                    is_relevant: false,
+                });
+                false
+            },
+            None => return Ok(true),
+        };
+
+        if !is_in_view {
+            let entity_in_range = state.in_range.remove(entity_id).expect("Updated entity not in range");
+            if was_relevant && !self.inner.send(dismissal(&entity_in_range))? {
+                return Ok(false);
+            }
+
+            return self.promote_first_other(state);
+        }
+
+        let key = (rank, entity_id.clone());
+        if was_relevant {
+            let is_still_relevant = state.others.iter().next().is_none_or(|first_other| key < *first_other);
+            if is_still_relevant {
+                state.relevant.insert(key);
+                return self.inner.send(event.clone());
+            }
+
+            state.others.insert(key);
+            let entity_in_range = state.in_range.get_mut(entity_id).expect("Updated entity not in range");
+            entity_in_range.is_relevant = false;
+            if !self.inner.send(dismissal(entity_in_range))? {
+                return Ok(false);
+            }
+
+            return self.promote_first_other(state);
+        }
+
+        let is_relevant = state.relevant.len() < self.capacity
+            || state.relevant.iter().next_back().is_some_and(|last_relevant| key < *last_relevant);
+        if !is_relevant {
+            state.others.insert(key);
+            return Ok(true);
+        }
+
+        if state.relevant.len() == self.capacity && !self.demote_last_relevant(state)? {
+            return Ok(false);
+        }
+
+        state.relevant.insert(key);
+        let entity_in_range = state.in_range.get_mut(entity_id).expect("Updated entity not in range");
+        entity_in_range.is_relevant = true;
+        self.inner.send(introduction(entity_in_range))
+    }
+
+    /// Forgets an entity that left the map, the event itself dismisses it.
+    fn remove_entity(&self, state: &mut InterestState<E>, event: &Rc<SpatialEvent<E>>) -> Result<bool, PubSubError> {
+        let entity_id = event.acting_entity.id();
+        let entity_in_range = match state.in_range.remove(entity_id) {
+            Some(entity_in_range) => entity_in_range,
+            None => return Ok(true),
+        };
+
+        let key = (entity_in_range.rank, entity_id.clone());
+        if !entity_in_range.is_relevant {
+            state.others.remove(&key);
+            return Ok(true);
+        }
+
+        state.relevant.remove(&key);
+        if !self.inner.send(event.clone())? {
+            return Ok(false);
+        }
+
+        self.promote_first_other(state)
+    }
+
+    /// Ranks everything in range again, sends the notifications of the entities getting out then
+    /// of those getting in.
+    fn rank_everything(&self, state: &mut InterestState<E>) -> Result<bool, PubSubError> {
+        let InterestState{ref position, ref mut in_range, ref mut relevant, ref mut others, ref mut ranking} = *state;
+
+        ranking.clear();
+        for (entity_id, entity_in_range) in in_range.iter_mut() {
+            if chebyshev_distance(position, &entity_in_range.position) <= self.view_distance {
+                entity_in_range.rank = self.relevance.rank(position, &entity_in_range.entity, &entity_in_range.position);
+                ranking.push((entity_in_range.rank, entity_id.clone()));
+            } else if entity_in_range.is_relevant {
+                entity_in_range.is_relevant = false;
+                if !self.inner.send(dismissal(entity_in_range))? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        in_range.retain(|_entity_id, entity_in_range| chebyshev_distance(position, &entity_in_range.position) <= self.view_distance);
+        ranking.sort_unstable();
+        let cut_off = min(self.capacity, ranking.len());
+
+        for &(_rank, ref entity_id) in ranking[cut_off..].iter() {
+            let entity_in_range = in_range.get_mut(entity_id).expect("Ranked entity not in range");
+            if entity_in_range.is_relevant {
+                entity_in_range.is_relevant = false;
+                if !self.inner.send(dismissal(entity_in_range))? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        for &(_rank, ref entity_id) in ranking[..cut_off].iter() {
+            let entity_in_range = in_range.get_mut(entity_id).expect("Ranked entity not in range");
+            if !entity_in_range.is_relevant {
+                entity_in_range.is_relevant = true;
+                if !self.inner.send(introduction(entity_in_range))? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        *relevant = ranking[..cut_off].iter().cloned().collect();
+        *others = ranking[cut_off..].iter().cloned().collect();
+
+        Ok(true)
+    }
+
+    /// Moves the least relevant entity to the others, and dismisses it.
+    fn demote_last_relevant(&self, state: &mut InterestState<E>) -> Result<bool, PubSubError> {
+        let key = match state.relevant.iter().next_back() {
+            Some(key) => key.clone(),
+            None => return Ok(true),
+        };
+
+        state.relevant.remove(&key);
+        let entity_in_range = state.in_range.get_mut(&key.1).expect("Relevant entity not in range");
+        entity_in_range.is_relevant = false;
+        state.others.insert(key);
+
+        self.inner.send(dismissal(entity_in_range))
+    }
+
+    /// Moves the most relevant of the others to the relevant entities if there is room, and
+    /// introduces it.
+    fn promote_first_other(&self, state: &mut InterestState<E>) -> Result<bool, PubSubError> {
+        if state.relevant.len() >= self.capacity {
+            return Ok(true);
+        }
+
+        let key = match state.others.iter().next() {
+            Some(key) => key.clone(),
+            None => return Ok(true),
+        };
+
+        state.others.remove(&key);
+        let entity_in_range = state.in_range.get_mut(&key.1).expect("Ranked entity not in range");
+        entity_in_range.is_relevant = true;
+        state.relevant.insert(key);
+
+        self.inner.send(introduction(entity_in_range))
+    }
+}
+
+impl <S, E, R> Clone for CappedSubscriber<S, E, R> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, R: Relevance<E> {
+    fn clone(&self) -> CappedSubscriber<S, E, R> {
+        CappedSubscriber{
+            inner: self.inner.clone(),
+            relevance: self.relevance.clone(),
+            capacity: self.capacity,
+            view_distance: self.view_distance,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl <S, E, R> Subscriber<SpatialEvent<E>> for CappedSubscriber<S, E, R> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, R: Relevance<E> {
+    type Id = E::Id;
+
+    fn send(&self, event: Rc<SpatialEvent<E>>) -> Result<bool, PubSubError> {
+        let mut state = self.state.borrow_mut();
+
+        if event.acting_entity.id() == self.inner.entity_id() {
+            if let Some(ref destination) = event.to {
+                state.position.clone_from(destination);
+            }
+
+            if !self.inner.send(event.clone())? {
+                return Ok(false);
+            }
+
+            return self.rank_everything(&mut state);
+        }
+
+        match event.to {
+            Some(ref destination) => self.update_entity(&mut state, &event, destination),
+            None => self.remove_entity(&mut state, &event),
+        }
+    }
+
+    fn entity_id(&self) -> &E::Id {
+        self.inner.entity_id()
+    }
+
+    fn on_subscribed(&self) {
+        self.inner.on_subscribed()
+    }
+
+    fn on_zone_changed(&self, from: &Zone, to: &Zone) {
+        self.inner.on_zone_changed(from, to)
+    }
+
+    fn on_dropped(&self, reason: &PubSubError) {
+        self.inner.on_dropped(reason)
+    }
+}
+
+fn introduction<E: Entity+Clone>(entity_in_range: &InRange<E>) -> Rc<SpatialEvent<E>> {
+    Rc::new(SpatialEvent{
+        from: entity_in_range.position.clone(),
+        to: Some(entity_in_range.position.clone()),
+        acting_entity: entity_in_range.entity.clone(),
+        is_a_move: false,
+    })
+}
+
+fn dismissal<E: Entity+Clone>(entity_in_range: &InRange<E>) -> Rc<SpatialEvent<E>> {
+    Rc::new(SpatialEvent{
+        from: entity_in_range.position.clone(),
+        to: None,
+        acting_entity: entity_in_range.entity.clone(),
+        is_a_move: false,
+    })
+}
+
+fn chebyshev_distance(point: &Point, other: &Point) -> usize {
+    max(point.0.abs_diff(other.0), point.1.abs_diff(other.1))
+}
+
+#[cfg(test)]
+mod tests{
+    use crossbeam_channel::Receiver;
+    use spatial::MapDefinition;
+    use spatial::SpatialChannel;
+    use subscriber_tests::TestEntity;
+    use super::*;
+    use sync_sub;
+    use std::cell::Cell;
+    use std::mem;
+    use sync_sub::SyncSubscriber;
+    use uuid::Uuid;
+
+    const VIEW_DISTANCE: usize = 32;
+
+    type TestSubscriber<R> = CappedSubscriber<SyncSubscriber<SpatialEvent<TestEntity>>, TestEntity, R>;
+
+    #[test]
+    pub fn only_the_nearest_entities_are_forwarded() {
+        let (subscriber, receiver) = capped_subscriber(Point(0, 0), 2, Nearest);
+        let (near, middle, far) = (new_entity(), new_entity(), new_entity());
+
+        subscriber.send(move_event(&near, Point(1, 0), Point(1, 0))).unwrap();
+        subscriber.send(move_event(&middle, Point(2, 0), Point(2, 0))).unwrap();
+        subscriber.send(move_event(&far, Point(5, 0), Point(5, 0))).unwrap();
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_entered(&near, &Point(1, 0), &received_events[0]);
+        assert_entered(&middle, &Point(2, 0), &received_events[1]);
+
+        subscriber.send(move_event(&far, Point(5, 0), Point(6, 0))).unwrap();
+        assert_eq!(0, receiver.try_iter().count());
+
+        subscriber.send(move_event(&near, Point(1, 0), Point(1, 1))).unwrap();
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(1, received_events.len());
+        assert!(received_events[0].is_a_move);
+        assert_eq!(Some(Point(1, 1)), received_events[0].to);
+    }
+
+    #[test]
+    pub fn entities_crossing_the_cut_off_enter_and_leave() {
+        let (subscriber, receiver) = capped_subscriber(Point(0, 0), 2, Nearest);
+        let (near, middle, far) = (new_entity(), new_entity(), new_entity());
+        subscriber.send(move_event(&near, Point(1, 0), Point(1, 0))).unwrap();
+        subscriber.send(move_event(&middle, Point(2, 0), Point(2, 0))).unwrap();
+        subscriber.send(move_event(&far, Point(5, 0), Point(5, 0))).unwrap();
+        receiver.try_iter().count();
+
+        subscriber.send(move_event(&far, Point(5, 0), Point(0, 1))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_left(&middle, &Point(2, 0), &received_events[0]);
+        assert_entered(&far, &Point(0, 1), &received_events[1]);
+
+        subscriber.send(move_event(&middle, Point(2, 0), Point(3, 0))).unwrap();
+        assert_eq!(0, receiver.try_iter().count());
+    }
+
+    #[test]
+    pub fn own_moves_update_the_relevance() {
+        let entity = new_entity();
+        let (inner, receiver) = sync_sub::new_subscriber(entity.id);
+        let subscriber = CappedSubscriber::new(inner, Point(0, 0), 1, VIEW_DISTANCE, Nearest);
+        let other_entity = new_entity();
+        subscriber.send(move_event(&other_entity, Point(1, 0), Point(1, 0))).unwrap();
+        let far_entity = new_entity();
+        subscriber.send(move_event(&far_entity, Point(10, 0), Point(10, 0))).unwrap();
+        receiver.try_iter().count();
+
+        subscriber.send(move_event(&entity, Point(0, 0), Point(9, 0))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(3, received_events.len());
+        assert_eq!(entity, received_events[0].acting_entity);
+        assert_left(&other_entity, &Point(1, 0), &received_events[1]);
+        assert_entered(&far_entity, &Point(10, 0), &received_events[2]);
+    }
+
+    #[test]
+    pub fn entities_leaving_the_map_free_their_place() {
+        let (subscriber, receiver) = capped_subscriber(Point(0, 0), 1, Nearest);
+        let (near, far) = (new_entity(), new_entity());
+        subscriber.send(move_event(&near, Point(1, 0), Point(1, 0))).unwrap();
+        subscriber.send(move_event(&far, Point(5, 0), Point(5, 0))).unwrap();
+        receiver.try_iter().count();
+
+        subscriber.send(Rc::new(SpatialEvent{
+            from: Point(1, 0),
+            to: None,
+            acting_entity: near.clone(),
+            is_a_move: true,
+        })).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_eq!(near, received_events[0].acting_entity);
+        assert_eq!(None, received_events[0].to);
+        assert_entered(&far, &Point(5, 0), &received_events[1]);
+    }
+
+    #[test]
+    pub fn entities_can_be_ranked_by_priority() {
+        let leader = new_entity();
+        let leader_id = leader.id;
+        let prefers_leader = move |_own_position: &Point, entity: &TestEntity, _position: &Point| {
+            if entity.id == leader_id { 0 } else { 1 }
+        };
+        let (subscriber, receiver) = capped_subscriber(Point(0, 0), 1, prefers_leader);
+        let follower = new_entity();
+
+        subscriber.send(move_event(&follower, Point(1, 0), Point(1, 0))).unwrap();
+        subscriber.send(move_event(&leader, Point(8, 0), Point(8, 0))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(3, received_events.len());
+        assert_entered(&follower, &Point(1, 0), &received_events[0]);
+        assert_left(&follower, &Point(1, 0), &received_events[1]);
+        assert_entered(&leader, &Point(8, 0), &received_events[2]);
+    }
+
+    #[test]
+    pub fn entities_of_the_same_rank_are_ordered_by_id() {
+        let (subscriber, receiver) = capped_subscriber(Point(0, 0), 1, Nearest);
+        let (first, second) = {
+            let (entity, other_entity) = (new_entity(), new_entity());
+            if entity.id < other_entity.id { (entity, other_entity) } else { (other_entity, entity) }
+        };
+
+        subscriber.send(move_event(&second, Point(0, 1), Point(0, 1))).unwrap();
+        subscriber.send(move_event(&first, Point(1, 0), Point(1, 0))).unwrap();
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(3, received_events.len());
+        assert_entered(&second, &Point(0, 1), &received_events[0]);
+        assert_left(&second, &Point(0, 1), &received_events[1]);
+        assert_entered(&first, &Point(1, 0), &received_events[2]);
+
+        let (mut first_position, mut second_position) = (Point(1, 0), Point(0, 1));
+        for _i in 0..10 {
+            subscriber.send(move_event(&second, second_position.clone(), first_position.clone())).unwrap();
+            subscriber.send(move_event(&first, first_position.clone(), second_position.clone())).unwrap();
+            mem::swap(&mut first_position, &mut second_position);
+        }
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(10, received_events.len());
+        assert!(received_events.iter().all(|event| event.acting_entity == first && event.is_a_move));
+    }
+
+    #[test]
+    pub fn a_crowd_is_only_ranked_again_by_moves_of_the_subscriber() {
+        let number_of_ranks = Rc::new(Cell::new(0));
+        let counting_ranks = number_of_ranks.clone();
+        let nearest = move |own_position: &Point, entity: &TestEntity, position: &Point| {
+            counting_ranks.set(counting_ranks.get() + 1);
+            Nearest.rank(own_position, entity, position)
+        };
+        let entity = new_entity();
+        let (inner, receiver) = sync_sub::new_subscriber(entity.id);
+        let subscriber = CappedSubscriber::new(inner, Point(0, 0), 10, VIEW_DISTANCE, nearest);
+
+        let crowd: Vec<_> = (0..1000).map(|i| (new_entity(), Point(i % 32, i / 32))).collect();
+        for (other_entity, position) in crowd.iter() {
+            subscriber.send(move_event(other_entity, position.clone(), position.clone())).unwrap();
+        }
+        for (other_entity, position) in crowd.iter() {
+            subscriber.send(move_event(other_entity, position.clone(), position.clone())).unwrap();
+        }
+        receiver.try_iter().count();
+        assert_eq!(crowd.len() * 2, number_of_ranks.get());
+
+        subscriber.send(move_event(&entity, Point(0, 0), Point(1, 0))).unwrap();
+        assert_eq!(crowd.len() * 3, number_of_ranks.get());
+        let mut expected: Vec<_> = crowd.iter()
+            .map(|(other_entity, position)| (Nearest.rank(&Point(1, 0), other_entity, position), other_entity.id))
+            .collect();
+        expected.sort();
+        expected.truncate(10);
+        assert_eq!(expected, subscriber.state.borrow().relevant.iter().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn entities_out_of_view_are_forgotten() {
+        let entity = new_entity();
+        let (inner, receiver) = sync_sub::new_subscriber(entity.id);
+        let subscriber = CappedSubscriber::new(inner, Point(0, 0), 1, 4, Nearest);
+        let other_entity = new_entity();
+        subscriber.send(move_event(&other_entity, Point(2, 0), Point(2, 0))).unwrap();
+        receiver.try_iter().count();
+
+        subscriber.send(move_event(&entity, Point(0, 0), Point(0, 10))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_left(&other_entity, &Point(2, 0), &received_events[1]);
+        assert!(subscriber.state.borrow().in_range.is_empty());
+    }
+
+    #[test]
+    pub fn caps_what_a_spatial_channel_sends() {
+        let mut channel = SpatialChannel::new(MapDefinition::new(16, 4));
+        for x in 1..6 {
+            let entity = new_entity();
+            channel.publish(SpatialEvent{
+                from: Point(x, 0),
+                to: Some(Point(x, 0)),
+                acting_entity: entity,
+                is_a_move: true,
+            });
+        }
+
+        let entity = new_entity();
+        let (inner, receiver) = sync_sub::new_subscriber(entity.id);
+        channel.subscribe(CappedSubscriber::new(inner, Point(0, 0), 3, 32, Nearest), &Point(0, 0));
+
+        let mut positions = vec![];
+        for event in receiver.try_iter() {
+            match event.to {
+                Some(ref position) => positions.push(position.0),
+                None => positions.retain(|&x| x != event.from.0),
+            }
+        }
+        positions.sort();
+        assert_eq!(vec![1, 2, 3], positions);
+    }
+
+    fn capped_subscriber<R: Relevance<TestEntity>>(position: Point, capacity: usize, relevance: R)
+        -> (TestSubscriber<R>, Receiver<SpatialEvent<TestEntity>>) {
+        let (inner, receiver) = sync_sub::new_subscriber(Uuid::new_v4());
+        (CappedSubscriber::new(inner, position, capacity, VIEW_DISTANCE, relevance), receiver)
+    }
+
+    fn new_entity() -> TestEntity {
+        TestEntity{
+            id: Uuid::new_v4(),
+        }
+    }
+
+    fn move_event(entity: &TestEntity, from: Point, to: Point) -> Rc<SpatialEvent<TestEntity>> {
+        Rc::new(SpatialEvent{
+            from,
+            to: Some(to),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        })
+    }
+
+    fn assert_entered(entity: &TestEntity, position: &Point, event: &SpatialEvent<TestEntity>) {
+        assert_eq!(entity, &event.acting_entity);
+        assert_eq!(Some(position), event.to.as_ref());
+        assert!(!event.is_a_move);
+    }
+
+    fn assert_left(entity: &TestEntity, position: &Point, event: &SpatialEvent<TestEntity>) {
+        assert_eq!(entity, &event.acting_entity);
+        assert_eq!(position, &event.from);
+        assert_eq!(None, event.to);
+        assert!(!event.is_a_move);
+    }
+}
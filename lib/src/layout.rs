@@ -0,0 +1,165 @@
+/// How the zones of a map are laid out in memory.
+///
+/// Each publish touches the neighbourhood of a zone. With `RowMajor` that neighbourhood is spread
+/// over three rows, far apart on large maps. The curves keep neighbouring zones close to each
+/// other, at the cost of padding the map to a power of two width and of a more expensive index
+/// computation. `bench_zone_layouts` compares them.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneLayout {
+    /// Zones are stored line after line, `x` being the line.
+    #[default]
+    RowMajor,
+    /// Zones are stored along a Z-order curve, interleaving the bits of the coordinates.
+    Morton,
+    /// Zones are stored along a Hilbert curve, which never jumps between consecutive zones.
+    Hilbert,
+}
+
+impl ZoneLayout {
+    /// The number of indexes needed to lay out a map of the given width.
+    pub fn capacity(self, map_width_in_zones: usize) -> usize {
+        let side = self.side(map_width_in_zones);
+        side * side
+    }
+
+    /// The index of the zone at the given zone coordinates.
+    pub fn zone_index(self, x: usize, y: usize, map_width_in_zones: usize) -> usize {
+        match self {
+            ZoneLayout::RowMajor => x * map_width_in_zones + y,
+            ZoneLayout::Morton => spread_bits(x) << 1 | spread_bits(y),
+            ZoneLayout::Hilbert => hilbert_index(x, y, self.side(map_width_in_zones)),
+        }
+    }
+
+    /// The zone coordinates of the given index, the inverse of `zone_index`.
+    /// Padding indexes of the curves decode to coordinates outside of the map.
+    pub fn zone_coordinates(self, index: usize, map_width_in_zones: usize) -> (usize, usize) {
+        match self {
+            ZoneLayout::RowMajor => (index / map_width_in_zones, index % map_width_in_zones),
+            ZoneLayout::Morton => (compact_bits(index >> 1), compact_bits(index)),
+            ZoneLayout::Hilbert => hilbert_coordinates(index, self.side(map_width_in_zones)),
+        }
+    }
+
+    fn side(self, map_width_in_zones: usize) -> usize {
+        match self {
+            ZoneLayout::RowMajor => map_width_in_zones,
+            ZoneLayout::Morton | ZoneLayout::Hilbert => map_width_in_zones.next_power_of_two(),
+        }
+    }
+}
+
+/// Inserts a zero bit between each of the 32 low bits of the value.
+fn spread_bits(value: usize) -> usize {
+    let mut spread = value as u64 & 0x0000_0000_FFFF_FFFF;
+    spread = (spread | spread << 16) & 0x0000_FFFF_0000_FFFF;
+    spread = (spread | spread << 8) & 0x00FF_00FF_00FF_00FF;
+    spread = (spread | spread << 4) & 0x0F0F_0F0F_0F0F_0F0F;
+    spread = (spread | spread << 2) & 0x3333_3333_3333_3333;
+    spread = (spread | spread << 1) & 0x5555_5555_5555_5555;
+    spread as usize
+}
+
+/// Keeps one bit out of two, the inverse of `spread_bits`.
+fn compact_bits(value: usize) -> usize {
+    let mut compact = value as u64 & 0x5555_5555_5555_5555;
+    compact = (compact | compact >> 1) & 0x3333_3333_3333_3333;
+    compact = (compact | compact >> 2) & 0x0F0F_0F0F_0F0F_0F0F;
+    compact = (compact | compact >> 4) & 0x00FF_00FF_00FF_00FF;
+    compact = (compact | compact >> 8) & 0x0000_FFFF_0000_FFFF;
+    compact = (compact | compact >> 16) & 0x0000_0000_FFFF_FFFF;
+    compact as usize
+}
+
+fn hilbert_index(x: usize, y: usize, side: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut index = 0;
+    let mut quadrant_width = side / 2;
+
+    while quadrant_width > 0 {
+        let rx = (x & quadrant_width > 0) as usize;
+        let ry = (y & quadrant_width > 0) as usize;
+        index += quadrant_width * quadrant_width * ((3 * rx) ^ ry);
+        rotate_quadrant(side, &mut x, &mut y, rx, ry);
+        quadrant_width /= 2;
+    }
+
+    index
+}
+
+fn hilbert_coordinates(index: usize, side: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut remaining = index;
+    let mut quadrant_width = 1;
+
+    while quadrant_width < side {
+        let rx = 1 & (remaining / 2);
+        let ry = 1 & (remaining ^ rx);
+        rotate_quadrant(quadrant_width, &mut x, &mut y, rx, ry);
+        x += quadrant_width * rx;
+        y += quadrant_width * ry;
+        remaining /= 4;
+        quadrant_width *= 2;
+    }
+
+    (x, y)
+}
+
+fn rotate_quadrant(width: usize, x: &mut usize, y: &mut usize, rx: usize, ry: usize) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = width - 1 - *x;
+            *y = width - 1 - *y;
+        }
+
+        ::std::mem::swap(x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::collections::HashSet;
+
+    const LAYOUTS: [ZoneLayout; 3] = [ZoneLayout::RowMajor, ZoneLayout::Morton, ZoneLayout::Hilbert];
+
+    #[test]
+    pub fn indexes_are_unique_and_can_be_decoded(){
+        for layout in LAYOUTS.iter() {
+            for &map_width_in_zones in [1, 7, 16].iter() {
+                let mut indexes = HashSet::new();
+
+                for x in 0..map_width_in_zones {
+                    for y in 0..map_width_in_zones {
+                        let index = layout.zone_index(x, y, map_width_in_zones);
+
+                        assert!(index < layout.capacity(map_width_in_zones));
+                        assert!(indexes.insert(index), "{:?} reuses index {}", layout, index);
+                        assert_eq!((x, y), layout.zone_coordinates(index, map_width_in_zones));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn hilbert_curve_only_moves_to_neighbours(){
+        let side = 16;
+        let mut previous = ZoneLayout::Hilbert.zone_coordinates(0, side);
+
+        for index in 1..ZoneLayout::Hilbert.capacity(side) {
+            let (x, y) = ZoneLayout::Hilbert.zone_coordinates(index, side);
+            let distance = (x as isize - previous.0 as isize).abs() + (y as isize - previous.1 as isize).abs();
+
+            assert_eq!(1, distance, "Index {} jumps from {:?} to {:?}", index, previous, (x, y));
+            previous = (x, y);
+        }
+    }
+
+    #[test]
+    pub fn morton_curve_interleaves_coordinates(){
+        assert_eq!(0b11, ZoneLayout::Morton.zone_index(1, 1, 4));
+        assert_eq!(0b1000, ZoneLayout::Morton.zone_index(2, 0, 4));
+        assert_eq!(0b0100, ZoneLayout::Morton.zone_index(0, 2, 4));
+    }
+}
@@ -1,13 +1,29 @@
 extern crate bincode;
 extern crate core;
+extern crate crossbeam_channel;
 extern crate env_logger;
 extern crate futures;
+extern crate futures03;
+extern crate indexmap;
 #[macro_use] extern crate log;
 extern crate rand;
 extern crate serde;
 #[macro_use]extern crate serde_derive;
 extern crate uuid;
 
+#[cfg(test)]
+#[macro_use]
+mod subscriber_tests;
+
 pub mod pub_sub;
 pub mod futures_sub;
-pub mod spatial;
\ No newline at end of file
+pub mod stream_sub;
+pub mod sync_sub;
+pub mod fn_sub;
+pub mod spatial;
+pub mod layout;
+pub mod quadtree;
+pub mod interest;
+pub mod lod;
+pub mod tick;
+pub mod topic;
\ No newline at end of file
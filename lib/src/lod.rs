@@ -0,0 +1,569 @@
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use spatial::Entity;
+use spatial::Point;
+use spatial::SpatialEvent;
+use spatial::Zone;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Tells the time to a `LodSubscriber`.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Entities up to the radius are forwarded at most one move per interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ring {
+    radius: usize,
+    interval: Duration,
+}
+
+/// A subscriber forwarding every move of the entities near it, and sampling the farther ones.
+///
+/// Entities within the near radius are not throttled. Beyond it, each ring forwards at most one
+/// move per entity per interval, and entities beyond the last ring are sampled like in it. The
+/// moves held back are aggregated: the next forwarded move starts where the previous forwarded one
+/// ended. Distances are measured along either axis, like zones.
+///
+/// Each event received forwards the moves held back which are due, so does `flush`, to be called
+/// by a tick when events are scarce: the last move of an entity which stopped is not lost. The
+/// events of the subscribing entity itself, the events which are not moves and the entities
+/// leaving the map are always forwarded straight away. Entities out of view of the zone the
+/// subscription moves to are forgotten, once their move held back is forwarded.
+pub struct LodSubscriber<S, E, C=SystemClock> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, C: Clock {
+    inner: S,
+    near_radius: usize,
+    rings: Rc<Vec<Ring>>,
+    clock: Rc<C>,
+    state: Rc<RefCell<LodState<E>>>,
+}
+
+struct LodState<E: Entity> {
+    position: Point,
+    entities: HashMap<E::Id, SampledEntity<E>>,
+    /// The earliest a move held back is due, so that events only flush when one is.
+    next_due: Option<Instant>,
+}
+
+struct SampledEntity<E: Entity> {
+    last_sent: Option<Instant>,
+    sent_position: Point,
+    pending: Option<Rc<SpatialEvent<E>>>,
+}
+
+impl <S, E> LodSubscriber<S, E, SystemClock> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    /// The position is where the subscription starts, the events of the entity update it.
+    pub fn new(inner: S, position: Point, near_radius: usize) -> LodSubscriber<S, E, SystemClock> {
+        LodSubscriber{
+            inner,
+            near_radius,
+            rings: Rc::new(vec![]),
+            clock: Rc::new(SystemClock),
+            state: Rc::new(RefCell::new(LodState{
+                position,
+                entities: HashMap::new(),
+                next_due: None,
+            })),
+        }
+    }
+}
+
+impl <S, E, C> LodSubscriber<S, E, C> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, C: Clock {
+    /// Adds a ring beyond the previous ones, forwarding at most one move per entity per interval.
+    pub fn with_ring(mut self, radius: usize, interval: Duration) -> LodSubscriber<S, E, C> {
+        let inner_radius = self.rings.last().map(|ring| ring.radius).unwrap_or(self.near_radius);
+        assert!(radius > inner_radius, "Ring of radius {} is not beyond radius {}", radius, inner_radius);
+
+        Rc::get_mut(&mut self.rings)
+            .expect("Rings are added before the subscriber is cloned")
+            .push(Ring{radius, interval});
+        self
+    }
+
+    pub fn with_clock<D: Clock>(self, clock: D) -> LodSubscriber<S, E, D> {
+        LodSubscriber{
+            inner: self.inner,
+            near_radius: self.near_radius,
+            rings: self.rings,
+            clock: Rc::new(clock),
+            state: self.state,
+        }
+    }
+
+    /// Forwards the moves held back whose interval has elapsed.
+    /// Returns false if the inner subscriber is gone.
+    pub fn flush(&self) -> Result<bool, PubSubError> {
+        let mut state = self.state.borrow_mut();
+        self.flush_due(&mut state, self.clock.now(), None)
+    }
+
+    /// Forwards the moves held back whose interval has elapsed, but the one of the skipped entity.
+    fn flush_due(&self, state: &mut LodState<E>, now: Instant, skipped: Option<&E::Id>) -> Result<bool, PubSubError> {
+        match state.next_due {
+            Some(next_due) if next_due <= now => {},
+            _ => return Ok(true),
+        }
+
+        let LodState{ref position, ref mut entities, ref mut next_due} = *state;
+        *next_due = None;
+        for (entity_id, sampled) in entities.iter_mut() {
+            let interval = match sampled.pending.as_ref().and_then(|pending| pending.to.as_ref()) {
+                Some(destination) => self.interval_at(position, destination),
+                None => continue,
+            };
+
+            if Some(entity_id) != skipped && sampled.is_due(now, interval) {
+                let pending = sampled.pending.take().expect("Due entity without pending move");
+                if !self.inner.send(sampled.aggregate(pending, now))? {
+                    return Ok(false);
+                }
+            } else {
+                *next_due = earliest(*next_due, sampled.due(interval));
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Forgets the entities farther from the zone than its width, after forwarding their move
+    /// held back.
+    fn forget_out_of_view(&self, zone: &Zone) {
+        let view_distance = zone.end().0 - zone.start().0;
+        let now = self.clock.now();
+        let mut state = self.state.borrow_mut();
+
+        state.entities.retain(|_entity_id, sampled| {
+            let is_in_view = match sampled.pending.as_ref().and_then(|pending| pending.to.as_ref()) {
+                Some(destination) => zone.distance_to(destination) <= view_distance,
+                None => zone.distance_to(&sampled.sent_position) <= view_distance,
+            };
+
+            if !is_in_view {
+                if let Some(pending) = sampled.pending.take() {
+                    // Nothing to do if it fails, the next event drops the subscriber.
+                    let _ = self.inner.send(sampled.aggregate(pending, now));
+                }
+            }
+
+            is_in_view
+        });
+    }
+
+    /// The number of entities with a move held back.
+    pub fn number_of_pending(&self) -> usize {
+        self.state.borrow().entities.values()
+            .filter(|sampled| sampled.pending.is_some())
+            .count()
+    }
+
+    fn interval_at(&self, position: &Point, destination: &Point) -> Duration {
+        let distance = max(position.0.abs_diff(destination.0), position.1.abs_diff(destination.1));
+        if distance <= self.near_radius {
+            return Duration::from_secs(0);
+        }
+
+        self.rings.iter()
+            .find(|ring| distance <= ring.radius)
+            .or_else(|| self.rings.last())
+            .map(|ring| ring.interval)
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+}
+
+impl <E: Entity+Clone> SampledEntity<E> {
+    fn is_due(&self, now: Instant, interval: Duration) -> bool {
+        match self.due(interval) {
+            Some(due) => now >= due,
+            None => true,
+        }
+    }
+
+    /// When the next move can be forwarded, `None` if none was yet.
+    fn due(&self, interval: Duration) -> Option<Instant> {
+        self.last_sent.map(|last_sent| last_sent + interval)
+    }
+
+    /// The event moving the entity from where it was last sent to where the event takes it.
+    fn aggregate(&mut self, event: Rc<SpatialEvent<E>>, now: Instant) -> Rc<SpatialEvent<E>> {
+        let aggregated = if event.from == self.sent_position {
+            event
+        } else {
+            Rc::new(SpatialEvent{
+                from: self.sent_position.clone(),
+                to: event.to.clone(),
+                acting_entity: event.acting_entity.clone(),
+                is_a_move: event.is_a_move,
+            })
+        };
+
+        if let Some(ref destination) = aggregated.to {
+            self.sent_position.clone_from(destination);
+        }
+        self.last_sent = Some(now);
+        aggregated
+    }
+}
+
+impl <S, E, C> Clone for LodSubscriber<S, E, C> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, C: Clock {
+    fn clone(&self) -> LodSubscriber<S, E, C> {
+        LodSubscriber{
+            inner: self.inner.clone(),
+            near_radius: self.near_radius,
+            rings: self.rings.clone(),
+            clock: self.clock.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl <S, E, C> Subscriber<SpatialEvent<E>> for LodSubscriber<S, E, C> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone, C: Clock {
+    type Id = E::Id;
+
+    fn send(&self, event: Rc<SpatialEvent<E>>) -> Result<bool, PubSubError> {
+        let mut state = self.state.borrow_mut();
+        let now = self.clock.now();
+        let acting_entity_id = event.acting_entity.id();
+
+        // The move of the acting entity itself is superseded by the event.
+        if !self.flush_due(&mut state, now, Some(acting_entity_id))? {
+            return Ok(false);
+        }
+
+        let LodState{ref mut position, ref mut entities, ref mut next_due} = *state;
+        if acting_entity_id == self.inner.entity_id() {
+            if let Some(ref destination) = event.to {
+                position.clone_from(destination);
+            }
+
+            return self.inner.send(event);
+        }
+
+        let destination = match event.to {
+            Some(ref destination) => destination.clone(),
+            None => {
+                entities.remove(acting_entity_id);
+                return self.inner.send(event);
+            },
+        };
+
+        let interval = self.interval_at(position, &destination);
+        let sampled = entities.entry(acting_entity_id.clone())
+            .or_insert_with(|| SampledEntity{
+                last_sent: None,
+                sent_position: event.from.clone(),
+                pending: None,
+            });
+
+        if !event.is_a_move {
+            sampled.sent_position = destination;
+            sampled.pending = None;
+            return self.inner.send(event);
+        }
+
+        if !sampled.is_due(now, interval) {
+            *next_due = earliest(*next_due, sampled.due(interval));
+            sampled.pending = Some(event);
+            return Ok(true);
+        }
+
+        sampled.pending = None;
+        self.inner.send(sampled.aggregate(event, now))
+    }
+
+    fn entity_id(&self) -> &E::Id {
+        self.inner.entity_id()
+    }
+
+    fn on_subscribed(&self) {
+        self.inner.on_subscribed()
+    }
+
+    fn on_zone_changed(&self, from: &Zone, to: &Zone) {
+        self.forget_out_of_view(to);
+        self.inner.on_zone_changed(from, to)
+    }
+
+    fn on_dropped(&self, reason: &PubSubError) {
+        self.inner.on_dropped(reason)
+    }
+}
+
+fn earliest(instant: Option<Instant>, other: Option<Instant>) -> Option<Instant> {
+    match (instant, other) {
+        (Some(instant), Some(other)) => Some(min(instant, other)),
+        (instant, None) => instant,
+        (None, other) => other,
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crossbeam_channel::Receiver;
+    use spatial::MapDefinition;
+    use spatial::SpatialChannel;
+    use std::cell::Cell;
+    use subscriber_tests::TestEntity;
+    use super::*;
+    use sync_sub;
+    use sync_sub::SyncSubscriber;
+    use uuid::Uuid;
+
+    const NEAR_RADIUS: usize = 10;
+    const RING_RADIUS: usize = 50;
+
+    type TestSubscriber = LodSubscriber<SyncSubscriber<SpatialEvent<TestEntity>>, TestEntity, ManualClock>;
+
+    #[derive(Clone)]
+    struct ManualClock(Rc<Cell<Instant>>);
+
+    impl ManualClock {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    pub fn near_entities_are_not_throttled() {
+        let (subscriber, receiver, _clock) = lod_subscriber();
+        let entity = new_entity();
+
+        let number_of_moves = walk(&subscriber, &entity, Point(1, 0), NEAR_RADIUS - 1);
+
+        assert_eq!(number_of_moves, receiver.try_iter().count());
+        assert_eq!(0, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn far_entities_are_sampled_once_per_interval() {
+        let (subscriber, receiver, clock) = lod_subscriber();
+        let entity = new_entity();
+
+        let mut position = Point(NEAR_RADIUS + 1, 0);
+        for _i in 0..20 {
+            let destination = Point(position.0 + 1, 0);
+            subscriber.send(move_event(&entity, position, destination.clone())).unwrap();
+            position = destination;
+            clock.advance(Duration::from_millis(100));
+        }
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(4, received_events.len());
+        for pair in received_events.windows(2) {
+            assert_eq!(pair[0].to.as_ref(), Some(&pair[1].from));
+        }
+        assert_eq!(1, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn flush_forwards_the_aggregated_move_once_due() {
+        let (subscriber, receiver, clock) = lod_subscriber();
+        let entity = new_entity();
+        let start = Point(NEAR_RADIUS + 1, 0);
+        let end = walk(&subscriber, &entity, start.clone(), 5);
+        assert_eq!(1, receiver.try_iter().count());
+
+        clock.advance(Duration::from_millis(499));
+        subscriber.flush().unwrap();
+        assert_eq!(0, receiver.try_iter().count());
+
+        clock.advance(Duration::from_millis(1));
+        subscriber.flush().unwrap();
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(1, received_events.len());
+        assert_eq!(Point(start.0 + 1, 0), received_events[0].from);
+        assert_eq!(Some(Point(start.0 + end, 0)), received_events[0].to);
+        assert!(received_events[0].is_a_move);
+        assert_eq!(0, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn the_next_event_forwards_the_moves_due() {
+        let (subscriber, receiver, clock) = lod_subscriber();
+        let (far, near) = (new_entity(), new_entity());
+        let start = Point(NEAR_RADIUS + 1, 0);
+        let end = walk(&subscriber, &far, start.clone(), 5);
+        assert_eq!(1, receiver.try_iter().count());
+
+        clock.advance(Duration::from_millis(500));
+        subscriber.send(move_event(&near, Point(1, 0), Point(2, 0))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_eq!(far, received_events[0].acting_entity);
+        assert_eq!(Some(Point(start.0 + end, 0)), received_events[0].to);
+        assert_eq!(near, received_events[1].acting_entity);
+        assert_eq!(0, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn entities_out_of_view_are_forgotten_on_zone_change() {
+        let (subscriber, receiver, _clock) = lod_subscriber();
+        let (far, ahead) = (new_entity(), new_entity());
+        let start = Point(NEAR_RADIUS + 1, 0);
+        let end = walk(&subscriber, &far, start.clone(), 5);
+        subscriber.send(Rc::new(SpatialEvent{
+            from: Point(70, 0),
+            to: Some(Point(70, 0)),
+            acting_entity: ahead.clone(),
+            is_a_move: false,
+        })).unwrap();
+        assert_eq!(2, receiver.try_iter().count());
+
+        subscriber.on_zone_changed(&Zone::new(Point(0, 0), Point(16, 16)), &Zone::new(Point(64, 0), Point(80, 16)));
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(1, received_events.len());
+        assert_eq!(far, received_events[0].acting_entity);
+        assert_eq!(Some(Point(start.0 + end, 0)), received_events[0].to);
+        assert_eq!(0, subscriber.number_of_pending());
+        assert_eq!(1, subscriber.state.borrow().entities.len());
+        assert!(subscriber.state.borrow().entities.contains_key(&ahead.id));
+    }
+
+    #[test]
+    pub fn each_ring_has_its_own_interval() {
+        let (subscriber, receiver, clock) = lod_subscriber();
+        let subscriber = subscriber.with_ring(RING_RADIUS * 2, Duration::from_secs(2));
+        let (middle, far) = (new_entity(), new_entity());
+        let middle_start = Point(NEAR_RADIUS + 1, 0);
+        let far_start = Point(RING_RADIUS + 1, 0);
+
+        for _i in 0..20 {
+            subscriber.send(move_event(&middle, middle_start.clone(), middle_start.clone())).unwrap();
+            subscriber.send(move_event(&far, far_start.clone(), far_start.clone())).unwrap();
+            clock.advance(Duration::from_millis(250));
+        }
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        let count = |entity: &TestEntity| received_events.iter().filter(|event| &event.acting_entity == entity).count();
+        assert_eq!(10, count(&middle));
+        assert_eq!(3, count(&far));
+    }
+
+    #[test]
+    pub fn entities_getting_near_are_forwarded_straight_away() {
+        let (subscriber, receiver, _clock) = lod_subscriber();
+        let entity = new_entity();
+        subscriber.send(move_event(&entity, Point(20, 0), Point(19, 0))).unwrap();
+        subscriber.send(move_event(&entity, Point(19, 0), Point(18, 0))).unwrap();
+        assert_eq!(1, receiver.try_iter().count());
+
+        subscriber.send(move_event(&entity, Point(18, 0), Point(NEAR_RADIUS, 0))).unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(1, received_events.len());
+        assert_eq!(Point(19, 0), received_events[0].from);
+        assert_eq!(Some(Point(NEAR_RADIUS, 0)), received_events[0].to);
+        assert_eq!(0, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn own_events_presences_and_departures_are_never_throttled() {
+        let entity = new_entity();
+        let clock = ManualClock(Rc::new(Cell::new(Instant::now())));
+        let (inner, receiver) = sync_sub::new_subscriber(entity.id);
+        let subscriber = LodSubscriber::new(inner, Point(0, 0), NEAR_RADIUS)
+            .with_ring(RING_RADIUS, Duration::from_millis(500))
+            .with_clock(clock);
+        let far_away = Point(RING_RADIUS * 2, 0);
+
+        walk(&subscriber, &entity, Point(0, 0), 5);
+        let other_entity = new_entity();
+        for _i in 0..5 {
+            subscriber.send(Rc::new(SpatialEvent{
+                from: far_away.clone(),
+                to: Some(far_away.clone()),
+                acting_entity: other_entity.clone(),
+                is_a_move: false,
+            })).unwrap();
+        }
+        subscriber.send(move_event(&other_entity, far_away.clone(), Point(far_away.0 + 1, 0))).unwrap();
+        subscriber.send(move_event(&other_entity, Point(far_away.0 + 1, 0), Point(far_away.0 + 2, 0))).unwrap();
+        subscriber.send(Rc::new(SpatialEvent{
+            from: Point(far_away.0 + 2, 0),
+            to: None,
+            acting_entity: other_entity.clone(),
+            is_a_move: true,
+        })).unwrap();
+
+        assert_eq!(5 + 5 + 1 + 1, receiver.try_iter().count());
+        assert_eq!(0, subscriber.number_of_pending());
+    }
+
+    #[test]
+    pub fn samples_what_a_spatial_channel_sends() {
+        let mut channel = SpatialChannel::new(MapDefinition::new(RING_RADIUS, 4));
+        let (subscriber, receiver, clock) = lod_subscriber();
+        channel.subscribe(subscriber.clone(), &Point(0, 0));
+        let (near, far) = (new_entity(), new_entity());
+
+        for x in 0..10 {
+            channel.publish(SpatialEvent{from: Point(x, 1), to: Some(Point(x + 1, 1)), acting_entity: near.clone(), is_a_move: true});
+            channel.publish(SpatialEvent{from: Point(x + 20, 1), to: Some(Point(x + 21, 1)), acting_entity: far.clone(), is_a_move: true});
+            clock.advance(Duration::from_millis(100));
+        }
+        clock.advance(Duration::from_millis(500));
+        subscriber.flush().unwrap();
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        let far_events: Vec<_> = received_events.iter().filter(|event| event.acting_entity == far).collect();
+        assert_eq!(10 + 3, received_events.len());
+        assert_eq!(Some(Point(30, 1)), far_events.last().unwrap().to);
+    }
+
+    fn lod_subscriber() -> (TestSubscriber, Receiver<SpatialEvent<TestEntity>>, ManualClock) {
+        let clock = ManualClock(Rc::new(Cell::new(Instant::now())));
+        let (inner, receiver) = sync_sub::new_subscriber(Uuid::new_v4());
+        let subscriber = LodSubscriber::new(inner, Point(0, 0), NEAR_RADIUS)
+            .with_ring(RING_RADIUS, Duration::from_millis(500))
+            .with_clock(clock.clone());
+        (subscriber, receiver, clock)
+    }
+
+    /// Moves the entity along x at the same instant, returns the number of moves.
+    fn walk(subscriber: &TestSubscriber, entity: &TestEntity, start: Point, number_of_moves: usize) -> usize {
+        let mut position = start;
+        for _i in 0..number_of_moves {
+            let destination = Point(position.0 + 1, position.1);
+            subscriber.send(move_event(entity, position, destination.clone())).unwrap();
+            position = destination;
+        }
+        number_of_moves
+    }
+
+    fn new_entity() -> TestEntity {
+        TestEntity{
+            id: Uuid::new_v4(),
+        }
+    }
+
+    fn move_event(entity: &TestEntity, from: Point, to: Point) -> Rc<SpatialEvent<TestEntity>> {
+        Rc::new(SpatialEvent{
+            from,
+            to: Some(to),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        })
+    }
+}
@@ -1,9 +1,13 @@
 use std::rc::Rc;
 use std::error::Error;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::hash::Hash;
 use core::fmt;
 use std::marker::PhantomData;
+use std::ops::AddAssign;
+use spatial::Zone;
 use uuid::Uuid;
 
 pub struct PubSubChannel<S, E>
@@ -21,33 +25,126 @@ impl <S, E> PubSubChannel<S, E> where S: Subscriber<E>{
     }
 
     pub fn subscribe(&mut self, subscriber: S) {
+        subscriber.on_subscribed();
         self.subscribers.push(subscriber);
     }
 
-    pub fn publish(&mut self, event: Rc<E>) -> Result<(), PubSubError>{
+    /// Removes the subscription of the given entity, if any, and returns it.
+    pub fn unsubscribe(&mut self, entity_id: &S::Id) -> Option<S> {
+        let position = self.subscribers.iter()
+            .position(|subscriber| subscriber.entity_id() == entity_id);
+
+        match position {
+            Some(index) => {
+                let subscriber = self.subscribers.remove(index);
+                subscriber.on_dropped(&PubSubError::Unsubscribed);
+                Some(subscriber)
+            },
+            None => None,
+        }
+    }
+
+    pub fn publish(&mut self, event: Rc<E>) -> DeliveryReport {
+        self.publish_filtered(event, |_entity_id| true)
+    }
+
+    /// Publishes the event to the subscribers accepted by the filter, the others keep their
+    /// subscription.
+    pub(crate) fn publish_filtered<F>(&mut self, event: Rc<E>, mut filter: F) -> DeliveryReport
+        where F: FnMut(&S::Id) -> bool {
+        let mut report = DeliveryReport::default();
+
         self.subscribers.retain(|subscriber|{
+            if !filter(subscriber.entity_id()) {
+                return true;
+            }
+
             match subscriber.send(event.clone()) {
-                Ok(retain) => retain,
+                Ok(retain) => {
+                    report.delivered += 1;
+
+                    if !retain {
+                        debug!("Subscriber {} unsubscribed", subscriber.entity_id());
+                        subscriber.on_dropped(&PubSubError::Unsubscribed);
+                        report.dropped += 1;
+                    }
+
+                    retain
+                },
                 Err(err) => {
-                    warn!("Subscriber dropped. Cause: {}", err);
+                    warn!("Subscriber {} dropped. Cause: {}", subscriber.entity_id(), err);
+                    subscriber.on_dropped(&err);
+                    report.dropped += 1;
                     false
                 }
             }
         });
 
-        Ok(())
+        report
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+/// What happened to a published event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryReport {
+    /// The number of subscribers that accepted the event.
+    pub delivered: usize,
+    /// The number of subscriptions dropped while publishing.
+    pub dropped: usize,
 }
 
+impl AddAssign for DeliveryReport {
+    fn add_assign(&mut self, other: DeliveryReport) {
+        self.delivered += other.delivered;
+        self.dropped += other.dropped;
+    }
+}
+
+/// The identifier of an entity, and of the subscription it owns.
+///
+/// Uuids are convenient for the demo, dense integer ids (eg slab keys) are cheaper to hash
+/// and compare on the hot path. Ids are ordered, to break ties between entities.
+pub trait EntityId: Eq + Ord + Hash + Clone + Debug + Display {}
+
+impl EntityId for Uuid {}
+impl EntityId for u32 {}
+impl EntityId for u64 {}
+impl EntityId for usize {}
+
 pub trait Subscriber<E>: Clone{
+    type Id: EntityId;
+
     /// Returns Ok(false) or Err to drop the subscription.
     fn send(&self, event: Rc<E>) -> Result<bool, PubSubError>;
-    fn entity_id(&self) -> &Uuid;
+    fn entity_id(&self) -> &Self::Id;
+
+    /// Lifecycle hook, called once the subscription is registered.
+    fn on_subscribed(&self) {}
+
+    /// Lifecycle hook, called when a spatial subscription is handed over to another zone.
+    fn on_zone_changed(&self, _from: &Zone, _to: &Zone) {}
+
+    /// Lifecycle hook, called once the subscription is dropped.
+    fn on_dropped(&self, _reason: &PubSubError) {}
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PubSubError{
+    /// The receiving end of the subscription was dropped.
     ReceiverIsGone,
+    /// The subscriber could not keep up and its queue is full.
+    QueueOverflow,
+    /// The subscription was removed on purpose, by the channel or by the subscriber.
+    Unsubscribed,
+    InvalidTopic(String),
 }
 
 impl Error for PubSubError{}
@@ -56,4 +153,117 @@ impl Display for PubSubError{
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+
+    #[test]
+    pub fn can_unsubscribe(){
+        let mut pub_sub = PubSubChannel::new();
+        let first = RecordingSubscriber::new(true);
+        let second = RecordingSubscriber::new(true);
+        pub_sub.subscribe(first.clone());
+        pub_sub.subscribe(second.clone());
+
+        assert!(pub_sub.unsubscribe(first.entity_id()).is_some());
+        assert!(pub_sub.unsubscribe(first.entity_id()).is_none());
+
+        let report = pub_sub.publish(Rc::new(()));
+
+        assert_eq!(DeliveryReport{ delivered: 1, dropped: 0 }, report);
+        assert_eq!(0, first.received.borrow().len());
+        assert_eq!(1, second.received.borrow().len());
+        assert_eq!(vec![PubSubError::Unsubscribed], *first.drop_reasons.borrow());
+        assert!(second.drop_reasons.borrow().is_empty());
+    }
+
+    #[test]
+    pub fn dropped_subscribers_are_told_why(){
+        let mut pub_sub = PubSubChannel::new();
+        let unsubscribing = RecordingSubscriber::new(false);
+        let failing = RecordingSubscriber::failing(PubSubError::QueueOverflow);
+        pub_sub.subscribe(unsubscribing.clone());
+        pub_sub.subscribe(failing.clone());
+
+        assert!(unsubscribing.is_subscribed.get());
+        assert!(failing.is_subscribed.get());
+
+        let report = pub_sub.publish(Rc::new(()));
+
+        assert_eq!(DeliveryReport{ delivered: 1, dropped: 2 }, report);
+        assert_eq!(vec![PubSubError::Unsubscribed], *unsubscribing.drop_reasons.borrow());
+        assert_eq!(vec![PubSubError::QueueOverflow], *failing.drop_reasons.borrow());
+    }
+
+    #[test]
+    pub fn publish_reports_dropped_subscribers(){
+        let mut pub_sub = PubSubChannel::new();
+        pub_sub.subscribe(RecordingSubscriber::new(true));
+        pub_sub.subscribe(RecordingSubscriber::new(false));
+
+        let report = pub_sub.publish(Rc::new(()));
+        assert_eq!(DeliveryReport{ delivered: 2, dropped: 1 }, report);
+        assert_eq!(1, pub_sub.len());
+
+        let report = pub_sub.publish(Rc::new(()));
+        assert_eq!(DeliveryReport{ delivered: 1, dropped: 0 }, report);
+    }
+
+    #[derive(Clone)]
+    struct RecordingSubscriber{
+        entity_id: Uuid,
+        result: Result<bool, PubSubError>,
+        received: Rc<RefCell<Vec<Rc<()>>>>,
+        is_subscribed: Rc<Cell<bool>>,
+        drop_reasons: Rc<RefCell<Vec<PubSubError>>>,
+    }
+
+    impl RecordingSubscriber{
+        fn new(keep: bool) -> RecordingSubscriber{
+            RecordingSubscriber::with_result(Ok(keep))
+        }
+
+        fn failing(error: PubSubError) -> RecordingSubscriber{
+            RecordingSubscriber::with_result(Err(error))
+        }
+
+        fn with_result(result: Result<bool, PubSubError>) -> RecordingSubscriber{
+            RecordingSubscriber{
+                entity_id: Uuid::new_v4(),
+                result,
+                received: Rc::new(RefCell::new(vec![])),
+                is_subscribed: Rc::new(Cell::new(false)),
+                drop_reasons: Rc::new(RefCell::new(vec![])),
+            }
+        }
+    }
+
+    impl Subscriber<()> for RecordingSubscriber{
+        type Id = Uuid;
+
+        fn send(&self, event: Rc<()>) -> Result<bool, PubSubError> {
+            if self.result.is_ok() {
+                self.received.borrow_mut().push(event);
+            }
+
+            self.result.clone()
+        }
+
+        fn entity_id(&self) -> &Uuid {
+            &self.entity_id
+        }
+
+        fn on_subscribed(&self) {
+            self.is_subscribed.set(true);
+        }
+
+        fn on_dropped(&self, reason: &PubSubError) {
+            self.is_subscribed.set(false);
+            self.drop_reasons.borrow_mut().push(reason.clone());
+        }
+    }
+}
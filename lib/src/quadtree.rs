@@ -0,0 +1,733 @@
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use spatial::Entity;
+use spatial::MapDefinition;
+use spatial::Point;
+use spatial::PublishReport;
+use spatial::SpatialEvent;
+use spatial::SpatialPubSub;
+use spatial::Zone;
+use spatial::ZoneChannel;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+/// When the leaves of a `QuadtreeChannel` split and merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuadtreeConfig {
+    /// A leaf holding more subscribers than this splits into four quadrants.
+    pub split_threshold: usize,
+    /// Four sibling leaves holding this many subscribers or fewer merge back.
+    /// Must be lower than `split_threshold`, or leaves would split and merge on every move.
+    pub merge_threshold: usize,
+    /// How many times a zone of the map can be split.
+    pub max_depth: usize,
+}
+
+impl Default for QuadtreeConfig {
+    fn default() -> QuadtreeConfig {
+        QuadtreeConfig{
+            split_threshold: 64,
+            merge_threshold: 16,
+            max_depth: 4,
+        }
+    }
+}
+
+/// A spatial backend where crowded zones split into quadrants, and sparse ones merge back.
+///
+/// Each zone of the map is the root of a quadtree whose leaves are `ZoneChannel`s. Two leaves
+/// see each other when they are closer than the width of the smaller one, and an event reaches
+/// the leaves in view of the leaf it happens in. As long as nothing splits, that is the zones
+/// next to each other, the same way the `SpatialChannel` publishes. In a crowd the leaves get
+/// smaller and so does their view, so each move reaches fewer subscribers.
+pub struct QuadtreeChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    map_definition: MapDefinition,
+    config: QuadtreeConfig,
+    /// The roots first, indexed like the zones of the map, then the quadrants.
+    nodes: Vec<Node<S, E>>,
+    /// The indexes of the nodes released by a merge, to be reused by the next split.
+    free_nodes: Vec<usize>,
+    subscriptions: HashMap<E::Id, QuadtreeSubscription>,
+    sequence: u64,
+    /// Reused by each publication, to avoid allocating.
+    dropped_subscriptions: Vec<E::Id>,
+    from_view: Vec<usize>,
+    to_view: Vec<usize>,
+}
+
+struct Node<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    /// Empty unless the node is a leaf.
+    channel: ZoneChannel<S, E>,
+    depth: usize,
+    parent: Option<usize>,
+    children: Option<[usize; 4]>,
+}
+
+/// Where a subscription lives. The position is needed to place it when its leaf splits.
+struct QuadtreeSubscription {
+    leaf: usize,
+    position: Point,
+}
+
+impl <S, E> QuadtreeChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    pub fn new(map_definition: MapDefinition, config: QuadtreeConfig) -> QuadtreeChannel<S, E> {
+        let mut nodes = vec![];
+
+        let zone_width = map_definition.zone_width();
+        let map_width_in_zones = map_definition.map_width_in_zones();
+        let layout = map_definition.layout();
+
+        // Curve layouts pad the map, the padding zones are never published to.
+        for index in 0..layout.capacity(map_width_in_zones) {
+            let (x, y) = layout.zone_coordinates(index, map_width_in_zones);
+            let area_start = Point(x * zone_width, y * zone_width);
+            let area_end = Point(area_start.0 + zone_width, area_start.1 + zone_width);
+
+            nodes.push(Node{
+                channel: ZoneChannel::new(Zone::new(area_start, area_end)),
+                depth: 0,
+                parent: None,
+                children: None,
+            });
+        }
+
+        QuadtreeChannel{
+            map_definition,
+            config,
+            nodes,
+            free_nodes: vec![],
+            subscriptions: HashMap::new(),
+            sequence: 0,
+            dropped_subscriptions: vec![],
+            from_view: vec![],
+            to_view: vec![],
+        }
+    }
+
+    pub fn number_of_leaves(&self) -> usize {
+        let number_of_nodes = self.nodes.len() - self.free_nodes.len();
+        let number_of_branches = self.nodes.iter().filter(|node| node.children.is_some()).count();
+        number_of_nodes - number_of_branches
+    }
+
+    /// The area of the leaf the subscription of the entity lives in.
+    pub fn subscription_area(&self, entity_id: &E::Id) -> Option<&Zone> {
+        self.subscriptions.get(entity_id)
+            .map(|subscription| self.nodes[subscription.leaf].channel.area())
+    }
+
+    pub fn subscribe(&mut self, subscriber: S, position: &Point) {
+        subscriber.on_subscribed();
+
+        let leaf = self.leaf_at(position);
+        self.subscriptions.insert(subscriber.entity_id().clone(), QuadtreeSubscription{
+            leaf,
+            position: position.clone(),
+        });
+
+        // The entities of the leaf itself are replayed by the zone channel.
+        let mut view = mem::take(&mut self.from_view);
+        view.clear();
+        self.collect_view(leaf, &mut view);
+        for &other_leaf in view.iter().filter(|&&other_leaf| other_leaf != leaf) {
+            self.nodes[other_leaf].channel.for_each_entity_changed_since(0, |entity_in_zone_event|{
+                let _res = // Nothing to do if it fails, result is ignored.
+                    subscriber.send(entity_in_zone_event.clone());
+            });
+        }
+        self.from_view = view;
+
+        self.nodes[leaf].channel.subscribe(subscriber, true);
+        self.split_if_crowded(leaf);
+    }
+
+    pub fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S> {
+        let subscription = self.subscriptions.remove(entity_id)?;
+        let subscriber_option = self.nodes[subscription.leaf].channel.unsubscribe(entity_id);
+        self.merge_if_sparse(subscription.leaf, &mut PublishReport::default());
+
+        subscriber_option
+    }
+
+    pub fn publish(&mut self, event: SpatialEvent<E>) -> PublishReport {
+        debug!("Publishing {}: {:?} => {:?}", event.acting_entity.id(), event.from, event.to);
+        let event = Rc::new(event);
+        let mut report = PublishReport::default();
+        self.sequence += 1;
+
+        let from_leaf = self.leaf_at(&event.from);
+        let to_leaf = event.to.as_ref().map(|destination| self.leaf_at(destination));
+
+        // Take the subscription of the acting entity out of its leaf if it has to follow it.
+        let mut handed_off_subscription = None;
+        let mut owner_leaf = from_leaf;
+        if let Some(subscription) = self.subscriptions.get_mut(event.acting_entity.id()) {
+            if event.is_a_move {
+                if let Some(ref destination) = event.to {
+                    subscription.position.clone_from(destination);
+                }
+
+                if to_leaf != Some(subscription.leaf) {
+                    owner_leaf = subscription.leaf;
+                    handed_off_subscription = self.nodes[owner_leaf].channel.take_subscriber(event.acting_entity.id());
+                }
+            }
+        }
+
+        let mut from_view = mem::take(&mut self.from_view);
+        let mut to_view = mem::take(&mut self.to_view);
+        from_view.clear();
+        to_view.clear();
+
+        // Publish in the leaves that were already in range, then in the ones that are now in range.
+        self.collect_view(from_leaf, &mut from_view);
+        if let Some(to_leaf) = to_leaf {
+            self.collect_view(to_leaf, &mut to_view);
+        }
+
+        let newly_in_range = to_view.iter().filter(|leaf| !from_view.contains(leaf));
+        for &leaf in from_view.iter().chain(newly_in_range) {
+            let delivery = self.nodes[leaf].channel.publish(event.clone(), self.sequence, &mut self.dropped_subscriptions);
+            report.record_delivery(delivery);
+        }
+
+        if handed_off_subscription.is_some() && owner_leaf != from_leaf {
+            from_view.clear();
+            self.collect_view(owner_leaf, &mut from_view);
+        }
+
+        self.from_view = from_view;
+        self.to_view = to_view;
+
+        if let Some(subscriber) = handed_off_subscription {
+            self.hand_off(subscriber, &event, owner_leaf, to_leaf, &mut report);
+        }
+
+        for dropped_subscription in self.dropped_subscriptions.drain(..) {
+            // A new subscription of the same entity may live in another leaf.
+            let is_still_subscribed = match self.subscriptions.get(&dropped_subscription) {
+                Some(subscription) => self.nodes[subscription.leaf].channel.subscriber(&dropped_subscription).is_some(),
+                None => false,
+            };
+
+            if !is_still_subscribed {
+                self.subscriptions.remove(&dropped_subscription);
+            }
+        }
+
+        if let Some(to_leaf) = to_leaf {
+            self.split_if_crowded(to_leaf);
+        }
+
+        if self.nodes[from_leaf].children.is_none() {
+            self.merge_if_sparse(from_leaf, &mut report);
+        }
+
+        report
+    }
+
+    /// Sends its own event to a subscription taken out of its leaf, then moves it to the leaf of
+    /// the destination and replays the entities now in view. Expects the views of both leaves
+    /// in `from_view` and `to_view`.
+    fn hand_off(&mut self, subscriber: S, event: &Rc<SpatialEvent<E>>, owner_leaf: usize, to_leaf: Option<usize>,
+                report: &mut PublishReport) {
+        let entity_id = event.acting_entity.id();
+
+        match subscriber.send(event.clone()) {
+            Ok(_retain) => {
+                report.fan_out += 1;
+            },
+            Err(err) => {
+                subscriber.on_dropped(&err);
+                report.subscribers_dropped += 1;
+                self.subscriptions.remove(entity_id);
+                return;
+            }
+        }
+
+        let to_leaf = match to_leaf {
+            Some(to_leaf) => to_leaf,
+            None => {
+                // The entity left the map, so does its subscription.
+                subscriber.on_dropped(&PubSubError::Unsubscribed);
+                report.subscribers_dropped += 1;
+                self.subscriptions.remove(entity_id);
+                return;
+            }
+        };
+
+        let owner_view = &self.from_view;
+        for &leaf in self.to_view.iter().filter(|leaf| !owner_view.contains(leaf)) {
+            self.nodes[leaf].channel.for_each_entity_changed_since(0, |entity_in_zone_event|{
+                if entity_in_zone_event.acting_entity.id() != entity_id {
+                    let _res = // Nothing to do if it fails, result is ignored.
+                        subscriber.send(entity_in_zone_event.clone());
+                    report.entities_in_range_notifications += 1;
+                }
+            });
+        }
+
+        if let Some(subscription) = self.subscriptions.get_mut(entity_id) {
+            subscription.leaf = to_leaf;
+        }
+
+        self.nodes[to_leaf].channel.subscribe(subscriber.clone(), false);
+        subscriber.on_zone_changed(self.nodes[owner_leaf].channel.area(), self.nodes[to_leaf].channel.area());
+        report.changed_zone = true;
+    }
+
+    fn split_if_crowded(&mut self, leaf: usize) {
+        let (area, depth) = {
+            let node = &self.nodes[leaf];
+            let area = node.channel.area();
+            let is_crowded = node.channel.number_of_subscribers() > self.config.split_threshold;
+            let is_splittable = node.depth < self.config.max_depth && area.end().0 - area.start().0 >= 2;
+
+            if node.children.is_some() || !is_crowded || !is_splittable {
+                return;
+            }
+
+            (area.clone(), node.depth)
+        };
+
+        debug!("Splitting {:?}", area);
+        let (start, end) = (area.start(), area.end());
+        let middle = Point(start.0 + (end.0 - start.0) / 2, start.1 + (end.1 - start.1) / 2);
+        let quadrants = [
+            Zone::new(start.clone(), middle.clone()),
+            Zone::new(Point(middle.0, start.1), Point(end.0, middle.1)),
+            Zone::new(Point(start.0, middle.1), Point(middle.0, end.1)),
+            Zone::new(middle.clone(), end.clone()),
+        ];
+
+        let mut children = [0; 4];
+        for (child, quadrant) in children.iter_mut().zip(quadrants.iter()) {
+            *child = self.add_node(Node{
+                channel: ZoneChannel::new(quadrant.clone()),
+                depth: depth + 1,
+                parent: Some(leaf),
+                children: None,
+            });
+        }
+
+        let subscribers = self.nodes[leaf].channel.take_subscribers();
+        let entities = self.nodes[leaf].channel.take_entities();
+        self.nodes[leaf].children = Some(children);
+
+        for (entity_id, entity_in_zone) in entities {
+            let position = entity_in_zone.event.to.clone().unwrap_or_else(|| entity_in_zone.event.from.clone());
+            let child = self.child_at(&children, &position);
+            self.nodes[child].channel.insert_entity_in_zone(entity_id, entity_in_zone);
+        }
+
+        for subscriber in subscribers {
+            let child = match self.subscriptions.get_mut(subscriber.entity_id()) {
+                Some(subscription) => {
+                    let child = quadrant_at(&self.nodes, &children, &subscription.position);
+                    subscription.leaf = child;
+                    child
+                },
+                None => children[0],
+            };
+
+            // The view of the subscriber shrinks, nothing to replay.
+            subscriber.on_zone_changed(&area, self.nodes[child].channel.area());
+            self.nodes[child].channel.subscribe(subscriber, false);
+        }
+
+        for &child in children.iter() {
+            self.split_if_crowded(child);
+        }
+    }
+
+    fn merge_if_sparse(&mut self, leaf: usize, report: &mut PublishReport) {
+        let parent = match self.nodes[leaf].parent {
+            Some(parent) => parent,
+            None => return,
+        };
+
+        let children = match self.nodes[parent].children {
+            Some(children) => children,
+            None => return,
+        };
+
+        let is_mergeable = children.iter().all(|&child| self.nodes[child].children.is_none());
+        let number_of_subscribers: usize = children.iter()
+            .map(|&child| self.nodes[child].channel.number_of_subscribers())
+            .sum();
+
+        if !is_mergeable || number_of_subscribers > self.config.merge_threshold {
+            return;
+        }
+
+        let area = self.nodes[parent].channel.area().clone();
+        debug!("Merging {:?}", area);
+
+        // The merged leaf sees farther than its quadrants did: what is now in view of its
+        // subscribers and of its neighbours is replayed.
+        let mut views = vec![];
+        for &child in children.iter() {
+            let mut view = vec![];
+            self.collect_view(child, &mut view);
+            views.push(view);
+        }
+
+        let mut neighbours = vec![];
+        self.collect_view_of(&area, &mut neighbours);
+        neighbours.retain(|neighbour| !children.contains(neighbour));
+
+        for &neighbour in &neighbours {
+            for (&child, view) in children.iter().zip(views.iter()) {
+                if !view.contains(&neighbour) {
+                    self.replay(child, neighbour, report);
+                }
+            }
+        }
+
+        let mut merged_subscribers = vec![];
+        for (&child, view) in children.iter().zip(views.iter()) {
+            for &neighbour in &neighbours {
+                if !view.contains(&neighbour) {
+                    self.replay(neighbour, child, report);
+                }
+            }
+
+            for (entity_id, entity_in_zone) in self.nodes[child].channel.take_entities() {
+                self.nodes[parent].channel.insert_entity_in_zone(entity_id, entity_in_zone);
+            }
+
+            for subscriber in self.nodes[child].channel.take_subscribers() {
+                subscriber.on_zone_changed(self.nodes[child].channel.area(), &area);
+                merged_subscribers.push(subscriber);
+            }
+
+            self.free_nodes.push(child);
+        }
+
+        self.nodes[parent].children = None;
+        for subscriber in merged_subscribers {
+            if let Some(subscription) = self.subscriptions.get_mut(subscriber.entity_id()) {
+                subscription.leaf = parent;
+            }
+
+            self.nodes[parent].channel.subscribe(subscriber, false);
+        }
+
+        self.merge_if_sparse(parent, report);
+    }
+
+    /// Sends the entities of a leaf to the subscribers of another.
+    fn replay(&self, entities_leaf: usize, subscribers_leaf: usize, report: &mut PublishReport) {
+        let subscribers = &self.nodes[subscribers_leaf].channel;
+        self.nodes[entities_leaf].channel.for_each_entity_changed_since(0, |entity_in_zone_event|{
+            subscribers.for_each_subscriber(|subscriber|{
+                let _res = // Nothing to do if it fails, result is ignored.
+                    subscriber.send(entity_in_zone_event.clone());
+                report.entities_in_range_notifications += 1;
+            });
+        });
+    }
+
+    fn add_node(&mut self, node: Node<S, E>) -> usize {
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn leaf_at(&self, point: &Point) -> usize {
+        let zone_width = self.map_definition.zone_width();
+        let mut node = self.map_definition.zone_index(point.0 / zone_width, point.1 / zone_width);
+
+        while let Some(ref children) = self.nodes[node].children {
+            node = self.child_at(children, point);
+        }
+
+        node
+    }
+
+    fn child_at(&self, children: &[usize; 4], point: &Point) -> usize {
+        quadrant_at(&self.nodes, children, point)
+    }
+
+    /// Pushes the leaves in view of the given leaf, itself included, to the view.
+    fn collect_view(&self, leaf: usize, view: &mut Vec<usize>) {
+        let area = self.nodes[leaf].channel.area().clone();
+        self.collect_view_of(&area, view);
+    }
+
+    /// Pushes the leaves closer to the area than the width of the smaller of both.
+    fn collect_view_of(&self, area: &Zone, view: &mut Vec<usize>) {
+        let zone_width = self.map_definition.zone_width();
+        let map_width = zone_width * self.map_definition.map_width_in_zones();
+        let range = surroundings(area, width(area), map_width);
+
+        for x in range.start().0 / zone_width..=(range.end().0 - 1) / zone_width {
+            for y in range.start().1 / zone_width..=(range.end().1 - 1) / zone_width {
+                self.collect_leaves_in(self.map_definition.zone_index(x, y), area, &range, view);
+            }
+        }
+    }
+
+    fn collect_leaves_in(&self, node: usize, area: &Zone, range: &Zone, view: &mut Vec<usize>) {
+        let node_area = self.nodes[node].channel.area();
+        if !intersects(node_area, range) {
+            return;
+        }
+
+        match self.nodes[node].children {
+            Some(children) => {
+                for &child in children.iter() {
+                    self.collect_leaves_in(child, area, range, view);
+                }
+            },
+            None => {
+                let map_width = self.map_definition.zone_width() * self.map_definition.map_width_in_zones();
+                if intersects(&surroundings(node_area, width(node_area), map_width), area) {
+                    view.push(node);
+                }
+            },
+        }
+    }
+}
+
+impl <S, E> SpatialPubSub<S, E> for QuadtreeChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    fn subscribe(&mut self, subscriber: S, position: &Point) {
+        QuadtreeChannel::subscribe(self, subscriber, position)
+    }
+
+    fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S> {
+        QuadtreeChannel::unsubscribe(self, entity_id)
+    }
+
+    fn publish(&mut self, event: SpatialEvent<E>) -> PublishReport {
+        QuadtreeChannel::publish(self, event)
+    }
+}
+
+fn quadrant_at<S, E>(nodes: &[Node<S, E>], children: &[usize; 4], point: &Point) -> usize
+    where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    for &child in children.iter() {
+        if nodes[child].channel.area().point_is_in(point) {
+            return child;
+        }
+    }
+
+    panic!("Point {:?} is outside of the quadrants", point)
+}
+
+fn width(area: &Zone) -> usize {
+    area.end().0 - area.start().0
+}
+
+/// The area extended by the margin on every side, up to the edges of the map.
+fn surroundings(area: &Zone, margin: usize, map_width: usize) -> Zone {
+    Zone::new(
+        Point(area.start().0.saturating_sub(margin), area.start().1.saturating_sub(margin)),
+        Point(min(area.end().0 + margin, map_width), min(area.end().1 + margin, map_width)),
+    )
+}
+
+fn intersects(area: &Zone, other: &Zone) -> bool {
+    area.start().0 < other.end().0 && other.start().0 < area.end().0
+        && area.start().1 < other.end().1 && other.start().1 < area.end().1
+}
+
+#[cfg(test)]
+mod tests{
+    use crossbeam_channel::Receiver;
+    use spatial::SpatialChannel;
+    use subscriber_tests::TestEntity;
+    use super::*;
+    use sync_sub;
+    use sync_sub::SyncSubscriber;
+    use uuid::Uuid;
+
+    const ZONE_WIDTH: usize = 16;
+
+    type TestSubscriber = SyncSubscriber<SpatialEvent<TestEntity>>;
+
+    #[test]
+    pub fn subscription_follows_moving_entity() {
+        let mut channel = test_channel(QuadtreeConfig::default());
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let (subscriber, receiver) = sync_sub::new_subscriber(entity.id);
+
+        let mut position = Point(0, 0);
+        channel.subscribe(subscriber, &position);
+
+        let number_of_events = ZONE_WIDTH * 10;
+        for _i in 0..number_of_events {
+            let destination = Point(position.0 + 1, position.1);
+            channel.publish(move_event(&entity, position, destination.clone()));
+            position = destination;
+        }
+
+        assert_eq!(number_of_events, receiver.try_iter().count());
+        assert_eq!(Some(&Zone::new(Point(ZONE_WIDTH * 10, 0), Point(ZONE_WIDTH * 11, ZONE_WIDTH))),
+                   channel.subscription_area(&entity.id));
+    }
+
+    #[test]
+    pub fn crowded_leaves_split_and_sparse_ones_merge() {
+        let mut channel = test_channel(QuadtreeConfig{
+            split_threshold: 4,
+            merge_threshold: 2,
+            max_depth: 2,
+        });
+        let initial_number_of_leaves = channel.number_of_leaves();
+
+        let mut crowd = vec![];
+        for i in 0..5 {
+            crowd.push(subscribe(&mut channel, Point(ZONE_WIDTH + i * 3, ZONE_WIDTH + i * 3)));
+        }
+        assert_eq!(initial_number_of_leaves + 3, channel.number_of_leaves());
+
+        for (entity_id, _receiver) in crowd.drain(..3) {
+            assert!(channel.unsubscribe(&entity_id).is_some());
+        }
+        assert_eq!(initial_number_of_leaves, channel.number_of_leaves());
+    }
+
+    #[test]
+    pub fn splits_reduce_the_fan_out_in_crowds() {
+        let map = MapDefinition::new(ZONE_WIDTH, ZONE_WIDTH);
+        let mut grid: SpatialChannel<TestSubscriber, TestEntity> = SpatialChannel::new(map.clone());
+        let mut quadtree = QuadtreeChannel::new(map, QuadtreeConfig::default());
+
+        // Two entities on each unit of the same zone.
+        let mut crowd = vec![];
+        for i in 0..ZONE_WIDTH * ZONE_WIDTH * 2 {
+            let entity = TestEntity{
+                id: Uuid::new_v4(),
+            };
+            let position = Point(ZONE_WIDTH + i % ZONE_WIDTH, ZONE_WIDTH + i / ZONE_WIDTH % ZONE_WIDTH);
+            let (subscriber, receiver) = sync_sub::new_subscriber(entity.id);
+            grid.subscribe(subscriber.clone(), &position);
+            quadtree.subscribe(subscriber, &position);
+            crowd.push((entity, position, receiver));
+        }
+
+        // Each of them moves in place.
+        let (mut grid_fan_out, mut quadtree_fan_out) = (0, 0);
+        for (entity, position, receiver) in crowd.iter() {
+            grid_fan_out += grid.publish(move_event(entity, position.clone(), position.clone())).fan_out;
+            quadtree_fan_out += quadtree.publish(move_event(entity, position.clone(), position.clone())).fan_out;
+            receiver.try_iter().count();
+        }
+
+        assert_eq!(crowd.len() * crowd.len(), grid_fan_out);
+        assert!(quadtree_fan_out * 2 < grid_fan_out, "Fan-out of {} against {} for the grid", quadtree_fan_out, grid_fan_out);
+    }
+
+    #[test]
+    pub fn split_leaves_only_see_the_leaves_next_to_them() {
+        let mut channel = test_channel(QuadtreeConfig{
+            split_threshold: 2,
+            merge_threshold: 1,
+            max_depth: 4,
+        });
+
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let (entity_subscriber, entity_receiver) = sync_sub::new_subscriber(entity.id);
+        let entity_position = Point(ZONE_WIDTH + 1, ZONE_WIDTH + 1);
+        channel.subscribe(entity_subscriber, &entity_position);
+        let far_entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let (far_subscriber, far_receiver) = sync_sub::new_subscriber(far_entity.id);
+        let far_position = Point(ZONE_WIDTH + 11, ZONE_WIDTH + 1);
+        channel.subscribe(far_subscriber, &far_position);
+        let (_next_id, next_receiver) = subscribe(&mut channel, Point(ZONE_WIDTH + 2, ZONE_WIDTH + 1));
+        let _crowd = subscribe(&mut channel, Point(ZONE_WIDTH + 3, ZONE_WIDTH + 1));
+        assert_eq!(Some(&Zone::new(Point(ZONE_WIDTH, ZONE_WIDTH), Point(ZONE_WIDTH + 2, ZONE_WIDTH + 2))),
+                   channel.subscription_area(&entity.id));
+
+        // The next leaf sees the move, the far one does not, and neither does the entity see it.
+        channel.publish(move_event(&entity, entity_position.clone(), Point(entity_position.0, entity_position.1 + 1)));
+        let received: Vec<_> = next_receiver.try_iter().collect();
+        assert_eq!(1, received.len());
+        assert_eq!(entity, received[0].acting_entity);
+        assert_eq!(0, far_receiver.try_iter().count());
+
+        entity_receiver.try_iter().count();
+        channel.publish(move_event(&far_entity, far_position.clone(), Point(far_position.0, far_position.1 + 1)));
+        assert_eq!(0, entity_receiver.try_iter().count());
+    }
+
+    #[test]
+    pub fn merged_subscribers_are_warned_of_entities_now_in_range() {
+        let mut channel = test_channel(QuadtreeConfig{
+            split_threshold: 2,
+            merge_threshold: 1,
+            max_depth: 1,
+        });
+
+        // In the next zone, crowded too.
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let entity_position = Point(ZONE_WIDTH * 2 + 4, ZONE_WIDTH + 1);
+        channel.publish(move_event(&entity, entity_position.clone(), entity_position.clone()));
+        let _next_crowd = [
+            subscribe(&mut channel, Point(ZONE_WIDTH * 2 + 5, ZONE_WIDTH + 1)),
+            subscribe(&mut channel, Point(ZONE_WIDTH * 2 + 6, ZONE_WIDTH + 1)),
+            subscribe(&mut channel, Point(ZONE_WIDTH * 2 + 7, ZONE_WIDTH + 1)),
+        ];
+
+        let (subscriber_id, receiver) = subscribe(&mut channel, Point(ZONE_WIDTH + 1, ZONE_WIDTH + 1));
+        let crowd = vec![
+            subscribe(&mut channel, Point(ZONE_WIDTH + 2, ZONE_WIDTH + 1)),
+            subscribe(&mut channel, Point(ZONE_WIDTH + 3, ZONE_WIDTH + 1)),
+        ];
+        assert_eq!(1, receiver.try_iter().count(), "Warned of the entity when subscribing");
+
+        // Both zones split, so the entity is out of view.
+        channel.publish(move_event(&entity, entity_position.clone(), Point(entity_position.0, entity_position.1 + 1)));
+        assert_eq!(0, receiver.try_iter().count());
+        assert_eq!(Some(&Zone::new(Point(ZONE_WIDTH, ZONE_WIDTH), Point(ZONE_WIDTH + 8, ZONE_WIDTH + 8))),
+                   channel.subscription_area(&subscriber_id));
+
+        for (entity_id, _receiver) in crowd {
+            channel.unsubscribe(&entity_id);
+        }
+
+        let received: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(1, received.len());
+        assert_eq!(entity, received[0].acting_entity);
+        assert_eq!(Some(Point(entity_position.0, entity_position.1 + 1)), received[0].to);
+    }
+
+    fn subscribe(channel: &mut QuadtreeChannel<TestSubscriber, TestEntity>, position: Point)
+                 -> (Uuid, Receiver<SpatialEvent<TestEntity>>) {
+        let entity_id = Uuid::new_v4();
+        let (subscriber, receiver) = sync_sub::new_subscriber(entity_id);
+        channel.subscribe(subscriber, &position);
+        (entity_id, receiver)
+    }
+
+    fn test_channel(config: QuadtreeConfig) -> QuadtreeChannel<TestSubscriber, TestEntity> {
+        QuadtreeChannel::new(MapDefinition::new(ZONE_WIDTH, ZONE_WIDTH), config)
+    }
+
+    fn move_event(entity: &TestEntity, from: Point, to: Point) -> SpatialEvent<TestEntity> {
+        SpatialEvent{
+            from,
+            to: Some(to),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        }
+    }
+}
@@ -1,17 +1,73 @@
+use indexmap::IndexMap;
+use layout::ZoneLayout;
+use quadtree::QuadtreeChannel;
+use quadtree::QuadtreeConfig;
+use pub_sub::DeliveryReport;
+use pub_sub::EntityId;
+use pub_sub::PubSubError;
 use pub_sub::Subscriber;
 use rand::prelude::*;
-use std::cell::RefCell;
+use std::cmp::max;
+use std::cmp::min;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::ops::Range;
 use std::rc::Rc;
-use uuid::Uuid;
 
-pub struct SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity+Clone {
+/// The contract of a spatial backend: a subscription follows its entity and receives the events
+/// published in range of it. See `Partitioning` to pick a backend.
+pub trait SpatialPubSub<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    /// The subscriber is warned of the entities already in view.
+    fn subscribe(&mut self, subscriber: S, position: &Point);
+
+    /// Removes the subscription of the given entity, if any, and returns it.
+    fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S>;
+
+    fn publish(&mut self, event: SpatialEvent<E>) -> PublishReport;
+}
+
+/// How a map is partitioned into channels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    /// Fixed size zones, see `SpatialChannel`.
+    #[default]
+    Grid,
+    /// Zones split into quadrants when crowded, see `QuadtreeChannel`.
+    Quadtree(QuadtreeConfig),
+}
+
+impl Partitioning {
+    pub fn build<S, E>(self, map_definition: MapDefinition) -> Box<dyn SpatialPubSub<S, E>>
+        where S: Subscriber<SpatialEvent<E>, Id=E::Id> + 'static, E: Entity + Clone + 'static {
+        match self {
+            Partitioning::Grid => Box::new(SpatialChannel::new(map_definition)),
+            Partitioning::Quadtree(config) => Box::new(QuadtreeChannel::new(map_definition, config)),
+        }
+    }
+}
+
+/// The number of zones a subscription remembers having seen, to avoid replaying them.
+const RECENTLY_LEFT_ZONES: usize = 16;
+
+pub struct SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
     map_definition: MapDefinition,
     channels: Vec<ZoneChannel<S, E>>,
+    /// How far past the border of its zone an entity goes before its subscription follows.
+    hysteresis_margin: usize,
+    /// Incremented by each publication, to know what a subscriber has already seen.
+    sequence: u64,
+    subscriptions: HashMap<E::Id, SubscriptionState>,
+    /// Reused by each publication, to forget the state of the dropped subscriptions.
+    dropped_subscriptions: Vec<E::Id>,
 }
 
-impl <S, E> SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity+Clone{
+/// Where a subscription lives, and what it saw of the zones it recently lost sight of.
+struct SubscriptionState {
+    zone: (usize, usize),
+    /// The zones out of view, with the sequence of the last publication seen in them. Oldest first.
+    left_zones: Vec<((usize, usize), u64)>,
+}
+
+impl <S, E> SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone{
     pub fn new(map_definition: MapDefinition)
                -> SpatialChannel<S, E>
     {
@@ -19,114 +75,274 @@ impl <S, E> SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity
 
         let zone_width = map_definition.zone_width;
         let map_width_in_zones = map_definition.map_width_in_zones;
+        let layout = map_definition.layout;
 
-        for x in 0..map_width_in_zones {
-            for y in 0..map_width_in_zones {
-                let area_start = Point(x * zone_width, y * zone_width);
-                let area_end = Point(area_start.0 + zone_width, area_start.1 + zone_width);
-                let area = Zone(area_start, area_end);
+        // Curve layouts pad the map, the padding zones are never published to.
+        for index in 0..layout.capacity(map_width_in_zones) {
+            let (x, y) = layout.zone_coordinates(index, map_width_in_zones);
+            let area_start = Point(x * zone_width, y * zone_width);
+            let area_end = Point(area_start.0 + zone_width, area_start.1 + zone_width);
+            let area = Zone(area_start, area_end);
 
-                channels.push(ZoneChannel::new(area));
-            }
+            channels.push(ZoneChannel::new(area));
         }
 
         SpatialChannel{
             channels,
             map_definition,
+            hysteresis_margin: 0,
+            sequence: 0,
+            subscriptions: HashMap::new(),
+            dropped_subscriptions: vec![],
         }
     }
 
-    pub fn publish(&mut self, event: SpatialEvent<E>) {
+    /// Keeps a subscription in its zone until the entity is more than `margin` past the border,
+    /// so an entity pacing along a border does not hand its subscription back and forth.
+    /// The margin must be smaller than the zone width.
+    pub fn with_hysteresis_margin(mut self, margin: usize) -> SpatialChannel<S, E> {
+        assert!(margin < self.map_definition.zone_width, "The hysteresis margin must be smaller than the zone width");
+        self.hysteresis_margin = margin;
+        self
+    }
+
+    /// Publishing a move in the zones it was already visible from does not allocate, except for
+    /// the event itself which is shared by every subscriber.
+    pub fn publish(&mut self, event: SpatialEvent<E>) -> PublishReport {
         debug!("Publishing {}: {:?} => {:?}", event.acting_entity.id(), event.from, event.to);
         let event = Rc::new(event);
-        let zone_width = self.map_definition.zone_width;
-        let map_width_in_zones = self.map_definition.map_width_in_zones;
-
-        // Publish in the areas that were already in range.
-        let mut from_indexes = HashSet::new();
-        let mut entity_subscription_cell: RefCell<Option<S>> = RefCell::new(None);
-        compute_indexes_for_zones_in_range(&event.from, zone_width, map_width_in_zones, |index|{
-            from_indexes.insert(index);
-
-            if let Some(channel) =  self.channels.get_mut(index) {
-                if let Some(dropped_subscription) = channel.publish(event.clone()) {
-                    entity_subscription_cell.replace(Some(dropped_subscription));
-                }
+        let mut report = PublishReport::default();
+        self.sequence += 1;
+
+        // Take the subscription of the acting entity out of its zone if it has to follow it.
+        let mut handed_off_subscription = None;
+        if let Some(state) = self.subscriptions.get(event.acting_entity.id()) {
+            let destination_zone = match event.to {
+                Some(ref destination) => Some(zone_coordinates_for_point(destination, &self.map_definition)),
+                None => None,
             };
-        });
+
+            if event.is_a_move && destination_zone != Some(state.zone) {
+                let channel = &mut self.channels[self.map_definition.zone_index(state.zone.0, state.zone.1)];
+                let is_past_the_margin = match event.to {
+                    Some(ref destination) => channel.area.distance_to(destination) > self.hysteresis_margin,
+                    None => true,
+                };
+
+                if is_past_the_margin {
+                    handed_off_subscription = channel.take_subscriber(event.acting_entity.id());
+                }
+            }
+        }
+
+        // Publish in the areas that were already in range, the same view the subscriptions have
+        // from their zone.
+        let from_zone = zone_coordinates_for_point(&event.from, &self.map_definition);
+        let from_range = ZoneRange::around_zone(from_zone, &self.map_definition);
+        for (x, y) in from_range.zones() {
+            let index = self.map_definition.zone_index(x, y);
+            let delivery = self.channels[index].publish(event.clone(), self.sequence, &mut self.dropped_subscriptions);
+            report.record_delivery(delivery);
+        }
 
         if let Some(ref destination) = event.to {
             // Publish in the areas that are now in range.
-            compute_indexes_for_zones_in_range(destination, zone_width, map_width_in_zones, |index|{
-                if !from_indexes.contains(&index) { // Exclude the zones that were already in range.
-                    if let Some(channel) =  self.channels.get_mut(index) {
-                        if let Some(_dropped_subscription) = channel.publish(event.clone()){
-                            panic!() // No subscription should be dropped in the new areas in visible range.
-                        }
+            let to_zone = zone_coordinates_for_point(destination, &self.map_definition);
+            let to_range = ZoneRange::around_zone(to_zone, &self.map_definition);
+            for (x, y) in to_range.zones() {
+                if from_range.contains(x, y) { // Exclude the zones that were already in range.
+                    continue;
+                }
 
-                        if let Some(dropped_subscriber) = entity_subscription_cell.get_mut() {
-                            channel.for_each_entity_in_zone(|entity, position|{
-                                let entity_in_zone_event = SpatialEvent{
-                                    from: position.clone(),
-                                    to: Some(position.clone()),
-                                    acting_entity: entity.clone(),
-                                    is_a_move: false,
-                                };
-
-                                let _res = // Nothing to do if it fails, result is ignored.
-                                    dropped_subscriber.send(Rc::new(entity_in_zone_event));
-                            })
-                        }
-                    }
+                let index = self.map_definition.zone_index(x, y);
+                let delivery = self.channels[index].publish(event.clone(), self.sequence, &mut self.dropped_subscriptions);
+                report.record_delivery(delivery);
+            }
+        }
+
+        if let Some(subscriber) = handed_off_subscription {
+            self.hand_off(subscriber, &event, &mut report);
+        }
+
+        for dropped_subscription in self.dropped_subscriptions.drain(..) {
+            // A new subscription of the same entity may live in another zone.
+            let is_still_subscribed = match self.subscriptions.get(&dropped_subscription) {
+                Some(state) => {
+                    let zone_index = self.map_definition.zone_index(state.zone.0, state.zone.1);
+                    self.channels[zone_index].subscriber(&dropped_subscription).is_some()
+                },
+                None => false,
+            };
+
+            if !is_still_subscribed {
+                self.subscriptions.remove(&dropped_subscription);
+            }
+        }
+
+        report
+    }
+
+    /// Sends its own event to a subscription taken out of its zone, then moves it to the zone
+    /// of the destination and replays the entities it did not see yet.
+    fn hand_off(&mut self, subscriber: S, event: &Rc<SpatialEvent<E>>, report: &mut PublishReport) {
+        let entity_id = event.acting_entity.id();
+
+        match subscriber.send(event.clone()) {
+            Ok(true) => {
+                report.fan_out += 1;
+            },
+            Ok(false) => {
+                report.fan_out += 1;
+                subscriber.on_dropped(&PubSubError::Unsubscribed);
+                report.subscribers_dropped += 1;
+                self.subscriptions.remove(entity_id);
+                return;
+            },
+            Err(err) => {
+                subscriber.on_dropped(&err);
+                report.subscribers_dropped += 1;
+                self.subscriptions.remove(entity_id);
+                return;
+            }
+        }
+
+        let destination = match event.to {
+            Some(ref destination) => destination,
+            None => {
+                // The entity left the map, so does its subscription.
+                subscriber.on_dropped(&PubSubError::Unsubscribed);
+                report.subscribers_dropped += 1;
+                self.subscriptions.remove(entity_id);
+                return;
+            }
+        };
+
+        let to_zone = zone_coordinates_for_point(destination, &self.map_definition);
+        let sequence = self.sequence;
+        let map_definition = &self.map_definition;
+        let channels = &mut self.channels;
+        let state = self.subscriptions.get_mut(entity_id)
+            .expect("A subscription taken out of its zone has a state");
+
+        let from_zone = state.zone;
+        let from_view = ZoneRange::around_zone(from_zone, map_definition);
+        let to_view = ZoneRange::around_zone(to_zone, map_definition);
+
+        for (x, y) in from_view.zones() {
+            if !to_view.contains(x, y) {
+                state.left_zones.retain(|&(zone, _sequence)| zone != (x, y));
+                if state.left_zones.len() == RECENTLY_LEFT_ZONES {
+                    state.left_zones.remove(0);
                 }
-            });
 
-            if let Some(dropped_subscriber) = entity_subscription_cell.replace(None) {
-                self.do_subscribe(dropped_subscriber, destination, false);
-            } else {
-                // TODO Panic? Requires a change in the API because it means every entity.rs has a matching subscription.
+                state.left_zones.push(((x, y), sequence));
+            }
+        }
+
+        for (x, y) in to_view.zones() {
+            if from_view.contains(x, y) {
+                continue;
             }
+
+            // Only replay what changed since the subscriber last saw the zone, if it did.
+            let last_seen = match state.left_zones.iter().position(|&(zone, _sequence)| zone == (x, y)) {
+                Some(position) => state.left_zones.remove(position).1,
+                None => 0,
+            };
+
+            let channel = &channels[map_definition.zone_index(x, y)];
+            channel.for_each_entity_changed_since(last_seen, |entity_in_zone_event|{
+                if entity_in_zone_event.acting_entity.id() != entity_id {
+                    let _res = // Nothing to do if it fails, result is ignored.
+                        subscriber.send(entity_in_zone_event.clone());
+                    report.entities_in_range_notifications += 1;
+                }
+            });
         }
+
+        state.zone = to_zone;
+        let from_area = channels[map_definition.zone_index(from_zone.0, from_zone.1)].area.clone();
+        let to_channel = &mut channels[map_definition.zone_index(to_zone.0, to_zone.1)];
+        to_channel.subscribe(subscriber.clone(), false);
+        subscriber.on_zone_changed(&from_area, &to_channel.area);
+        report.changed_zone = true;
     }
 
+    /// The subscriber is warned of the entities already in view, in its zone and the zones
+    /// around it, the same view a hand-off replays.
     pub fn subscribe(&mut self, subscriber: S, position: &Point) {
-        self.do_subscribe(subscriber, position, true);
+        subscriber.on_subscribed();
+
+        let zone = zone_coordinates_for_point(position, &self.map_definition);
+        self.subscriptions.insert(subscriber.entity_id().clone(), SubscriptionState{
+            zone,
+            left_zones: vec![],
+        });
+
+        // The entities of the zone itself are replayed by the zone channel.
+        for (x, y) in ZoneRange::around_zone(zone, &self.map_definition).zones() {
+            if (x, y) != zone {
+                let channel = &self.channels[self.map_definition.zone_index(x, y)];
+                channel.for_each_entity_changed_since(0, |entity_in_zone_event|{
+                    let _res = // Nothing to do if it fails, result is ignored.
+                        subscriber.send(entity_in_zone_event.clone());
+                });
+            }
+        }
+
+        let zone_index = self.map_definition.zone_index(zone.0, zone.1);
+        match self.channels.get_mut(zone_index) {
+            Some(channel) => channel.subscribe(subscriber, true),
+            None => panic!("Position {:?} is outside of the map", position),
+        }
     }
 
-    fn do_subscribe(&mut self, subscriber: S, position: &Point, warn_of_entities_in_zone: bool) {
-        let zone_index = zone_index_for_point(position, &self.map_definition);
-        if let Some(channel) = self.channels.get_mut(zone_index) {
-            channel.subscribe(subscriber, warn_of_entities_in_zone);
-        } else {
-            panic!()
+    /// Removes the subscription of the given entity, if any, and returns it.
+    pub fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S> {
+        match self.subscriptions.remove(entity_id) {
+            Some(state) => {
+                let zone_index = self.map_definition.zone_index(state.zone.0, state.zone.1);
+                self.channels[zone_index].unsubscribe(entity_id)
+            },
+            None => None,
         }
     }
 }
 
-pub struct ZoneChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity+Clone{
+impl <S, E> SpatialPubSub<S, E> for SpatialChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
+    fn subscribe(&mut self, subscriber: S, position: &Point) {
+        SpatialChannel::subscribe(self, subscriber, position)
+    }
+
+    fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S> {
+        SpatialChannel::unsubscribe(self, entity_id)
+    }
+
+    fn publish(&mut self, event: SpatialEvent<E>) -> PublishReport {
+        SpatialChannel::publish(self, event)
+    }
+}
+
+pub struct ZoneChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone{
     area: Zone,
-    subscribers: Vec<S>,
-    entities_in_zone: HashMap<Uuid, (Point, E)>,
+    /// Indexed by entity id, stored contiguously.
+    subscribers: IndexMap<E::Id, S>,
+    entities_in_zone: HashMap<E::Id, EntityInZone<E>>,
 }
 
-impl <S, E> ZoneChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity+Clone {
+impl <S, E> ZoneChannel<S, E> where S: Subscriber<SpatialEvent<E>, Id=E::Id>, E: Entity+Clone {
     pub fn new(area: Zone) -> ZoneChannel<S, E> {
         ZoneChannel{
             area,
-            subscribers: vec![],
+            subscribers: IndexMap::new(),
             entities_in_zone: HashMap::new(),
         }
     }
 
     pub fn subscribe(&mut self, subscriber: S, warn_of_entities_in_zone: bool) {
         if warn_of_entities_in_zone{
-            for (position, entity) in self.entities_in_zone.values(){
-                match subscriber.send(Rc::new(SpatialEvent{
-                    from: position.clone(),
-                    to: Some(position.clone()),
-                    acting_entity: entity.clone(),
-                    is_a_move: false,
-                })) {
+            for entity_in_zone in self.entities_in_zone.values(){
+                match subscriber.send(entity_in_zone.event.clone()) {
                     Ok(keep) => {
                         if !keep {
                             panic!("This is not an expected behavior to subscribe with an subscriber that drops immediately.")
@@ -140,77 +356,178 @@ impl <S, E> ZoneChannel<S, E> where S: Subscriber<SpatialEvent<E>>, E: Entity+Cl
         }
 
         debug!("Entity {} subscribing to zone {:?}", subscriber.entity_id(), self.area);
-        self.subscribers.push(subscriber);
-    }
-
-    pub fn publish(&mut self, event: Rc<SpatialEvent<E>>) -> Option<S>{
-        let leaves_the_zone = if event.is_a_move {
-            if self.area.point_is_in(&event.from){
-                if let Some(ref destination) = &event.to {
-                    if self.area.point_is_not_in(destination) {
-                        self.entities_in_zone.remove(event.acting_entity.id());
-                        true
-                    } else {
-                        self.insert_entity(event.acting_entity.clone(), destination.clone());
-                        false
-                    }
-                } else {
-                    self.entities_in_zone.remove(event.acting_entity.id());
-                    true
-                }
-            } else {
-                if let Some(ref destination) = &event.to {
-                    if self.area.point_is_in(destination) {
-                        self.insert_entity(event.acting_entity.clone(), destination.clone());
-                    }
-                    false
-                } else {
-                    false
+        let entity_id = subscriber.entity_id().clone();
+        self.subscribers.insert(entity_id, subscriber);
+    }
+
+    /// Keeps track of the entities in the zone and sends the event to the subscribers. The ids of
+    /// the dropped subscriptions are pushed to `dropped_subscriptions`.
+    pub fn publish(&mut self, event: Rc<SpatialEvent<E>>, sequence: u64, dropped_subscriptions: &mut Vec<E::Id>) -> DeliveryReport {
+        if event.is_a_move {
+            let is_in_destination = match event.to {
+                Some(ref destination) => self.area.point_is_in(destination),
+                None => false,
+            };
+
+            if is_in_destination {
+                if let Some(ref destination) = event.to {
+                    self.insert_entity(&event.acting_entity, destination, sequence);
                 }
+            } else if self.area.point_is_in(&event.from) {
+                debug!("Entity {} leaving zone {:?}", event.acting_entity.id(), self.area);
+                self.entities_in_zone.remove(event.acting_entity.id());
             }
-        } else {
-            false
-        };
-
-        if leaves_the_zone {
-            debug!("Entity {} leaving zone {:?}", event.acting_entity.id(), self.area);
         }
 
-        let mut dropped_subscriber_option = None;
-        self.subscribers.retain(|subscriber|{
-            match subscriber.send(event.clone()) {
+        let mut delivery = DeliveryReport::default();
+
+        let mut index = 0;
+        while let Some((_entity_id, subscriber)) = self.subscribers.get_index(index) {
+            let retain = match subscriber.send(event.clone()) {
                 Ok(retain) => {
-                    if leaves_the_zone && subscriber.entity_id() == event.acting_entity.id() {
-                        dropped_subscriber_option = Some(subscriber.clone());
+                    delivery.delivered += 1;
 
-                        false
-                    } else {
-                        retain
+                    if !retain {
+                        subscriber.on_dropped(&PubSubError::Unsubscribed);
                     }
+
+                    retain
                 },
-                Err(_err) => {
+                Err(err) => {
+                    debug!("Subscriber {} dropped from zone {:?}. Cause: {}", subscriber.entity_id(), self.area, err);
+                    subscriber.on_dropped(&err);
                     false
                 }
+            };
+
+            if retain {
+                index += 1;
+            } else {
+                // The order of the subscribers does not matter, no need to shift them.
+                if let Some((entity_id, _subscriber)) = self.subscribers.swap_remove_index(index) {
+                    dropped_subscriptions.push(entity_id);
+                }
+                delivery.dropped += 1;
             }
-        });
+        }
 
-        dropped_subscriber_option
+        delivery
     }
 
-    fn insert_entity(&mut self, entity: E, position: Point) {
-        let entity_id = entity.id().clone();
-        self.entities_in_zone.insert(entity_id, (position, entity));
+    /// Removes the subscription of the given entity, if any, and returns it.
+    pub fn unsubscribe(&mut self, entity_id: &E::Id) -> Option<S> {
+        let subscriber_option = self.subscribers.swap_remove(entity_id);
+
+        if let Some(ref subscriber) = subscriber_option {
+            debug!("Entity {} unsubscribing from zone {:?}", entity_id, self.area);
+            subscriber.on_dropped(&PubSubError::Unsubscribed);
+        }
+
+        subscriber_option
+    }
+
+    pub(crate) fn area(&self) -> &Zone {
+        &self.area
+    }
+
+    /// Removes the subscription of the given entity without dropping it, to move it elsewhere.
+    pub(crate) fn take_subscriber(&mut self, entity_id: &E::Id) -> Option<S> {
+        self.subscribers.swap_remove(entity_id)
+    }
+
+    pub fn subscriber(&self, entity_id: &E::Id) -> Option<&S> {
+        self.subscribers.get(entity_id)
+    }
+
+    pub fn number_of_subscribers(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    fn insert_entity(&mut self, entity: &E, position: &Point, sequence: u64) {
+        if let Some(entity_in_zone) = self.entities_in_zone.get_mut(entity.id()) {
+            entity_in_zone.last_changed = sequence;
+
+            // Update in place if no subscriber still holds the previous event.
+            if let Some(event) = Rc::get_mut(&mut entity_in_zone.event) {
+                event.from.clone_from(position);
+                event.to = Some(position.clone());
+                event.acting_entity.clone_from(entity);
+                return;
+            }
+        }
+
+        let entity_in_zone = EntityInZone{
+            event: Rc::new(SpatialEvent{
+                from: position.clone(),
+                to: Some(position.clone()),
+                acting_entity: entity.clone(),
+                is_a_move: false,
+            }),
+            last_changed: sequence,
+        };
+
+        self.entities_in_zone.insert(entity.id().clone(), entity_in_zone);
     }
 
-    fn for_each_entity_in_zone<C>(&mut self, consumer: C) where C: Fn(&mut E, &Point) {
-        self.entities_in_zone.retain(|_id, (position, entity)|{
-            consumer(entity, position);
-            true
-        })
+    /// Calls the consumer with the entities whose last change was published after `sequence`.
+    pub(crate) fn for_each_entity_changed_since<C>(&self, sequence: u64, mut consumer: C) where C: FnMut(&Rc<SpatialEvent<E>>) {
+        for entity_in_zone in self.entities_in_zone.values() {
+            if entity_in_zone.last_changed > sequence {
+                consumer(&entity_in_zone.event);
+            }
+        }
     }
+
+    pub(crate) fn for_each_subscriber<C>(&self, consumer: C) where C: FnMut(&S) {
+        self.subscribers.values().for_each(consumer);
+    }
+
+    /// Removes every subscription without dropping them, to move them elsewhere.
+    pub(crate) fn take_subscribers(&mut self) -> Vec<S> {
+        self.subscribers.drain(..).map(|(_entity_id, subscriber)| subscriber).collect()
+    }
+
+    /// Removes every entity, to move them elsewhere.
+    pub(crate) fn take_entities(&mut self) -> Vec<(E::Id, EntityInZone<E>)> {
+        self.entities_in_zone.drain().collect()
+    }
+
+    pub(crate) fn insert_entity_in_zone(&mut self, entity_id: E::Id, entity_in_zone: EntityInZone<E>) {
+        self.entities_in_zone.insert(entity_id, entity_in_zone);
+    }
+}
+
+pub(crate) struct EntityInZone<E: Entity> {
+    /// Tells a subscriber where the entity stands. Shared by every subscriber to be warned of it.
+    pub(crate) event: Rc<SpatialEvent<E>>,
+    /// The sequence of the last publication that moved the entity.
+    last_changed: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// What happened during the publication of a `SpatialEvent`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PublishReport {
+    /// The number of subscribers the event was delivered to.
+    pub fan_out: usize,
+    /// The number of zones the event was published in.
+    pub zones_visited: usize,
+    /// The number of subscriptions dropped during the publication.
+    pub subscribers_dropped: usize,
+    /// The number of events sent to the acting entity about the entities now in range.
+    pub entities_in_range_notifications: usize,
+    /// Whether the subscription of the acting entity was handed over to another zone.
+    pub changed_zone: bool,
+}
+
+impl PublishReport {
+    pub(crate) fn record_delivery(&mut self, delivery: DeliveryReport) {
+        self.zones_visited += 1;
+        self.fan_out += delivery.delivered;
+        self.subscribers_dropped += delivery.dropped;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SpatialEvent<E: Entity>{
     pub from: Point,
     pub to: Option<Point>,
@@ -218,22 +535,50 @@ pub struct SpatialEvent<E: Entity>{
     pub is_a_move: bool,
 }
 
-#[derive(Debug, Clone)]
+/// Serializable, so that servers can tell their clients which map they run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct MapDefinition{
     zone_width: usize,
     map_width_in_zones: usize,
-    coordinate_max_value: usize,
+    layout: ZoneLayout,
 }
 
 impl MapDefinition{
     pub fn new(zone_width: usize, map_width_in_zones: usize) -> MapDefinition{
         MapDefinition{
-            coordinate_max_value: map_width_in_zones * zone_width - 1,
             zone_width,
             map_width_in_zones,
+            layout: ZoneLayout::default(),
         }
     }
 
+    /// Changes how the zones are laid out in memory, see `ZoneLayout`.
+    pub fn with_layout(mut self, layout: ZoneLayout) -> MapDefinition {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> ZoneLayout {
+        self.layout
+    }
+
+    pub fn zone_width(&self) -> usize {
+        self.zone_width
+    }
+
+    pub fn map_width_in_zones(&self) -> usize {
+        self.map_width_in_zones
+    }
+
+    fn coordinate_max_value(&self) -> usize {
+        self.map_width_in_zones * self.zone_width - 1
+    }
+
+    /// The index of the zone at the given zone coordinates.
+    pub(crate) fn zone_index(&self, x: usize, y: usize) -> usize {
+        self.layout.zone_index(x, y, self.map_width_in_zones)
+    }
+
     pub fn point_is_inside(&self, point: &Point) -> bool {
         self.coord_is_inside(&point.0) && self.coord_is_inside(&point.1)
     }
@@ -243,7 +588,7 @@ impl MapDefinition{
     }
 
     pub fn random_point(&self, rng: &mut ThreadRng) -> Point {
-        Point(rng.gen_range(0, self.coordinate_max_value), rng.gen_range(0, self.coordinate_max_value))
+        Point(rng.gen_range(0, self.coordinate_max_value()), rng.gen_range(0, self.coordinate_max_value()))
     }
 
     pub fn random_point_next_to(&self, point: &Point, rng: &mut ThreadRng) -> Point {
@@ -254,11 +599,11 @@ impl MapDefinition{
         // Doing it this way avoids the need of a loop.
         match direction {
             0 => {
-                if candidate.0 < self.coordinate_max_value {
+                if candidate.0 < self.coordinate_max_value() {
                     candidate.0 += 1;
                 } else if candidate.0 > 0 {
                     candidate.0 -= 1;
-                } else if candidate.1 < self.coordinate_max_value {
+                } else if candidate.1 < self.coordinate_max_value() {
                     candidate.1 += 1;
                 } else {
                     candidate.1 -= 1;
@@ -267,7 +612,7 @@ impl MapDefinition{
             1 => {
                 if candidate.0 > 0 {
                     candidate.0 -= 1;
-                } else if candidate.1 < self.coordinate_max_value {
+                } else if candidate.1 < self.coordinate_max_value() {
                     candidate.1 += 1;
                 } else if candidate.1 > 0 {
                     candidate.1 -= 1;
@@ -276,11 +621,11 @@ impl MapDefinition{
                 }
             },
             2 => {
-                if candidate.1 < self.coordinate_max_value {
+                if candidate.1 < self.coordinate_max_value() {
                     candidate.1 += 1;
                 } else if candidate.1 > 0 {
                     candidate.1 -= 1;
-                } else if candidate.0 < self.coordinate_max_value {
+                } else if candidate.0 < self.coordinate_max_value() {
                     candidate.0 += 1;
                 } else {
                     candidate.0 -= 1;
@@ -289,7 +634,7 @@ impl MapDefinition{
             3 => {
                 if candidate.1 > 0 {
                     candidate.1 -= 1;
-                } else if candidate.0 < self.coordinate_max_value {
+                } else if candidate.0 < self.coordinate_max_value() {
                     candidate.0 += 1;
                 } else if candidate.0 > 0 {
                     candidate.0 -= 1;
@@ -311,61 +656,95 @@ pub struct Point(pub usize, pub usize);
 pub struct Zone(Point, Point);
 
 impl Zone{
-    fn point_is_in(&self, point: &Point) -> bool {
+    pub fn new(start: Point, end: Point) -> Zone {
+        Zone(start, end)
+    }
+
+    /// The top left corner, inclusive.
+    pub fn start(&self) -> &Point {
+        &self.0
+    }
+
+    /// The bottom right corner, exclusive.
+    pub fn end(&self) -> &Point {
+        &self.1
+    }
+
+    pub fn point_is_in(&self, point: &Point) -> bool {
         point.0 >= (self.0).0 && point.1 >= (self.0).1
             && point.0 < (self.1).0 && point.1 < (self.1).1
     }
 
+    #[cfg(test)]
     fn point_is_not_in(&self, point: &Point) -> bool {
         !self.point_is_in(point)
     }
+
+    /// How far the point is from the zone along the farthest axis, 0 if it is in the zone.
+    pub(crate) fn distance_to(&self, point: &Point) -> usize {
+        let distance = |coord: usize, start: usize, end: usize| {
+            if coord < start {
+                start - coord
+            } else if coord >= end {
+                coord - end + 1
+            } else {
+                0
+            }
+        };
+
+        max(distance(point.0, (self.0).0, (self.1).0), distance(point.1, (self.0).1, (self.1).1))
+    }
 }
 
 pub trait Entity {
-    fn id(&self) -> &Uuid;
+    type Id: EntityId;
+
+    fn id(&self) -> &Self::Id;
 }
 
-fn compute_indexes_for_zones_in_range<F>(
-    point: &Point,
-    zone_width: usize,
-    map_width_in_zones: usize,
-    mut consumer: F
-) where F: FnMut(usize) {
-    let (start_x, x_offset_max) = if point.0 > zone_width {
-        (point.0 / zone_width - 1, 3)
-    } else {
-        (0, 2)
-    };
-
-    let (start_y, y_offset_max) = if point.1 > zone_width {
-        (point.1 / zone_width - 1, 3)
-    } else {
-        (0, 2)
-    };
-
-    for x_offset in 0..x_offset_max{
-        for y_offset in 0..y_offset_max{
-            let channel_index = (start_x + x_offset) * map_width_in_zones + (start_y + y_offset);
-            consumer(channel_index);
+/// The zones in range of a point, as ranges of zone coordinates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ZoneRange {
+    x: Range<usize>,
+    y: Range<usize>,
+}
+
+impl ZoneRange {
+    /// The zones next to the given zone, including itself.
+    fn around_zone(zone: (usize, usize), map_definition: &MapDefinition) -> ZoneRange {
+        let map_width_in_zones = map_definition.map_width_in_zones;
+        ZoneRange{
+            x: zone.0.saturating_sub(1)..min(zone.0 + 2, map_width_in_zones),
+            y: zone.1.saturating_sub(1)..min(zone.1 + 2, map_width_in_zones),
         }
     }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        self.x.start <= x && x < self.x.end && self.y.start <= y && y < self.y.end
+    }
+
+    fn zones(&self) -> impl Iterator<Item=(usize, usize)> {
+        let y_range = self.y.clone();
+        self.x.clone().flat_map(move |x| y_range.clone().map(move |y| (x, y)))
+    }
 }
 
-fn zone_index_for_point(point: &Point, map_definition: &MapDefinition) -> usize{
-    let x = point.0 / map_definition.zone_width ;
-    let y = point.1 / map_definition.zone_width;
-    x * map_definition.map_width_in_zones + y
+fn zone_coordinates_for_point(point: &Point, map_definition: &MapDefinition) -> (usize, usize) {
+    (point.0 / map_definition.zone_width, point.1 / map_definition.zone_width)
 }
 
 #[cfg(test)]
 mod tests{
+    use bincode;
     use env_logger;
-    use pub_sub::PubSubError;
+    use fn_sub::FnSubscriber;
     use std::iter::FromIterator;
     use std::sync::Mutex;
     use super::*;
-    use std::cmp::max;
-    use std::cmp::min;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use uuid::Uuid;
 
     const ZONE_WIDTH: usize = 16;
     const MAP_WIDTH_IN_ZONES: usize = 16;
@@ -463,27 +842,87 @@ mod tests{
 
     #[test]
     pub fn can_compute_indexes_for_zones_in_range(){
+        let map = MapDefinition::new(ZONE_WIDTH, MAP_WIDTH_IN_ZONES);
         let expected = HashSet::from_iter(vec![
             0, 1, ZONE_WIDTH, ZONE_WIDTH +1,
         ]);
 
-        let mut found = HashSet::new();
-        compute_indexes_for_zones_in_range(&Point(0, 0), ZONE_WIDTH, MAP_WIDTH_IN_ZONES, |index|{
-            found.insert(index);
-        });
+        assert_eq!(expected, indexes_for_zones_in_range(&Point(0, 0), &map));
 
-        assert_eq!(expected, found);
+        let expected = HashSet::from_iter(vec![
+            0, 1, ZONE_WIDTH, ZONE_WIDTH +1, ZONE_WIDTH * 2, ZONE_WIDTH * 2 + 1,
+        ]);
 
+        assert_eq!(expected, indexes_for_zones_in_range(&Point(16, 0), &map));
+    }
+
+    #[test]
+    pub fn zones_in_range_stop_at_the_edge_of_the_map(){
+        let map = MapDefinition::new(ZONE_WIDTH, MAP_WIDTH_IN_ZONES);
+        let last_zone = MAP_WIDTH_IN_ZONES - 1;
         let expected = HashSet::from_iter(vec![
-            0, 1, ZONE_WIDTH, ZONE_WIDTH +1,
+            (last_zone - 1) * MAP_WIDTH_IN_ZONES + last_zone - 1,
+            (last_zone - 1) * MAP_WIDTH_IN_ZONES + last_zone,
+            last_zone * MAP_WIDTH_IN_ZONES + last_zone - 1,
+            last_zone * MAP_WIDTH_IN_ZONES + last_zone,
         ]);
 
-        let mut found = HashSet::new();
-        compute_indexes_for_zones_in_range(&Point(16, 0), ZONE_WIDTH, MAP_WIDTH_IN_ZONES, |index|{
-            found.insert(index);
-        });
+        let corner = ZONE_WIDTH * MAP_WIDTH_IN_ZONES - 1;
+        assert_eq!(expected, indexes_for_zones_in_range(&Point(corner, corner), &map));
+    }
 
-        assert_eq!(expected, found);
+    #[test]
+    pub fn zone_layouts_do_not_change_what_is_received() {
+        let map_width_in_zones = 10; // Not a power of two, the curves need padding.
+        let layouts = vec![ZoneLayout::RowMajor, ZoneLayout::Morton, ZoneLayout::Hilbert];
+
+        let received_by_layout: Vec<Vec<usize>> = layouts.into_iter().map(|layout|{
+            let map = MapDefinition::new(ZONE_WIDTH, map_width_in_zones).with_layout(layout);
+            let mut channel = SpatialChannel::new(map);
+
+            let mut observers = vec![];
+            for x in 0..map_width_in_zones {
+                for y in 0..map_width_in_zones {
+                    let observer = CountingSubscriber::new(Uuid::new_v4());
+                    channel.subscribe(observer.clone(), &Point(x * ZONE_WIDTH, y * ZONE_WIDTH));
+                    observers.push(observer);
+                }
+            }
+
+            let walker = TestEntity{
+                id: Uuid::new_v4(),
+            };
+            let mut position = Point(0, 0);
+            while position.0 < map_width_in_zones * ZONE_WIDTH - 1 {
+                let destination = Point(position.0 + 1, position.0 / 2);
+                channel.publish(SpatialEvent{
+                    from: position,
+                    to: Some(destination.clone()),
+                    acting_entity: walker.clone(),
+                    is_a_move: true,
+                });
+                position = destination;
+            }
+
+            observers.iter().map(|observer| observer.number_of_events_received()).collect()
+        }).collect();
+
+        assert!(received_by_layout[0].iter().sum::<usize>() > 0);
+        assert_eq!(received_by_layout[0], received_by_layout[1]);
+        assert_eq!(received_by_layout[0], received_by_layout[2]);
+    }
+
+    #[test]
+    pub fn map_definitions_round_trip_with_their_layout() {
+        let map = MapDefinition::new(ZONE_WIDTH, 10).with_layout(ZoneLayout::Hilbert);
+
+        let bytes = bincode::serialize(&map).unwrap();
+        let received: MapDefinition = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(map, received);
+        assert_eq!(ZoneLayout::Hilbert, received.layout());
+        assert!(received.point_is_inside(&Point(ZONE_WIDTH * 10 - 1, 0)));
+        assert!(!received.point_is_inside(&Point(ZONE_WIDTH * 10, 0)));
     }
 
     #[test]
@@ -498,6 +937,21 @@ mod tests{
         assert_eq!(1, subscriber.number_of_events_received());
     }
 
+    #[test]
+    pub fn new_subscriber_is_warned_of_entities_in_neighbouring_zones() {
+        let mut channel = test_channel();
+
+        channel.publish(event(ZONE_WIDTH - 1, ZONE_WIDTH - 1, ZONE_WIDTH - 2, ZONE_WIDTH - 1));
+        channel.publish(event(ZONE_WIDTH * 2, ZONE_WIDTH * 2, ZONE_WIDTH * 2 + 1, ZONE_WIDTH * 2));
+        channel.publish(event(ZONE_WIDTH * 3, ZONE_WIDTH, ZONE_WIDTH * 3 + 1, ZONE_WIDTH));
+
+        let subscriber = CountingSubscriber::new(Uuid::new_v4());
+        channel.subscribe(subscriber.clone(), &Point(ZONE_WIDTH + 1, ZONE_WIDTH + 1));
+
+        // The entity three zones away is out of view.
+        assert_eq!(2, subscriber.number_of_events_received());
+    }
+
     #[test]
     pub fn moving_entity_is_warned_of_entities_now_in_range() {
         let mut channel = test_channel();
@@ -521,6 +975,245 @@ mod tests{
         assert_eq!(2, subscriber.number_of_events_received());
     }
 
+    #[test]
+    pub fn can_use_compact_entity_ids() {
+        let mut channel = SpatialChannel::new(MapDefinition::new(ZONE_WIDTH, MAP_WIDTH_IN_ZONES));
+        let received = Rc::new(RefCell::new(vec![]));
+        let neighbour_received = received.clone();
+        channel.subscribe(FnSubscriber::new(1u32, move |event: Rc<SpatialEvent<CompactEntity>>|{
+            neighbour_received.borrow_mut().push(event.acting_entity.id);
+            Ok(true)
+        }), &Point(0, 0));
+
+        let entity = CompactEntity{
+            id: 2,
+        };
+        channel.publish(SpatialEvent{
+            from: Point(1, 0),
+            to: Some(Point(2, 0)),
+            acting_entity: entity,
+            is_a_move: true,
+        });
+
+        assert_eq!(vec![2], *received.borrow());
+        assert!(channel.unsubscribe(&1).is_some());
+    }
+
+    #[test]
+    pub fn subscriber_is_told_about_its_lifecycle() {
+        let mut channel = test_channel();
+
+        let entity_id = Uuid::new_v4();
+        let subscriber = CountingSubscriber::new(entity_id);
+        channel.subscribe(subscriber.clone(), &Point(ZONE_WIDTH - 1, 0));
+        assert_eq!(vec!["subscribed".to_string()], subscriber.lifecycle());
+
+        let entity = TestEntity{
+            id: entity_id,
+        };
+        channel.publish(SpatialEvent{
+            from: Point(ZONE_WIDTH - 1, 0),
+            to: Some(Point(ZONE_WIDTH, 0)),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        });
+        channel.publish(SpatialEvent{
+            from: Point(ZONE_WIDTH, 0),
+            to: None,
+            acting_entity: entity,
+            is_a_move: true,
+        });
+
+        assert_eq!(vec![
+            "subscribed".to_string(),
+            format!("zone changed: {:?} => {:?}", Point(0, 0), Point(ZONE_WIDTH, 0)),
+            format!("dropped: {}", PubSubError::Unsubscribed),
+        ], subscriber.lifecycle());
+    }
+
+    #[test]
+    pub fn subscriber_dropping_itself_during_a_hand_off_is_not_moved() {
+        let mut channel = SpatialChannel::new(MapDefinition::new(ZONE_WIDTH, MAP_WIDTH_IN_ZONES));
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let received = Rc::new(Cell::new(0));
+        let subscriber_received = received.clone();
+        channel.subscribe(FnSubscriber::new(entity.id, move |_event: Rc<SpatialEvent<TestEntity>>|{
+            subscriber_received.set(subscriber_received.get() + 1);
+            Ok(false)
+        }), &Point(ZONE_WIDTH - 1, 0));
+
+        let report = channel.publish(SpatialEvent{
+            from: Point(ZONE_WIDTH - 1, 0),
+            to: Some(Point(ZONE_WIDTH, 0)),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        });
+        assert_eq!(1, report.subscribers_dropped);
+        assert!(!report.changed_zone);
+
+        channel.publish(event(ZONE_WIDTH + 1, 0, ZONE_WIDTH + 2, 0));
+        assert_eq!(1, received.get());
+        assert!(channel.unsubscribe(&entity.id).is_none());
+    }
+
+    #[test]
+    pub fn publish_reports_the_fan_out() {
+        let mut channel = test_channel();
+
+        let neighbour = CountingSubscriber::new(Uuid::new_v4());
+        channel.subscribe(neighbour.clone(), &Point(ZONE_WIDTH * 2, 0));
+        channel.publish(event(ZONE_WIDTH * 3, 1, ZONE_WIDTH * 3, 0));
+
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let subscriber = CountingSubscriber::new(entity.id);
+        channel.subscribe(subscriber.clone(), &Point(ZONE_WIDTH * 2 - 1, 0));
+
+        let report = channel.publish(SpatialEvent{
+            from: Point(ZONE_WIDTH * 2 - 1, 0),
+            to: Some(Point(ZONE_WIDTH * 2, 0)),
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        });
+
+        assert_eq!(PublishReport{
+            fan_out: 2,
+            zones_visited: 8,
+            subscribers_dropped: 0,
+            entities_in_range_notifications: 1,
+            changed_zone: true,
+        }, report);
+
+        let report = channel.publish(SpatialEvent{
+            from: Point(ZONE_WIDTH * 2, 0),
+            to: Some(Point(ZONE_WIDTH * 2 + 1, 0)),
+            acting_entity: entity,
+            is_a_move: true,
+        });
+
+        assert_eq!(PublishReport{
+            fan_out: 2,
+            zones_visited: 6,
+            subscribers_dropped: 0,
+            entities_in_range_notifications: 0,
+            changed_zone: false,
+        }, report);
+    }
+
+    fn indexes_for_zones_in_range(point: &Point, map: &MapDefinition) -> HashSet<usize> {
+        ZoneRange::around_zone(zone_coordinates_for_point(point, map), map).zones()
+            .map(|(x, y)| map.zone_index(x, y))
+            .collect()
+    }
+
+    #[test]
+    pub fn hysteresis_delays_the_zone_change() {
+        let mut channel = test_channel().with_hysteresis_margin(4);
+
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let subscriber = CountingSubscriber::new(entity.id);
+        let mut position = Point(ZONE_WIDTH - 1, 0);
+        channel.subscribe(subscriber.clone(), &position);
+
+        let mut zone_changes = vec![];
+        for &x in [ZONE_WIDTH, ZONE_WIDTH - 1, ZONE_WIDTH, ZONE_WIDTH + 1, ZONE_WIDTH + 2, ZONE_WIDTH + 3, ZONE_WIDTH + 4].iter() {
+            let destination = Point(x, 0);
+            let report = channel.publish(SpatialEvent{
+                from: position,
+                to: Some(destination.clone()),
+                acting_entity: entity.clone(),
+                is_a_move: true,
+            });
+
+            zone_changes.push(report.changed_zone);
+            position = destination;
+        }
+
+        assert_eq!(vec![false, false, false, false, false, false, true], zone_changes);
+        assert_eq!(7, subscriber.number_of_events_received());
+    }
+
+    #[test]
+    pub fn zones_seen_recently_are_only_replayed_if_they_changed() {
+        let mut channel = test_channel();
+
+        channel.publish(event(1, 0, 0, 0));
+        let far_entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let mut far_position = Point(ZONE_WIDTH * 3, 0);
+        channel.publish(SpatialEvent{
+            from: far_position.clone(),
+            to: Some(far_position.clone()),
+            acting_entity: far_entity.clone(),
+            is_a_move: true,
+        });
+
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let subscriber = CountingSubscriber::new(entity.id);
+        let west = Point(ZONE_WIDTH * 2 - 1, 0);
+        let east = Point(ZONE_WIDTH * 2, 0);
+        channel.subscribe(subscriber.clone(), &west);
+        assert_eq!(1, subscriber.number_of_events_received());
+
+        let mut replays = vec![];
+        for step in 0..6 {
+            if step == 3 || step == 4 {
+                let destination = Point(far_position.0, far_position.1 + 1);
+                channel.publish(SpatialEvent{
+                    from: far_position,
+                    to: Some(destination.clone()),
+                    acting_entity: far_entity.clone(),
+                    is_a_move: true,
+                });
+                far_position = destination;
+            }
+
+            let (from, to) = if step % 2 == 0 {
+                (west.clone(), east.clone())
+            } else {
+                (east.clone(), west.clone())
+            };
+
+            let report = channel.publish(SpatialEvent{
+                from,
+                to: Some(to),
+                acting_entity: entity.clone(),
+                is_a_move: true,
+            });
+            replays.push(report.entities_in_range_notifications);
+        }
+
+        // The far entity only moved out of view of the subscriber before the fifth step.
+        assert_eq!(vec![1, 0, 0, 0, 1, 0], replays);
+    }
+
+    #[test]
+    pub fn can_unsubscribe() {
+        let mut channel = test_channel();
+
+        let position = Point(ZONE_WIDTH + 1, ZONE_WIDTH + 1);
+        let subscriber = CountingSubscriber::new(Uuid::new_v4());
+        channel.subscribe(subscriber.clone(), &position);
+
+        assert!(channel.unsubscribe(&subscriber.entity_id).is_some());
+        assert!(channel.unsubscribe(&subscriber.entity_id).is_none());
+
+        channel.publish(event(ZONE_WIDTH, ZONE_WIDTH, ZONE_WIDTH + 1, ZONE_WIDTH));
+        assert_eq!(0, subscriber.number_of_events_received());
+        assert_eq!(vec![
+            "subscribed".to_string(),
+            format!("dropped: {}", PubSubError::Unsubscribed),
+        ], subscriber.lifecycle());
+    }
+
     fn assert_can_subscribe(subscription_point: &Point, event: SpatialEvent<TestEntity>) {
         let mut channel = test_channel();
         let subscriber = CountingSubscriber::new(Uuid::new_v4());
@@ -547,12 +1240,27 @@ mod tests{
         }
     }
 
+    #[derive(Clone)]
+    struct CompactEntity{
+        id: u32,
+    }
+
+    impl Entity for CompactEntity{
+        type Id = u32;
+
+        fn id(&self) -> &u32 {
+            &self.id
+        }
+    }
+
     #[derive(Clone)]
     struct TestEntity{
         id: Uuid,
     }
 
     impl Entity for TestEntity{
+        type Id = Uuid;
+
         fn id(&self) -> &Uuid {
             &self.id
         }
@@ -562,6 +1270,7 @@ mod tests{
     struct CountingSubscriber{
         entity_id: Uuid,
         number_of_events_received: Rc<Mutex<usize>>,
+        lifecycle: Rc<RefCell<Vec<String>>>,
     }
 
     impl CountingSubscriber{
@@ -569,9 +1278,14 @@ mod tests{
             CountingSubscriber{
                 entity_id,
                 number_of_events_received: Rc::new(Mutex::new(0)),
+                lifecycle: Rc::new(RefCell::new(vec![])),
             }
         }
 
+        fn lifecycle(&self) -> Vec<String> {
+            self.lifecycle.borrow().clone()
+        }
+
         fn number_of_events_received(&self) -> usize {
             match self.number_of_events_received.lock(){
                 Ok(number) => {
@@ -583,6 +1297,8 @@ mod tests{
     }
 
     impl Subscriber<SpatialEvent<TestEntity>> for CountingSubscriber{
+        type Id = Uuid;
+
         fn send(&self, _event: Rc<SpatialEvent<TestEntity>>) -> Result<bool, PubSubError> {
             match self.number_of_events_received.lock(){
                 Ok(mut number) => {
@@ -598,5 +1314,17 @@ mod tests{
         fn entity_id(&self) -> &Uuid {
             &self.entity_id
         }
+
+        fn on_subscribed(&self) {
+            self.lifecycle.borrow_mut().push("subscribed".to_string());
+        }
+
+        fn on_zone_changed(&self, from: &Zone, to: &Zone) {
+            self.lifecycle.borrow_mut().push(format!("zone changed: {:?} => {:?}", from.start(), to.start()));
+        }
+
+        fn on_dropped(&self, reason: &PubSubError) {
+            self.lifecycle.borrow_mut().push(format!("dropped: {}", reason));
+        }
     }
 }
\ No newline at end of file
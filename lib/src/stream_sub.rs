@@ -0,0 +1,96 @@
+use futures03::channel::mpsc::{self, UnboundedReceiver};
+use futures03::channel::mpsc::UnboundedSender;
+use pub_sub::EntityId;
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// A subscriber backed by a `std::future` compatible channel.
+/// The receiving end is a `futures::Stream` that can be polled by any modern executor.
+#[derive(Clone, Debug)]
+pub struct StreamSubscriber<E: Clone, I: EntityId = Uuid> {
+    sender: UnboundedSender<Rc<E>>,
+    entity_id: I,
+}
+
+pub fn new_subscriber<E: Clone, I: EntityId>(entity_id: I) -> (StreamSubscriber<E, I>, UnboundedReceiver<Rc<E>>) {
+    let (sender, receiver) = mpsc::unbounded();
+
+    let subscriber = StreamSubscriber {
+        sender,
+        entity_id,
+    };
+
+    (subscriber, receiver)
+}
+
+impl <E: Clone, I: EntityId> Subscriber<E> for StreamSubscriber<E, I> {
+    type Id = I;
+
+    fn send(&self, event: Rc<E>) -> Result<bool, PubSubError> {
+        match &self.sender.unbounded_send(event) {
+            Ok(()) => {
+                Ok(true)
+            },
+            Err(_err) => {
+                Err(PubSubError::ReceiverIsGone)
+            }
+        }
+    }
+
+    fn entity_id(&self) -> &I {
+        &self.entity_id
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use futures03::executor::block_on;
+    use futures03::StreamExt;
+    use pub_sub::PubSubChannel;
+    use spatial::SpatialEvent;
+    use subscriber_tests::SubscriberHarness;
+    use subscriber_tests::TestEntity;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct TestEvent {}
+
+    #[test]
+    pub fn can_subscribe(){
+        let (subscriber, mut receiver) = super::new_subscriber(Uuid::new_v4());
+        let mut pub_sub = PubSubChannel::new();
+
+        pub_sub.subscribe(subscriber);
+
+        pub_sub.publish(Rc::new(TestEvent {}));
+
+        let received_event_option = block_on(receiver.next());
+        assert_eq!(TestEvent {}, Rc::try_unwrap(received_event_option.unwrap()).unwrap());
+    }
+
+    struct StreamHarness {
+        receiver: UnboundedReceiver<Rc<SpatialEvent<TestEntity>>>,
+    }
+
+    impl SubscriberHarness for StreamHarness {
+        type Subscriber = StreamSubscriber<SpatialEvent<TestEntity>>;
+
+        fn new_subscriber(entity_id: Uuid) -> (Self::Subscriber, StreamHarness) {
+            let (subscriber, receiver) = super::new_subscriber(entity_id);
+            (subscriber, StreamHarness{ receiver })
+        }
+
+        fn received_events(&mut self) -> Vec<Rc<SpatialEvent<TestEntity>>> {
+            let mut events = vec![];
+            while let Ok(event) = self.receiver.try_recv() {
+                events.push(event);
+            }
+
+            events
+        }
+    }
+
+    subscriber_test_suite!(StreamHarness);
+}
@@ -0,0 +1,152 @@
+//! Tests shared by every `Subscriber` implementation, so they all behave the same with a `SpatialChannel`.
+//! Each implementation provides a `SubscriberHarness` and calls `subscriber_test_suite!` in its tests.
+
+use pub_sub::PubSubChannel;
+use pub_sub::Subscriber;
+use spatial::Entity;
+use spatial::MapDefinition;
+use spatial::Point;
+use spatial::SpatialChannel;
+use spatial::SpatialEvent;
+use std::rc::Rc;
+use uuid::Uuid;
+
+const ZONE_WIDTH: usize = 16;
+
+pub trait SubscriberHarness: Sized {
+    type Subscriber: Subscriber<SpatialEvent<TestEntity>, Id=Uuid>;
+
+    fn new_subscriber(entity_id: Uuid) -> (Self::Subscriber, Self);
+
+    /// Returns the events received since the last call.
+    fn received_events(&mut self) -> Vec<Rc<SpatialEvent<TestEntity>>>;
+}
+
+macro_rules! subscriber_test_suite {
+    ($harness:ty) => {
+        #[test]
+        pub fn subscription_follows_moving_entity() {
+            $crate::subscriber_tests::subscription_follows_moving_entity::<$harness>();
+        }
+
+        #[test]
+        pub fn new_subscriber_is_warned_of_existing_entities() {
+            $crate::subscriber_tests::new_subscriber_is_warned_of_existing_entities::<$harness>();
+        }
+
+        #[test]
+        pub fn moving_entity_is_warned_of_entities_now_in_range() {
+            $crate::subscriber_tests::moving_entity_is_warned_of_entities_now_in_range::<$harness>();
+        }
+
+        #[test]
+        pub fn subscription_is_dropped_with_the_receiver() {
+            $crate::subscriber_tests::subscription_is_dropped_with_the_receiver::<$harness>();
+        }
+    };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestEntity{
+    pub id: Uuid,
+}
+
+impl Entity for TestEntity{
+    type Id = Uuid;
+
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+}
+
+pub fn subscription_follows_moving_entity<H: SubscriberHarness>() {
+    let mut channel = test_channel::<H>();
+    let entity = TestEntity{
+        id: Uuid::new_v4(),
+    };
+
+    let (subscriber, mut harness) = H::new_subscriber(entity.id);
+
+    let mut position = Point(0, 0);
+    channel.subscribe(subscriber, &position);
+
+    let number_of_events = ZONE_WIDTH * 10;
+    for _i in 0..number_of_events {
+        let destination = Point(position.0 + 1, position.1);
+        channel.publish(move_event(&entity, position, destination.clone()));
+        position = destination;
+    }
+
+    let received_events = harness.received_events();
+    assert_eq!(number_of_events, received_events.len());
+    assert_eq!(Some(position), received_events.last().unwrap().to);
+}
+
+pub fn new_subscriber_is_warned_of_existing_entities<H: SubscriberHarness>() {
+    let mut channel = test_channel::<H>();
+    let entity = TestEntity{
+        id: Uuid::new_v4(),
+    };
+    channel.publish(move_event(&entity, Point(0, 0), Point(1, 0)));
+
+    let (subscriber, mut harness) = H::new_subscriber(Uuid::new_v4());
+    channel.subscribe(subscriber, &Point(0, 0));
+
+    let received_events = harness.received_events();
+    assert_eq!(1, received_events.len());
+    assert_eq!(entity, received_events[0].acting_entity);
+    assert!(!received_events[0].is_a_move);
+}
+
+pub fn moving_entity_is_warned_of_entities_now_in_range<H: SubscriberHarness>() {
+    let mut channel = test_channel::<H>();
+    let other_entity = TestEntity{
+        id: Uuid::new_v4(),
+    };
+    channel.publish(move_event(&other_entity, Point(0, 0), Point(1, 0)));
+
+    let entity = TestEntity{
+        id: Uuid::new_v4(),
+    };
+    let entity_position = Point(ZONE_WIDTH * 2, ZONE_WIDTH - 1);
+    let (subscriber, mut harness) = H::new_subscriber(entity.id);
+    channel.subscribe(subscriber, &entity_position);
+    assert!(harness.received_events().is_empty());
+
+    let destination = Point(ZONE_WIDTH * 2 - 1, ZONE_WIDTH - 1);
+    channel.publish(move_event(&entity, entity_position, destination));
+
+    let received_events = harness.received_events();
+    assert_eq!(2, received_events.len());
+    assert_eq!(other_entity, received_events[1].acting_entity);
+}
+
+pub fn subscription_is_dropped_with_the_receiver<H: SubscriberHarness>() {
+    let mut pub_sub = PubSubChannel::new();
+    let (subscriber, harness) = H::new_subscriber(Uuid::new_v4());
+    pub_sub.subscribe(subscriber);
+
+    drop(harness);
+
+    let entity = TestEntity{
+        id: Uuid::new_v4(),
+    };
+    let report = pub_sub.publish(Rc::new(move_event(&entity, Point(0, 0), Point(1, 0))));
+    assert_eq!(1, report.dropped);
+    assert!(pub_sub.is_empty());
+}
+
+fn test_channel<H: SubscriberHarness>() -> SpatialChannel<H::Subscriber, TestEntity> {
+    SpatialChannel::new(
+        MapDefinition::new(ZONE_WIDTH, ZONE_WIDTH)
+    )
+}
+
+fn move_event(entity: &TestEntity, from: Point, to: Point) -> SpatialEvent<TestEntity> {
+    SpatialEvent{
+        from,
+        to: Some(to),
+        acting_entity: entity.clone(),
+        is_a_move: true,
+    }
+}
@@ -0,0 +1,140 @@
+use crossbeam_channel::{self, Receiver, Sender};
+use crossbeam_channel::TrySendError;
+use pub_sub::EntityId;
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// A subscriber backed by a blocking multi-producer channel, for consumers that do not run an
+/// executor. The receiver offers `recv`, `try_recv` and `recv_timeout`.
+///
+/// Each subscriber receives its own copy of the events, so that the receiver can be moved to
+/// another thread when the events are `Send`.
+#[derive(Clone, Debug)]
+pub struct SyncSubscriber<E: Clone, I: EntityId = Uuid> {
+    sender: Sender<E>,
+    entity_id: I,
+}
+
+pub fn new_subscriber<E: Clone, I: EntityId>(entity_id: I) -> (SyncSubscriber<E, I>, Receiver<E>) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let subscriber = SyncSubscriber {
+        sender,
+        entity_id,
+    };
+
+    (subscriber, receiver)
+}
+
+/// Same as `new_subscriber`, except the subscription is dropped with `PubSubError::QueueOverflow`
+/// once `capacity` events are waiting in the receiver.
+pub fn new_bounded_subscriber<E: Clone, I: EntityId>(entity_id: I, capacity: usize) -> (SyncSubscriber<E, I>, Receiver<E>) {
+    let (sender, receiver) = crossbeam_channel::bounded(capacity);
+
+    let subscriber = SyncSubscriber {
+        sender,
+        entity_id,
+    };
+
+    (subscriber, receiver)
+}
+
+impl <E: Clone, I: EntityId> Subscriber<E> for SyncSubscriber<E, I> {
+    type Id = I;
+
+    fn send(&self, event: Rc<E>) -> Result<bool, PubSubError> {
+        match self.sender.try_send((*event).clone()) {
+            Ok(()) => {
+                Ok(true)
+            },
+            Err(TrySendError::Disconnected(_event)) => {
+                Err(PubSubError::ReceiverIsGone)
+            },
+            Err(TrySendError::Full(_event)) => {
+                Err(PubSubError::QueueOverflow)
+            },
+        }
+    }
+
+    fn entity_id(&self) -> &I {
+        &self.entity_id
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use pub_sub::PubSubChannel;
+    use spatial::SpatialEvent;
+    use std::thread;
+    use std::time::Duration;
+    use subscriber_tests::SubscriberHarness;
+    use subscriber_tests::TestEntity;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct TestEvent {}
+
+    #[test]
+    pub fn can_subscribe(){
+        let (subscriber, receiver) = super::new_subscriber(Uuid::new_v4());
+        let mut pub_sub = PubSubChannel::new();
+
+        pub_sub.subscribe(subscriber);
+
+        assert!(receiver.recv_timeout(Duration::from_millis(1)).is_err());
+
+        pub_sub.publish(Rc::new(TestEvent {}));
+
+        assert_eq!(TestEvent {}, receiver.recv().unwrap());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    pub fn events_are_received_on_another_thread(){
+        let (subscriber, receiver) = super::new_subscriber(Uuid::new_v4());
+        let consumer = thread::spawn(move || {
+            (0..3).map(|_| receiver.recv().unwrap()).collect::<Vec<u32>>()
+        });
+
+        let mut pub_sub = PubSubChannel::new();
+        pub_sub.subscribe(subscriber);
+        for event in 0..3 {
+            pub_sub.publish(Rc::new(event));
+        }
+
+        assert_eq!(vec![0, 1, 2], consumer.join().unwrap());
+    }
+
+    #[test]
+    pub fn bounded_subscription_is_dropped_on_overflow(){
+        let (subscriber, receiver) = super::new_bounded_subscriber(Uuid::new_v4(), 2);
+
+        assert_eq!(Ok(true), subscriber.send(Rc::new(TestEvent {})));
+        assert_eq!(Ok(true), subscriber.send(Rc::new(TestEvent {})));
+        assert_eq!(Err(PubSubError::QueueOverflow), subscriber.send(Rc::new(TestEvent {})));
+
+        drop(receiver);
+        assert_eq!(Err(PubSubError::ReceiverIsGone), subscriber.send(Rc::new(TestEvent {})));
+    }
+
+    struct SyncHarness {
+        receiver: Receiver<SpatialEvent<TestEntity>>,
+    }
+
+    impl SubscriberHarness for SyncHarness {
+        type Subscriber = SyncSubscriber<SpatialEvent<TestEntity>>;
+
+        fn new_subscriber(entity_id: Uuid) -> (Self::Subscriber, SyncHarness) {
+            let (subscriber, receiver) = super::new_subscriber(entity_id);
+            (subscriber, SyncHarness{ receiver })
+        }
+
+        fn received_events(&mut self) -> Vec<Rc<SpatialEvent<TestEntity>>> {
+            self.receiver.try_iter().map(Rc::new).collect()
+        }
+    }
+
+    subscriber_test_suite!(SyncHarness);
+}
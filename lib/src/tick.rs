@@ -0,0 +1,147 @@
+use indexmap::IndexMap;
+use spatial::Entity;
+use spatial::SpatialEvent;
+
+/// Collects the events published during a tick, collapsing those of the same entity into one.
+///
+/// A collapsed event starts where the first event of the entity started and ends where the last
+/// one ended, so that the channel finds the entity where it was last published. Entities are
+/// drained in the order of their first event.
+pub struct TickBuffer<E: Entity> {
+    events: IndexMap<E::Id, SpatialEvent<E>>,
+}
+
+impl <E: Entity> TickBuffer<E> {
+    pub fn new() -> TickBuffer<E> {
+        TickBuffer{
+            events: IndexMap::new(),
+        }
+    }
+
+    /// Returns true if the event was collapsed with a previous one of the same entity.
+    pub fn push(&mut self, event: SpatialEvent<E>) -> bool {
+        let entity_id = event.acting_entity.id().clone();
+
+        match self.events.get_mut(&entity_id) {
+            Some(buffered) => {
+                buffered.to = event.to;
+                buffered.acting_entity = event.acting_entity;
+                buffered.is_a_move |= event.is_a_move;
+                true
+            },
+            None => {
+                self.events.insert(entity_id, event);
+                false
+            },
+        }
+    }
+
+    /// Takes the events of the tick, to be published.
+    pub fn drain(&mut self) -> impl Iterator<Item=SpatialEvent<E>> + '_ {
+        self.events.drain(..).map(|(_entity_id, event)| event)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl <E: Entity> Default for TickBuffer<E> {
+    fn default() -> TickBuffer<E> {
+        TickBuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use spatial::MapDefinition;
+    use spatial::Point;
+    use spatial::SpatialChannel;
+    use subscriber_tests::TestEntity;
+    use super::*;
+    use sync_sub;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn moves_of_an_entity_are_collapsed() {
+        let mut buffer = TickBuffer::new();
+        let entity = new_entity();
+
+        assert!(!buffer.push(move_event(&entity, Point(0, 0), Some(Point(1, 0)))));
+        assert!(buffer.push(move_event(&entity, Point(1, 0), Some(Point(2, 0)))));
+        assert!(buffer.push(move_event(&entity, Point(2, 0), Some(Point(3, 1)))));
+
+        let events: Vec<_> = buffer.drain().collect();
+        assert_eq!(1, events.len());
+        assert_eq!(Point(0, 0), events[0].from);
+        assert_eq!(Some(Point(3, 1)), events[0].to);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    pub fn entities_are_drained_in_the_order_of_their_first_event() {
+        let mut buffer = TickBuffer::new();
+        let (first, second) = (new_entity(), new_entity());
+
+        buffer.push(move_event(&first, Point(0, 0), Some(Point(1, 0))));
+        buffer.push(move_event(&second, Point(5, 0), Some(Point(6, 0))));
+        buffer.push(move_event(&first, Point(1, 0), Some(Point(2, 0))));
+
+        let entities: Vec<_> = buffer.drain().map(|event| event.acting_entity).collect();
+        assert_eq!(vec![first, second], entities);
+    }
+
+    #[test]
+    pub fn leaving_after_moves_leaves_from_the_first_position() {
+        let mut buffer = TickBuffer::new();
+        let entity = new_entity();
+
+        buffer.push(move_event(&entity, Point(0, 0), Some(Point(1, 0))));
+        buffer.push(move_event(&entity, Point(1, 0), None));
+
+        let events: Vec<_> = buffer.drain().collect();
+        assert_eq!(Point(0, 0), events[0].from);
+        assert_eq!(None, events[0].to);
+    }
+
+    #[test]
+    pub fn subscribers_receive_one_event_per_entity_and_tick() {
+        let mut channel = SpatialChannel::new(MapDefinition::new(16, 4));
+        let (subscriber, receiver) = sync_sub::new_subscriber(Uuid::new_v4());
+        channel.subscribe(subscriber, &Point(0, 0));
+        let mut buffer = TickBuffer::new();
+        let (first, second) = (new_entity(), new_entity());
+
+        for x in 0..10 {
+            buffer.push(move_event(&first, Point(x, 0), Some(Point(x + 1, 0))));
+            buffer.push(move_event(&second, Point(x, 1), Some(Point(x + 1, 1))));
+        }
+        for event in buffer.drain() {
+            channel.publish(event);
+        }
+
+        let received_events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(2, received_events.len());
+        assert_eq!(Some(Point(10, 0)), received_events[0].to);
+        assert_eq!(Some(Point(10, 1)), received_events[1].to);
+    }
+
+    fn new_entity() -> TestEntity {
+        TestEntity{
+            id: Uuid::new_v4(),
+        }
+    }
+
+    fn move_event(entity: &TestEntity, from: Point, to: Option<Point>) -> SpatialEvent<TestEntity> {
+        SpatialEvent{
+            from,
+            to,
+            acting_entity: entity.clone(),
+            is_a_move: true,
+        }
+    }
+}
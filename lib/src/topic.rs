@@ -0,0 +1,306 @@
+use pub_sub::DeliveryReport;
+use pub_sub::PubSubChannel;
+use pub_sub::PubSubError;
+use pub_sub::Subscriber;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+const SEPARATOR: char = '.';
+const WILDCARD: &str = "*";
+const TRAILING_WILDCARD: &str = ">";
+
+/// A broker dispatching events to named topics, each topic being a `PubSubChannel`.
+///
+/// Topics are made of segments separated by dots, eg `guild.42.chat`. Subscriptions can use
+/// wildcards: `*` matches exactly one segment and a trailing `>` matches one or more segments,
+/// so `guild.*.chat` and `guild.>` both receive the events published on `guild.42.chat`.
+/// An entity subscribed to several matching patterns receives each event once.
+pub struct TopicBroker<S, E> where S: Subscriber<E> {
+    channels: HashMap<String, TopicChannel<S, E>>,
+}
+
+struct TopicChannel<S, E> where S: Subscriber<E> {
+    pattern: TopicPattern,
+    channel: PubSubChannel<S, E>,
+}
+
+impl <S, E> TopicBroker<S, E> where S: Subscriber<E> {
+    pub fn new() -> TopicBroker<S, E> {
+        TopicBroker{
+            channels: HashMap::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, pattern: &str, subscriber: S) -> Result<(), PubSubError> {
+        if !self.channels.contains_key(pattern) {
+            let topic_channel = TopicChannel{
+                pattern: TopicPattern::parse(pattern)?,
+                channel: PubSubChannel::new(),
+            };
+
+            self.channels.insert(pattern.to_string(), topic_channel);
+        }
+
+        if let Some(topic_channel) = self.channels.get_mut(pattern) {
+            topic_channel.channel.subscribe(subscriber);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the subscription of the entity to the given pattern, if any, and returns it.
+    pub fn unsubscribe(&mut self, pattern: &str, entity_id: &S::Id) -> Option<S> {
+        let (unsubscribed, is_empty) = match self.channels.get_mut(pattern) {
+            Some(topic_channel) => {
+                let unsubscribed = topic_channel.channel.unsubscribe(entity_id);
+                (unsubscribed, topic_channel.channel.is_empty())
+            },
+            None => return None,
+        };
+
+        if is_empty {
+            self.channels.remove(pattern);
+        }
+
+        unsubscribed
+    }
+
+    /// Removes every subscription of the entity. Returns the number of removed subscriptions.
+    pub fn unsubscribe_all(&mut self, entity_id: &S::Id) -> usize {
+        let mut number_of_unsubscriptions = 0;
+
+        self.channels.retain(|_pattern, topic_channel|{
+            while topic_channel.channel.unsubscribe(entity_id).is_some() {
+                number_of_unsubscriptions += 1;
+            }
+
+            !topic_channel.channel.is_empty()
+        });
+
+        number_of_unsubscriptions
+    }
+
+    /// Publishes the event to every subscription matching the topic, once per entity.
+    /// Wildcards are not allowed in the topic of a published event.
+    pub fn publish(&mut self, topic: &str, event: Rc<E>) -> Result<DeliveryReport, PubSubError> {
+        let topic_pattern = TopicPattern::parse(topic)?;
+        if topic_pattern.has_wildcards() {
+            return Err(PubSubError::InvalidTopic(topic.to_string()));
+        }
+
+        let segments: Vec<&str> = topic.split(SEPARATOR).collect();
+        let mut report = DeliveryReport::default();
+        let mut reached = HashSet::new();
+
+        self.channels.retain(|_pattern, topic_channel|{
+            if topic_channel.pattern.matches(&segments) {
+                report += topic_channel.channel.publish_filtered(event.clone(), |entity_id| reached.insert(entity_id.clone()));
+            }
+
+            !topic_channel.channel.is_empty()
+        });
+
+        Ok(report)
+    }
+
+    pub fn number_of_topics(&self) -> usize {
+        self.channels.len()
+    }
+}
+
+impl <S, E> Default for TopicBroker<S, E> where S: Subscriber<E> {
+    fn default() -> TopicBroker<S, E> {
+        TopicBroker::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Segment {
+    Literal(String),
+    Wildcard,
+    TrailingWildcard,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct TopicPattern {
+    segments: Vec<Segment>,
+}
+
+impl TopicPattern {
+    fn parse(pattern: &str) -> Result<TopicPattern, PubSubError> {
+        let raw_segments: Vec<&str> = pattern.split(SEPARATOR).collect();
+        let last_index = raw_segments.len() - 1;
+
+        let mut segments = vec![];
+        for (index, raw_segment) in raw_segments.into_iter().enumerate() {
+            let segment = match raw_segment {
+                "" => return Err(PubSubError::InvalidTopic(pattern.to_string())),
+                WILDCARD => Segment::Wildcard,
+                TRAILING_WILDCARD => {
+                    if index != last_index {
+                        // Only allowed at the end of the pattern.
+                        return Err(PubSubError::InvalidTopic(pattern.to_string()))
+                    }
+
+                    Segment::TrailingWildcard
+                },
+                literal => Segment::Literal(literal.to_string()),
+            };
+
+            segments.push(segment);
+        }
+
+        Ok(TopicPattern{
+            segments,
+        })
+    }
+
+    fn has_wildcards(&self) -> bool {
+        self.segments.iter().any(|segment|{
+            *segment == Segment::Wildcard || *segment == Segment::TrailingWildcard
+        })
+    }
+
+    fn matches(&self, topic: &[&str]) -> bool {
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::TrailingWildcard => {
+                    return topic.len() > index;
+                },
+                Segment::Wildcard => {
+                    if index >= topic.len() {
+                        return false;
+                    }
+                },
+                Segment::Literal(literal) => {
+                    match topic.get(index) {
+                        Some(topic_segment) if topic_segment == literal => {},
+                        _ => return false,
+                    }
+                },
+            }
+        }
+
+        topic.len() == self.segments.len()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::cell::RefCell;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn can_publish_to_a_topic(){
+        let mut broker = TopicBroker::new();
+        let guild_chat = RecordingSubscriber::new();
+        let party = RecordingSubscriber::new();
+        broker.subscribe("guild.42.chat", guild_chat.clone()).unwrap();
+        broker.subscribe("party.7", party.clone()).unwrap();
+
+        let report = broker.publish("guild.42.chat", Rc::new("hello")).unwrap();
+
+        assert_eq!(DeliveryReport{ delivered: 1, dropped: 0 }, report);
+        assert_eq!(vec!["hello"], guild_chat.received());
+        assert!(party.received().is_empty());
+    }
+
+    #[test]
+    pub fn wildcards_match_topics(){
+        let mut broker = TopicBroker::new();
+        let any_guild_chat = RecordingSubscriber::new();
+        let any_guild_topic = RecordingSubscriber::new();
+        let other_guild = RecordingSubscriber::new();
+        broker.subscribe("guild.*.chat", any_guild_chat.clone()).unwrap();
+        broker.subscribe("guild.>", any_guild_topic.clone()).unwrap();
+        broker.subscribe("guild.43.chat", other_guild.clone()).unwrap();
+
+        broker.publish("guild.42.chat", Rc::new("chat")).unwrap();
+        broker.publish("guild.42.roster", Rc::new("roster")).unwrap();
+        let report = broker.publish("guild", Rc::new("none")).unwrap();
+
+        assert_eq!(DeliveryReport::default(), report);
+        assert_eq!(vec!["chat"], any_guild_chat.received());
+        assert_eq!(vec!["chat", "roster"], any_guild_topic.received());
+        assert!(other_guild.received().is_empty());
+    }
+
+    #[test]
+    pub fn overlapping_patterns_receive_each_event_once(){
+        let mut broker = TopicBroker::new();
+        let subscriber = RecordingSubscriber::new();
+        broker.subscribe("guild.*", subscriber.clone()).unwrap();
+        broker.subscribe("guild.>", subscriber.clone()).unwrap();
+        broker.subscribe("guild.42", subscriber.clone()).unwrap();
+
+        let report = broker.publish("guild.42", Rc::new("chat")).unwrap();
+
+        assert_eq!(DeliveryReport{ delivered: 1, dropped: 0 }, report);
+        assert_eq!(vec!["chat"], subscriber.received());
+
+        broker.unsubscribe("guild.*", subscriber.entity_id());
+        broker.publish("guild.42", Rc::new("roster")).unwrap();
+        assert_eq!(vec!["chat", "roster"], subscriber.received());
+    }
+
+    #[test]
+    pub fn can_unsubscribe_by_id(){
+        let mut broker = TopicBroker::new();
+        let subscriber = RecordingSubscriber::new();
+        broker.subscribe("global", subscriber.clone()).unwrap();
+        broker.subscribe("guild.*.chat", subscriber.clone()).unwrap();
+        broker.subscribe("party.7", subscriber.clone()).unwrap();
+
+        assert!(broker.unsubscribe("global", subscriber.entity_id()).is_some());
+        assert!(broker.unsubscribe("global", subscriber.entity_id()).is_none());
+        assert_eq!(2, broker.unsubscribe_all(subscriber.entity_id()));
+        assert_eq!(0, broker.number_of_topics());
+
+        broker.publish("global", Rc::new("global")).unwrap();
+        assert!(subscriber.received().is_empty());
+    }
+
+    #[test]
+    pub fn rejects_invalid_topics(){
+        let mut broker: TopicBroker<RecordingSubscriber, &str> = TopicBroker::new();
+
+        assert!(broker.subscribe("guild..chat", RecordingSubscriber::new()).is_err());
+        assert!(broker.subscribe("guild.>.chat", RecordingSubscriber::new()).is_err());
+        assert!(broker.publish("guild.*.chat", Rc::new("wildcard")).is_err());
+        assert!(broker.publish("", Rc::new("empty")).is_err());
+    }
+
+    #[derive(Clone)]
+    struct RecordingSubscriber{
+        entity_id: Uuid,
+        received: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl RecordingSubscriber{
+        fn new() -> RecordingSubscriber{
+            RecordingSubscriber{
+                entity_id: Uuid::new_v4(),
+                received: Rc::new(RefCell::new(vec![])),
+            }
+        }
+
+        fn received(&self) -> Vec<&'static str> {
+            self.received.borrow().clone()
+        }
+    }
+
+    impl Subscriber<&'static str> for RecordingSubscriber{
+        type Id = Uuid;
+
+        fn send(&self, event: Rc<&'static str>) -> Result<bool, PubSubError> {
+            self.received.borrow_mut().push(*event);
+            Ok(true)
+        }
+
+        fn entity_id(&self) -> &Uuid {
+            &self.entity_id
+        }
+    }
+}
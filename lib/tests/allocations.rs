@@ -0,0 +1,100 @@
+extern crate spatiub;
+extern crate uuid;
+
+use spatiub::fn_sub::FnSubscriber;
+use spatiub::spatial::Entity;
+use spatiub::spatial::MapDefinition;
+use spatiub::spatial::Point;
+use spatiub::spatial::SpatialChannel;
+use spatiub::spatial::SpatialEvent;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+/// Counts the allocations of the whole test binary, hence the single test in this file.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ZONE_WIDTH: usize = 16;
+
+#[test]
+fn moves_only_allocate_the_published_event() {
+    let mut channel = SpatialChannel::new(MapDefinition::new(ZONE_WIDTH, 16));
+    let number_of_events_received = Rc::new(Cell::new(0));
+
+    let mut entities = vec![];
+    for i in 0..10 {
+        let entity = TestEntity{
+            id: Uuid::new_v4(),
+        };
+        let position = Point(ZONE_WIDTH * 2 + i, ZONE_WIDTH * 2);
+
+        let received = number_of_events_received.clone();
+        let subscriber = FnSubscriber::new(entity.id, move |_event|{
+            received.set(received.get() + 1);
+            Ok(true)
+        });
+        channel.subscribe(subscriber, &position);
+        channel.publish(move_event(&entity, position.clone(), position.clone()));
+
+        entities.push((entity, position));
+    }
+
+    // Warm up, the zones reach their steady state.
+    for (entity, position) in &entities {
+        let destination = Point(position.0, position.1 + 1);
+        channel.publish(move_event(entity, position.clone(), destination.clone()));
+        channel.publish(move_event(entity, destination, position.clone()));
+    }
+
+    let number_of_events = 1000;
+    let allocations_before = ALLOCATIONS.load(Ordering::SeqCst);
+    for i in 0..number_of_events {
+        let (ref entity, ref position) = entities[i % entities.len()];
+        let destination = Point(position.0, position.1 + (i / entities.len()) % 2);
+        channel.publish(move_event(entity, position.clone(), destination));
+    }
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst) - allocations_before;
+
+    assert!(number_of_events_received.get() > number_of_events * entities.len());
+    assert_eq!(number_of_events, allocations);
+}
+
+fn move_event(entity: &TestEntity, from: Point, to: Point) -> SpatialEvent<TestEntity> {
+    SpatialEvent{
+        from,
+        to: Some(to),
+        acting_entity: entity.clone(),
+        is_a_move: true,
+    }
+}
+
+#[derive(Clone)]
+struct TestEntity{
+    id: Uuid,
+}
+
+impl Entity for TestEntity{
+    type Id = Uuid;
+
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+}